@@ -0,0 +1,70 @@
+// Counts heap allocations per acquisition for a high-frequency, single-resource system. Compares
+// building RequiredResources from Vecs (what from_system does) with the fixed from_slices path.
+//
+// Run with: cargo bench --bench acquire_allocations
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_dispatcher::{AcquireResources, DispatcherBuilder, RequiredResources};
+use futures::Future;
+use shred::ResourceId;
+
+struct CountingAllocator;
+
+static ALLOCATION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATION_COUNT.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+struct ExampleResource;
+struct ExampleSystem;
+
+const ITERATIONS: usize = 10_000;
+
+// Prints the average number of allocations and time per acquisition
+fn measure<F>(name: &str, f: F)
+where
+    F: Fn() -> RequiredResources<ExampleSystem>,
+{
+    let dispatcher = Arc::new(DispatcherBuilder::new().insert(ExampleResource).build());
+
+    let start_count = ALLOCATION_COUNT.load(Ordering::Relaxed);
+    let start_time = std::time::Instant::now();
+    for _ in 0..ITERATIONS {
+        let required_resources = f();
+        AcquireResources::new(dispatcher.clone(), required_resources)
+            .wait()
+            .unwrap();
+    }
+    let elapsed = start_time.elapsed();
+    let allocations = ALLOCATION_COUNT.load(Ordering::Relaxed) - start_count;
+
+    println!(
+        "{:>12}: {:.2} allocations/acquire, {:?}/acquire",
+        name,
+        allocations as f64 / ITERATIONS as f64,
+        elapsed / ITERATIONS as u32
+    );
+}
+
+fn main() {
+    let writes = [ResourceId::new::<ExampleResource>()];
+
+    measure("vec", || RequiredResources::new(vec![], writes.to_vec()));
+    measure("from_slices", || {
+        RequiredResources::from_slices(&[], &writes)
+    });
+}
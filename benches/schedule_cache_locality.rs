@@ -0,0 +1,80 @@
+// Compares a read-heavy schedule with its systems in the order they were added against the same
+// schedule ordered with ScheduleHint::CacheLocality. Every system sums one of a few large
+// resources, and they're added so that no two systems next to each other read the same one. Each
+// resource fits in cache on its own but all of them together don't, so reading the same resource
+// back to back mostly hits the cache while alternating between them mostly misses. Timing is only
+// a proxy for cache misses, to see the misses themselves run it under perf stat -e cache-misses.
+//
+// Run with: cargo bench --bench schedule_cache_locality
+
+use std::sync::Arc;
+
+use async_dispatcher::{Dispatcher, DispatcherBuilder, Schedule, ScheduleHint};
+
+// Each resource is 1MB
+const RESOURCE_LEN: usize = 128 * 1024;
+const SYSTEMS_PER_RESOURCE: usize = 8;
+const FRAME_COUNT: usize = 200;
+
+struct Data<const N: usize>(Vec<u64>);
+
+struct SumSystem<const N: usize>;
+
+impl<'a, const N: usize> shred::System<'a> for SumSystem<N> {
+    type SystemData = shred::ReadExpect<'a, Data<N>>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        std::hint::black_box(data.0.iter().sum::<u64>());
+    }
+}
+
+fn build_dispatcher() -> Dispatcher {
+    DispatcherBuilder::new()
+        .insert(Data::<0>(vec![1; RESOURCE_LEN]))
+        .insert(Data::<1>(vec![1; RESOURCE_LEN]))
+        .insert(Data::<2>(vec![1; RESOURCE_LEN]))
+        .insert(Data::<3>(vec![1; RESOURCE_LEN]))
+        .insert(Data::<4>(vec![1; RESOURCE_LEN]))
+        .insert(Data::<5>(vec![1; RESOURCE_LEN]))
+        .insert(Data::<6>(vec![1; RESOURCE_LEN]))
+        .insert(Data::<7>(vec![1; RESOURCE_LEN]))
+        .build()
+}
+
+// All systems have no ordering constraints, so they end up in a single level
+fn build_schedule(hint: ScheduleHint) -> Schedule {
+    let mut schedule = Schedule::new().with_hint(hint);
+    for _ in 0..SYSTEMS_PER_RESOURCE {
+        schedule.add(SumSystem::<0>, &[]);
+        schedule.add(SumSystem::<1>, &[]);
+        schedule.add(SumSystem::<2>, &[]);
+        schedule.add(SumSystem::<3>, &[]);
+        schedule.add(SumSystem::<4>, &[]);
+        schedule.add(SumSystem::<5>, &[]);
+        schedule.add(SumSystem::<6>, &[]);
+        schedule.add(SumSystem::<7>, &[]);
+    }
+
+    schedule
+}
+
+// Prints the average and slowest frame time
+fn measure(name: &str, hint: ScheduleHint) {
+    // run_frames needs a closure that's Copy, so the schedule has to live for the rest of the
+    // program
+    let schedule: &'static Schedule = Box::leak(Box::new(build_schedule(hint)));
+    let (_world, frame_stats) = build_dispatcher()
+        .run_frames(FRAME_COUNT, move |dispatcher: Arc<Dispatcher>| {
+            schedule.create_future(&dispatcher)
+        });
+
+    println!(
+        "{:>16}: {:?}/frame, slowest {:?}",
+        name, frame_stats.mean, frame_stats.max
+    );
+}
+
+fn main() {
+    measure("insertion order", ScheduleHint::InsertionOrder);
+    measure("cache locality", ScheduleHint::CacheLocality);
+}
@@ -0,0 +1,85 @@
+// Compares acquiring a tiny resource that every system reads through a normal resource lock with
+// reading it through a SeqLock. Several threads acquire at once so that the lock is contended, the
+// way it would be if most systems in a frame read it.
+//
+// Run with: cargo bench --bench seqlock_reads
+
+use std::sync::Arc;
+
+use async_dispatcher::{
+    AcquireResources, Dispatcher, DispatcherBuilder, RequiredResources, SeqLock,
+};
+use futures::Future;
+use shred::ResourceId;
+
+#[derive(Clone, Copy)]
+struct DeltaTime;
+
+struct ExampleSystem;
+
+const THREAD_COUNT: usize = 4;
+const ITERATIONS: usize = 10_000;
+
+// Prints the average time per acquisition and how many acquisitions waited over 16us for the
+// dispatch lock
+fn measure(name: &str, dispatcher: Dispatcher, reads: ResourceId) {
+    let dispatcher = Arc::new(dispatcher);
+
+    let start_time = std::time::Instant::now();
+    let threads: Vec<_> = (0..THREAD_COUNT)
+        .map(|_| {
+            let dispatcher = dispatcher.clone();
+            let reads = reads.clone();
+            std::thread::spawn(move || {
+                for _ in 0..ITERATIONS {
+                    let required_resources = RequiredResources::<ExampleSystem>::from_slices(
+                        std::slice::from_ref(&reads),
+                        &[],
+                    );
+                    AcquireResources::new(dispatcher.clone(), required_resources)
+                        .wait()
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let elapsed = start_time.elapsed();
+    let histogram = dispatcher.dispatch_lock_wait_histogram();
+    let slow_waits: u64 = (0..histogram.bucket_count())
+        .filter(|index| histogram.bucket_range(*index).0.as_micros() >= 16)
+        .map(|index| histogram.count(index))
+        .sum();
+
+    println!(
+        "{:>8}: {:?}/acquire, {} of {} dispatch lock waits over 16us",
+        name,
+        elapsed / (THREAD_COUNT * ITERATIONS) as u32,
+        slow_waits,
+        histogram.total_count()
+    );
+}
+
+fn main() {
+    measure(
+        "lock",
+        DispatcherBuilder::new()
+            .insert(DeltaTime)
+            .with_dispatch_lock_wait_histogram()
+            .build(),
+        ResourceId::new::<DeltaTime>(),
+    );
+
+    measure(
+        "seqlock",
+        DispatcherBuilder::new()
+            .insert_seqlock(DeltaTime)
+            .with_dispatch_lock_wait_histogram()
+            .build(),
+        ResourceId::new::<SeqLock<DeltaTime>>(),
+    );
+}
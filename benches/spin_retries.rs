@@ -0,0 +1,68 @@
+// Measures acquisition latency when several threads contend for a resource that is only held
+// briefly, with and without spinning before parking (DispatcherBuilder::with_spin_retries).
+//
+// Run with: cargo bench --bench spin_retries
+
+use std::sync::Arc;
+
+use async_dispatcher::{AcquireResources, DispatcherBuilder, RequiredResources};
+use futures::Future;
+use shred::ResourceId;
+
+struct ExampleResource;
+struct ExampleSystem;
+
+const THREAD_COUNT: usize = 4;
+const ITERATIONS: usize = 2_000;
+
+// Roughly how long each acquisition holds the resource
+const HOLD_DURATION: std::time::Duration = std::time::Duration::from_micros(1);
+
+// Prints the average time per acquisition
+fn measure(spin_retries: usize) {
+    let dispatcher = Arc::new(
+        DispatcherBuilder::new()
+            .insert(ExampleResource)
+            .with_spin_retries(spin_retries)
+            .build(),
+    );
+
+    let writes = [ResourceId::new::<ExampleResource>()];
+    let start_time = std::time::Instant::now();
+    let threads: Vec<_> = (0..THREAD_COUNT)
+        .map(|_| {
+            let dispatcher = dispatcher.clone();
+            let writes = writes.clone();
+            std::thread::spawn(move || {
+                for _ in 0..ITERATIONS {
+                    let required_resources =
+                        RequiredResources::<ExampleSystem>::from_slices(&[], &writes);
+                    let _guards = AcquireResources::new(dispatcher.clone(), required_resources)
+                        .wait()
+                        .unwrap();
+
+                    let hold_start = std::time::Instant::now();
+                    while hold_start.elapsed() < HOLD_DURATION {
+                        std::hint::spin_loop();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    println!(
+        "{:>4} spin retries: {:?}/acquire",
+        spin_retries,
+        start_time.elapsed() / (THREAD_COUNT * ITERATIONS) as u32
+    );
+}
+
+fn main() {
+    for spin_retries in &[0, 16, 256] {
+        measure(*spin_retries);
+    }
+}
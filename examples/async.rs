@@ -1,14 +1,13 @@
 // This shows an example of kicking off an async task that, on completion, acquires locks to
 // resources and uses them. This may block the main loop. (In a realistic scenario this block should
 // be as short as possible, but for illustrative purposes, these tasks artificially block with
-// std::thread::sleep calls.
+// std::thread::sleep calls. The file read handler is run with create_future_blocking, so while
+// it does still hold its lock, it doesn't tie up a worker thread that other tasks could use.)
 
 #[macro_use]
 extern crate log;
 
-use std::sync::Arc;
-
-use async_dispatcher::{Dispatcher, DispatcherBuilder, ExecuteSequential};
+use async_dispatcher::{Dispatcher, DispatcherBuilder, ExecuteSequential, WeakDispatcher};
 
 // A trivial resource that will be written to by the main loop via IncrementSystem and occasionally
 // by HandleFileReadComplete which is an external task that reads a file
@@ -35,7 +34,7 @@ struct HandleFileReadComplete {
 }
 
 impl<'a> shred::System<'a> for HandleFileReadComplete {
-    type SystemData = (shred::WriteExpect<'a, ExampleResource>);
+    type SystemData = shred::WriteExpect<'a, ExampleResource>;
 
     fn run(&mut self, data: Self::SystemData) {
         let mut a = data;
@@ -55,7 +54,7 @@ impl<'a> shred::System<'a> for HandleFileReadComplete {
 
 // This is kicked off regularly by the main thread
 struct IncrementSystem {
-    dispatcher: Arc<Dispatcher>,
+    dispatcher: WeakDispatcher,
 }
 
 use futures::future::Future;
@@ -63,19 +62,30 @@ impl IncrementSystem {
     fn spawn_read_file_task(&mut self) {
         info!("  Going to kick off a read request");
 
-        let dispatcher_clone = self.dispatcher.clone();
-        tokio::spawn(
+        // The loop is shutting down if the dispatcher is gone
+        let dispatcher = match self.dispatcher.upgrade() {
+            Some(dispatcher) => dispatcher,
+            None => return,
+        };
+
+        // Spawning through the dispatcher ties the task to the game loop, so the loop won't end
+        // while the read (or the system it kicks off) is still in flight
+        let dispatcher_clone = dispatcher.clone();
+        dispatcher.spawn(
             tokio::fs::read("testfile.txt")
                 .map_err(|err| warn!("File read failed: {}", err))
                 .and_then(move |data| {
-                    Dispatcher::create_future(&dispatcher_clone, HandleFileReadComplete { data })
+                    Dispatcher::create_future_blocking(
+                        &dispatcher_clone,
+                        HandleFileReadComplete { data },
+                    )
                 }),
         );
     }
 }
 
 impl<'a> shred::System<'a> for IncrementSystem {
-    type SystemData = (shred::WriteExpect<'a, ExampleResource>);
+    type SystemData = shred::WriteExpect<'a, ExampleResource>;
 
     fn run(&mut self, data: Self::SystemData) {
         let mut a = data;
@@ -119,7 +129,7 @@ fn main() {
         ExecuteSequential::new(vec![Dispatcher::create_future(
             &dispatcher,
             IncrementSystem {
-                dispatcher: dispatcher.clone(),
+                dispatcher: Dispatcher::weak_handle(&dispatcher),
             },
         )])
     });
@@ -0,0 +1,155 @@
+// This is the same as game_loop, but each frame is written as an async block instead of being put
+// together from ExecuteSequential. The dispatcher's futures are awaited with .compat(). Run with
+// --features std-futures
+
+#[macro_use]
+extern crate log;
+
+use async_dispatcher::{Dispatcher, DispatcherBuilder, ExecuteParallel, WeakDispatcher};
+use futures03::compat::Future01CompatExt;
+
+#[derive(Debug)]
+struct MyResourceA {
+    value: i32,
+}
+
+impl MyResourceA {
+    fn new(value: i32) -> Self {
+        MyResourceA { value }
+    }
+}
+
+#[derive(Debug)]
+struct MyResourceB {
+    value: i32,
+}
+
+impl MyResourceB {
+    fn new() -> Self {
+        MyResourceB { value: 0 }
+    }
+}
+
+#[derive(Debug)]
+struct PrintSystems;
+impl<'a> shred::System<'a> for PrintSystems {
+    type SystemData = (
+        shred::ReadExpect<'a, MyResourceA>,
+        shred::WriteExpect<'a, MyResourceB>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (a, b) = data;
+
+        info!("PrintSystem {:?} {:?}", &*a, &*b);
+    }
+}
+
+#[derive(Debug)]
+struct IncrementResourceBWithA;
+impl<'a> shred::System<'a> for IncrementResourceBWithA {
+    type SystemData = (
+        shred::ReadExpect<'a, MyResourceA>,
+        shred::WriteExpect<'a, MyResourceB>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (a, mut b) = data;
+        b.value += a.value;
+    }
+}
+
+#[derive(Debug)]
+struct IncrementResourceBWithValue {
+    value: i32,
+}
+impl<'a> shred::System<'a> for IncrementResourceBWithValue {
+    type SystemData = (
+        shred::ReadExpect<'a, MyResourceA>,
+        shred::WriteExpect<'a, MyResourceB>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (_a, mut b) = data;
+        b.value += self.value;
+    }
+}
+
+struct TerminateIfIncrementResourceBHighEnough {
+    value: i32,
+    dispatcher: WeakDispatcher,
+}
+impl<'a> shred::System<'a> for TerminateIfIncrementResourceBHighEnough {
+    type SystemData = shred::ReadExpect<'a, MyResourceB>;
+
+    fn run(&mut self, data: Self::SystemData) {
+        let b = data;
+
+        if b.value > self.value {
+            if let Some(dispatcher) = self.dispatcher.upgrade() {
+                dispatcher.end_game_loop();
+            }
+        }
+    }
+}
+
+fn main() {
+    // Set up logging
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Debug)
+        .init();
+
+    // Populate resources
+    let dispatcher = DispatcherBuilder::new()
+        .insert(MyResourceA::new(1))
+        .insert(MyResourceB::new())
+        .build();
+
+    let world = dispatcher.enter_game_loop_async(|dispatcher| {
+        async move {
+            // These will happen in sequence
+            Dispatcher::create_future(&dispatcher, PrintSystems)
+                .compat()
+                .await?;
+            Dispatcher::create_future(&dispatcher, IncrementResourceBWithA)
+                .compat()
+                .await?;
+            Dispatcher::create_future(&dispatcher, IncrementResourceBWithValue { value: 5 })
+                .compat()
+                .await?;
+            Dispatcher::create_future(&dispatcher, PrintSystems)
+                .compat()
+                .await?;
+
+            // A few things in parallel
+            ExecuteParallel::new(vec![
+                Dispatcher::create_future(&dispatcher, PrintSystems),
+                Dispatcher::create_future(&dispatcher, PrintSystems),
+                Dispatcher::create_future(&dispatcher, PrintSystems),
+            ])
+            .compat()
+            .await?;
+
+            // Then finish the sequence
+            Dispatcher::create_future(&dispatcher, PrintSystems)
+                .compat()
+                .await?;
+            Dispatcher::create_future(
+                &dispatcher,
+                TerminateIfIncrementResourceBHighEnough {
+                    value: 10000,
+                    dispatcher: Dispatcher::weak_handle(&dispatcher),
+                },
+            )
+            .compat()
+            .await
+        }
+    });
+
+    // At the end, print results
+    info!(
+        "MyResource1: {} MyResource2: {}",
+        world.fetch::<MyResourceA>().value,
+        world.fetch::<MyResourceB>().value
+    );
+}
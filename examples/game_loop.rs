@@ -4,9 +4,9 @@
 #[macro_use]
 extern crate log;
 
-use std::sync::Arc;
-
-use async_dispatcher::{Dispatcher, DispatcherBuilder, ExecuteParallel, ExecuteSequential};
+use async_dispatcher::{
+    Dispatcher, DispatcherBuilder, ExecuteParallel, ExecuteSequential, WeakDispatcher,
+};
 
 #[derive(Debug)]
 struct MyResourceA {
@@ -86,16 +86,18 @@ impl<'a> shred::System<'a> for IncrementResourceBWithValue {
 
 struct TerminateIfIncrementResourceBHighEnough {
     value: i32,
-    dispatcher: Arc<Dispatcher>,
+    dispatcher: WeakDispatcher,
 }
 impl<'a> shred::System<'a> for TerminateIfIncrementResourceBHighEnough {
-    type SystemData = (shred::ReadExpect<'a, MyResourceB>);
+    type SystemData = shred::ReadExpect<'a, MyResourceB>;
 
     fn run(&mut self, data: Self::SystemData) {
         let b = data;
 
         if b.value > self.value {
-            self.dispatcher.end_game_loop();
+            if let Some(dispatcher) = self.dispatcher.upgrade() {
+                dispatcher.end_game_loop();
+            }
         }
     }
 }
@@ -133,7 +135,7 @@ fn main() {
                 &dispatcher,
                 TerminateIfIncrementResourceBHighEnough {
                     value: 10000,
-                    dispatcher: dispatcher.clone(),
+                    dispatcher: Dispatcher::weak_handle(&dispatcher),
                 },
             ),
         ])
@@ -1,9 +1,8 @@
 // Near-minimal example of using this crate.
 
-use std::sync::Arc;
-
 use async_dispatcher::Dispatcher;
 use async_dispatcher::DispatcherBuilder;
+use async_dispatcher::WeakDispatcher;
 
 struct HelloWorldResourceA {
     value: i32,
@@ -14,7 +13,7 @@ struct HelloWorldResourceB {
 }
 
 struct HelloWorldSystem {
-    dispatcher: Arc<Dispatcher>,
+    dispatcher: WeakDispatcher,
 }
 
 impl<'a> shred::System<'a> for HelloWorldSystem {
@@ -30,7 +29,9 @@ impl<'a> shred::System<'a> for HelloWorldSystem {
         b.value += 1;
 
         if b.value > 20 {
-            self.dispatcher.end_game_loop();
+            if let Some(dispatcher) = self.dispatcher.upgrade() {
+                dispatcher.end_game_loop();
+            }
         }
     }
 }
@@ -47,7 +48,7 @@ fn main() {
         Dispatcher::create_future(
             &dispatcher,
             HelloWorldSystem {
-                dispatcher: dispatcher.clone(),
+                dispatcher: Dispatcher::weak_handle(&dispatcher),
             },
         )
     });
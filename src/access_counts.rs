@@ -0,0 +1,33 @@
+use hashbrown::HashMap;
+use std::sync::Mutex;
+
+use shred::ResourceId;
+
+// How many times each resource was acquired for reading and for writing, see
+// Dispatcher::access_ratios. Only exists with the metrics feature
+pub(super) struct AccessCounts {
+    counts: Mutex<HashMap<ResourceId, (u64, u64)>>,
+}
+
+impl AccessCounts {
+    pub(super) fn new() -> Self {
+        AccessCounts {
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(super) fn acquired(&self, reads: &[ResourceId], writes: &[ResourceId]) {
+        let mut counts = self.counts.lock().unwrap();
+        for resource_id in reads {
+            counts.entry(resource_id.clone()).or_insert((0, 0)).0 += 1;
+        }
+
+        for resource_id in writes {
+            counts.entry(resource_id.clone()).or_insert((0, 0)).1 += 1;
+        }
+    }
+
+    pub(super) fn counts(&self) -> HashMap<ResourceId, (u64, u64)> {
+        self.counts.lock().unwrap().clone()
+    }
+}
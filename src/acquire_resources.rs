@@ -1,107 +1,899 @@
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::sync::Mutex;
 
 use shred::ResourceId;
+use smallvec::SmallVec;
 
+use super::AcquisitionEventKind;
+use super::AcquisitionRecorder;
+use super::AsyncResourceLock;
+#[cfg(feature = "audit")]
+use super::AuditRecord;
+use super::ContendedShutdownPolicy;
+use super::DefaultResourceLock;
 use super::Dispatcher;
 use super::RequiredResources;
+use super::ResourceIdList;
+use crate::acquisition_order::ordered_resources;
+use crate::acquisition_order::AcquisitionOrderList;
+use crate::expedite::ExpediteQueue;
+use crate::health::LockHolders;
+use crate::resource_lock::release_when_available;
+use crate::resource_policy::ResourceAccess;
+use crate::runtime::DefaultRuntime;
+use crate::runtime::Runtime;
+use crate::runtime::RuntimeFuture;
+use crate::write_versions::WriteVersions;
+
+// Guards for the locks taken during an acquisition, inline for the same reason as ResourceIdList
+pub(super) type LockGuardList<L> = SmallVec<[<L as AsyncResourceLock>::Guard; 8]>;
+
+// Calls the dispatcher's on_release callback for each resource as its lock is released
+pub(super) struct ReleaseCallback {
+    on_release: fn(&ResourceId),
+    resources: ResourceIdList,
+    snapshots: ResourceIdList,
+}
 
 // This holds the locks for resources that were acquired by the AcquireResources future
-pub struct AcquiredResourcesLockGuards<T> {
-    _reads: Vec<tokio::sync::lock::LockGuard<()>>,
-    _writes: Vec<tokio::sync::lock::LockGuard<()>>,
+pub struct AcquiredResourcesLockGuards<T, L: AsyncResourceLock = DefaultResourceLock> {
+    _reads: LockGuardList<L>,
+    _writes: LockGuardList<L>,
+    // Released as soon as the system's data is fetched, see Snapshot
+    snapshots: LockGuardList<L>,
+    release_record: Option<(u64, Vec<ResourceId>, Arc<AcquisitionRecorder>)>,
+    release_callback: Option<ReleaseCallback>,
+    holder_record: Option<(u64, Vec<ResourceId>, Arc<LockHolders>)>,
+    // Write locks to count as released, see ReacquireGuard
+    version_record: Option<(Vec<ResourceId>, Arc<WriteVersions>)>,
+    // Used by critical_section
+    dispatcher: Arc<Dispatcher<L>>,
     phantom_data: PhantomData<T>,
 }
 
-impl<T> AcquiredResourcesLockGuards<T> {
+impl<T, L: AsyncResourceLock> AcquiredResourcesLockGuards<T, L> {
     fn new(
-        reads: Vec<tokio::sync::lock::LockGuard<()>>,
-        writes: Vec<tokio::sync::lock::LockGuard<()>>,
+        reads: LockGuardList<L>,
+        writes: LockGuardList<L>,
+        snapshots: LockGuardList<L>,
+        release_record: Option<(u64, Vec<ResourceId>, Arc<AcquisitionRecorder>)>,
+        release_callback: Option<ReleaseCallback>,
+        holder_record: Option<(u64, Vec<ResourceId>, Arc<LockHolders>)>,
+        dispatcher: Arc<Dispatcher<L>>,
     ) -> Self {
-        AcquiredResourcesLockGuards::<T> {
+        AcquiredResourcesLockGuards::<T, L> {
             _reads: reads,
             _writes: writes,
+            snapshots,
+            release_record,
+            release_callback,
+            holder_record,
+            version_record: None,
+            dispatcher,
             phantom_data: PhantomData,
         }
     }
+
+    // Runs f while no other acquisition can take any locks, for when a system needs a moment where
+    // nothing else in the world changes hands (i.e. a consistent snapshot across several
+    // resources). Resolves to the guards (still held) and f's output.
+    //
+    // This waits for the dispatch lock (and, with shared read dispatch, enters the gate
+    // exclusively) while holding our resource locks, which an acquisition never does. It still
+    // can't deadlock: whoever holds the dispatch lock only tries locks without waiting on them and
+    // releases it before returning from its poll, so it never waits on anything we hold. Once we
+    // have it, every other acquisition is stopped at the dispatch lock and f runs alone. f must not
+    // wait on anything itself (i.e. Dispatcher::acquire_blocking), since whatever it waits on would
+    // need the dispatch lock we're holding.
+    //
+    // Our resources are covered for as long as we hold their guards, which is always, except for
+    // snapshot resources once their guards were released early (see Snapshot). Locks that aren't
+    // taken through an acquisition (seqlock resources) aren't covered either
+    pub fn critical_section<F, O>(self, f: F) -> CriticalSection<T, L, F>
+    where
+        F: FnOnce(&shred::World) -> O,
+    {
+        CriticalSection {
+            dispatch_lock: self.dispatcher.dispatch_lock().clone(),
+            guards: Some(self),
+            f: Some(f),
+        }
+    }
+}
+
+// Future returned by AcquiredResourcesLockGuards::critical_section
+pub struct CriticalSection<T, L: AsyncResourceLock, F> {
+    guards: Option<AcquiredResourcesLockGuards<T, L>>,
+    dispatch_lock: L,
+    f: Option<F>,
+}
+
+impl<T, L: AsyncResourceLock, F, O> futures::future::Future for CriticalSection<T, L, F>
+where
+    F: FnOnce(&shred::World) -> O,
+{
+    type Item = (AcquiredResourcesLockGuards<T, L>, O);
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let guards = self
+            .guards
+            .take()
+            .expect("Polled a critical section after it completed");
+
+        let _dispatch_guard = match self.dispatch_lock.poll_lock() {
+            futures::Async::Ready(guard) => guard,
+            futures::Async::NotReady => {
+                self.guards = Some(guards);
+                return Ok(futures::Async::NotReady);
+            }
+        };
+
+        let _gate_guard = match guards.dispatcher.poll_dispatch_gate(false) {
+            futures::Async::Ready(guard) => guard,
+            futures::Async::NotReady => {
+                self.guards = Some(guards);
+                return Ok(futures::Async::NotReady);
+            }
+        };
+
+        let f = self.f.take().unwrap();
+        let output = f(&guards.dispatcher.world());
+        Ok(futures::Async::Ready((guards, output)))
+    }
+}
+
+impl<T, L: AsyncResourceLock> AcquiredResourcesLockGuards<T, L> {
+    pub(super) fn release_snapshots(&mut self) {
+        if let Some(release_callback) = &mut self.release_callback {
+            for resource_id in release_callback.snapshots.drain() {
+                (release_callback.on_release)(&resource_id);
+            }
+        }
+
+        self.snapshots.clear();
+    }
+}
+
+impl<T, L: AsyncResourceLock> Drop for AcquiredResourcesLockGuards<T, L> {
+    fn drop(&mut self) {
+        if let Some((task_id, resources, recorder)) = &self.release_record {
+            recorder.record(*task_id, resources, AcquisitionEventKind::Release);
+        }
+
+        if let Some((task_id, resources, lock_holders)) = &self.holder_record {
+            lock_holders.released(*task_id, resources);
+        }
+
+        // The locks are only released once the fields are dropped, after this
+        if let Some((resources, write_versions)) = &self.version_record {
+            write_versions.released(resources);
+        }
+
+        if let Some(release_callback) = &self.release_callback {
+            let resources = release_callback.resources.iter();
+            for resource_id in resources.chain(release_callback.snapshots.iter()) {
+                (release_callback.on_release)(resource_id);
+            }
+        }
+    }
+}
+
+// A public mirror of the internal acquire state. This is what observability tools see when they ask
+// an in-flight acquisition what it is currently doing
+#[derive(Debug, Clone, PartialEq)]
+pub enum AcquireStatus {
+    // Waiting for our turn to take the dispatch lock and try to acquire everything
+    WaitForDispatch,
+
+    // Waiting on a resource that another task is holding
+    WaitForResource(ResourceId),
+
+    // Gave up waiting on a resource that was inserted with a timeout. The acquisition failed
+    TimedOut(ResourceId),
+
+    // Gave up waiting on a resource because the dispatcher is terminating. The acquisition failed
+    Terminated,
+
+    // All resources were acquired
+    Finished,
+}
+
+// Why an acquisition failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DispatchError {
+    // Gave up waiting on a resource because the dispatcher is terminating (see
+    // ContendedShutdownPolicy)
+    Terminated,
+
+    // Gave up waiting on a resource that was inserted with a timeout
+    TimedOut(ResourceId),
+}
+
+#[derive(Debug)]
+struct AcquireStatusShared {
+    status: AcquireStatus,
+
+    // The task that last polled the acquisition, so that it can be woken externally
+    task: Option<futures::task::Task>,
+
+    #[cfg(feature = "metrics")]
+    poll_count: u64,
+    #[cfg(feature = "metrics")]
+    not_ready_wake_count: u64,
+}
+
+// A cloneable handle to the status of a single acquisition. This can be held by code outside the
+// future (i.e. a debug view) and polled at any time
+#[derive(Debug, Clone)]
+pub struct AcquireStatusHandle {
+    task_id: u64,
+    shared: Arc<Mutex<AcquireStatusShared>>,
+    expedite_queue: Arc<ExpediteQueue>,
+}
+
+impl AcquireStatusHandle {
+    fn new(task_id: u64, status: AcquireStatus, expedite_queue: Arc<ExpediteQueue>) -> Self {
+        AcquireStatusHandle {
+            task_id,
+            shared: Arc::new(Mutex::new(AcquireStatusShared {
+                status,
+                task: None,
+                #[cfg(feature = "metrics")]
+                poll_count: 0,
+                #[cfg(feature = "metrics")]
+                not_ready_wake_count: 0,
+            })),
+            expedite_queue,
+        }
+    }
+
+    fn set(&self, status: AcquireStatus) {
+        self.shared.lock().unwrap().status = status;
+    }
+
+    fn set_task(&self, task: futures::task::Task) {
+        self.shared.lock().unwrap().task = Some(task);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn record_poll(&self) {
+        self.shared.lock().unwrap().poll_count += 1;
+    }
+
+    #[cfg(feature = "metrics")]
+    fn record_not_ready_wake(&self) {
+        self.shared.lock().unwrap().not_ready_wake_count += 1;
+    }
+
+    // The task id assigned by the dispatcher, matches the id used in trace logging
+    pub fn task_id(&self) -> u64 {
+        self.task_id
+    }
+
+    // How many times the acquisition has been polled so far. An acquisition that only had to wait
+    // once or twice but was polled many times is being woken when it has no chance of progressing
+    #[cfg(feature = "metrics")]
+    pub fn poll_count(&self) -> u64 {
+        self.shared.lock().unwrap().poll_count
+    }
+
+    // How many of those polls were wakeups while waiting on a resource that turned out to still be
+    // held. Ideally this stays at 0, since we should only be woken once the lock is handed to us
+    #[cfg(feature = "metrics")]
+    pub fn not_ready_wake_count(&self) -> u64 {
+        self.shared.lock().unwrap().not_ready_wake_count
+    }
+
+    // Returns what the acquisition is currently doing
+    pub fn status(&self) -> AcquireStatus {
+        self.shared.lock().unwrap().status.clone()
+    }
+
+    // Returns the resource the acquisition is blocked on, if any
+    pub fn waiting_on(&self) -> Option<ResourceId> {
+        match self.status() {
+            AcquireStatus::WaitForResource(resource_id) => Some(resource_id),
+            _ => None,
+        }
+    }
+
+    // Move this acquisition to the front of the line. Until it has acquired its resources, no other
+    // acquisition on the dispatcher will be allowed to try. This is an escape hatch for work that
+    // must happen right away (i.e. handling input this frame). Using it often defeats the fairness
+    // the dispatcher otherwise provides, and if several acquisitions are expedited they are served
+    // in the order they were expedited.
+    pub fn expedite(&self) {
+        self.expedite_queue.push(self.task_id);
+
+        // Wake the acquisition so that it retries right away
+        if let Some(task) = &self.shared.lock().unwrap().task {
+            task.notify();
+        }
+    }
+
+    // Returns a handle that can wake this acquisition from outside of tokio
+    pub fn external_waker(&self) -> ExternalWaker {
+        ExternalWaker {
+            task_id: self.task_id,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+// Lets an event source that isn't driven by tokio (i.e. a custom epoll loop) wake an acquisition so
+// that it polls again. A spurious wake is harmless, the acquisition will just go back to waiting.
+#[derive(Debug, Clone)]
+pub struct ExternalWaker {
+    task_id: u64,
+    shared: Arc<Mutex<AcquireStatusShared>>,
+}
+
+impl ExternalWaker {
+    // The task id of the acquisition this wakes
+    pub fn task_id(&self) -> u64 {
+        self.task_id
+    }
+
+    // Wake the task that is waiting on the acquisition. Does nothing if it hasn't been polled yet
+    // (in which case it will be polled anyways)
+    pub fn wake(&self) {
+        if let Some(task) = &self.shared.lock().unwrap().task {
+            task.notify();
+        }
+    }
 }
 
 // Waits until the locks for all required resources can be gathered. The result is a struct that owns
 // the guards for the resources
-pub struct AcquireResources<T> {
-    id: usize,
-    dispatcher: Arc<Dispatcher>,
-    state: AcquireResourcesState,
+pub struct AcquireResources<T, L: AsyncResourceLock = DefaultResourceLock> {
+    id: u64,
+    dispatcher: Arc<Dispatcher<L>>,
+    state: AcquireResourcesState<L>,
+    status: AcquireStatus,
+    // Only allocated if someone asks to observe this acquisition
+    status_handle: Option<AcquireStatusHandle>,
     phantom_data: PhantomData<T>,
-    required_reads: Vec<ResourceId>,
-    required_writes: Vec<ResourceId>,
+    required_reads: ResourceIdList,
+    required_writes: ResourceIdList,
+    // The reads and writes together, in the order their locks will be tried
+    acquisition_order: AcquisitionOrderList,
+
+    // The resource we are currently waiting on, if it has a policy other than Fifo. This lets other
+    // tasks defer to us according to that resource's policy
+    pending: Option<(ResourceId, ResourceAccess)>,
+
+    // When we started waiting for the dispatch lock. Only set if the dispatcher is tracking how
+    // long that takes
+    dispatch_wait_start: Option<std::time::Instant>,
+
+    // The resource we are waiting for and when we started waiting. Only set if the dispatcher is
+    // reporting contention
+    resource_wait_start: Option<(ResourceId, std::time::Instant, Option<&'static str>)>,
+
+    // Set while waiting on a resource that was inserted with a timeout
+    resource_timeout: Option<ResourceTimeout>,
+
+    // Whether we're registered with the dispatcher's resource waiters, so that end_game_loop can
+    // wake us
+    parked: bool,
+
+    // The phase we were created in (see Dispatcher::phase), and when we were first polled
+    #[cfg(feature = "metrics")]
+    phase: Option<&'static str>,
+    #[cfg(feature = "metrics")]
+    first_poll: Option<std::time::Instant>,
 }
 
-#[derive(Debug)]
-enum AcquireResourcesState {
+struct ResourceTimeout {
+    resource_id: ResourceId,
+    deadline: std::time::Instant,
+    // Wakes us at the deadline
+    delay: RuntimeFuture,
+}
+
+impl ResourceTimeout {
+    fn has_expired(&mut self) -> bool {
+        use futures::Future;
+
+        if std::time::Instant::now() >= self.deadline {
+            return true;
+        }
+
+        // If there's no timer (i.e. not running on the runtime) this errors, and the deadline will
+        // only be checked when something else wakes us
+        matches!(self.delay.poll(), Ok(futures::Async::Ready(())))
+    }
+}
+
+enum AcquireResourcesState<L: AsyncResourceLock> {
     // We think we can acquire all required locks and are waiting for our turn to try
-    WaitForDispatch(tokio::sync::lock::Lock<()>),
+    WaitForDispatch(L),
 
     // We were not able to acquire a lock we needed (this lock is pending on the resource we failed
     // to get)
-    WaitForResource(tokio::sync::lock::Lock<()>),
+    WaitForResource(L),
 
     // We acquired the resources
     Finished,
 }
 
-impl<T> AcquireResources<T> {
-    pub fn new(dispatcher: Arc<Dispatcher>, required_resources: RequiredResources<T>) -> Self {
-        AcquireResources::<T> {
-            id: dispatcher.take_task_id(),
+impl<T, L: AsyncResourceLock> AcquireResources<T, L> {
+    pub fn new(dispatcher: Arc<Dispatcher<L>>, required_resources: RequiredResources<T>) -> Self {
+        for resource_id in &required_resources.writes {
+            assert!(
+                !dispatcher.is_seqlock_resource(resource_id),
+                "A SeqLock resource was declared as a write, use SeqLock::set from a read instead"
+            );
+        }
+
+        let id = dispatcher.take_task_id();
+        AcquireResources::<T, L> {
+            id,
             state: AcquireResourcesState::WaitForDispatch(dispatcher.dispatch_lock().clone()),
+            status: AcquireStatus::WaitForDispatch,
+            status_handle: None,
             dispatcher,
             required_reads: required_resources.reads,
             required_writes: required_resources.writes,
+            acquisition_order: AcquisitionOrderList::new(),
             phantom_data: PhantomData,
+            pending: None,
+            dispatch_wait_start: None,
+            resource_wait_start: None,
+            resource_timeout: None,
+            parked: false,
+            #[cfg(feature = "metrics")]
+            phase: crate::phase::current_phase(),
+            #[cfg(feature = "metrics")]
+            first_poll: None,
+        }
+    }
+
+    // Returns a handle that can be used to observe this acquisition while it is in flight
+    pub fn status_handle(&mut self) -> AcquireStatusHandle {
+        let id = self.id;
+        let status = &self.status;
+        let expedite_queue = self.dispatcher.expedite_queue();
+        self.status_handle
+            .get_or_insert_with(|| {
+                AcquireStatusHandle::new(id, status.clone(), expedite_queue.clone())
+            })
+            .clone()
+    }
+
+    fn set_status(&mut self, status: AcquireStatus) {
+        if self.parked && !matches!(status, AcquireStatus::WaitForResource(_)) {
+            self.parked = false;
+            self.dispatcher.resource_waiters().unpark(self.id);
+        }
+
+        if let Some(transition_logger) = self.dispatcher.transition_logger() {
+            transition_logger.log(self.id, std::any::type_name::<T>(), &status);
+        }
+
+        if let Some(status_handle) = &self.status_handle {
+            status_handle.set(status.clone());
         }
+        self.status = status;
+    }
+
+    // Reports the acquisition to the dispatcher's audit sink, if it has one
+    #[cfg(feature = "audit")]
+    fn record_audit(&self) {
+        if let Some(audit_sink) = self.dispatcher.audit_sink() {
+            (audit_sink)(AuditRecord {
+                task_id: self.id,
+                system: std::any::type_name::<T>(),
+                frame: self.dispatcher.frame_count(),
+                reads: self.required_reads.to_vec(),
+                writes: self.required_writes.to_vec(),
+            });
+        }
+    }
+
+    // Calls the dispatcher's on_acquire callback for every lock we just took, and gathers what the
+    // on_release callback will need once they're released
+    fn notify_acquired(&self) -> Option<ReleaseCallback> {
+        let on_acquire = self.dispatcher.on_acquire();
+        let on_release = self.dispatcher.on_release();
+        if on_acquire.is_none() && on_release.is_none() {
+            return None;
+        }
+
+        let mut resources = ResourceIdList::new();
+        let mut snapshots = ResourceIdList::new();
+        for (resource_id, _) in &self.acquisition_order {
+            if self.dispatcher.is_seqlock_resource(resource_id) {
+                continue;
+            }
+
+            if let Some(on_acquire) = on_acquire {
+                on_acquire(resource_id);
+            }
+
+            if self.dispatcher.is_snapshot_resource(resource_id) {
+                snapshots.push(resource_id.clone());
+            } else {
+                resources.push(resource_id.clone());
+            }
+        }
+
+        on_release.map(|on_release| ReleaseCallback {
+            on_release,
+            resources,
+            snapshots,
+        })
+    }
+
+    // Records us as the holder of the locks we just took, if the dispatcher is tracking that.
+    // Snapshot locks are only held until the system's data is fetched, so they're left out
+    fn record_holder(&self) -> Option<(u64, Vec<ResourceId>, Arc<LockHolders>)> {
+        let lock_holders = self.dispatcher.lock_holders()?;
+        let resources: Vec<ResourceId> = self
+            .acquisition_order
+            .iter()
+            .map(|(resource_id, _)| resource_id)
+            .filter(|resource_id| {
+                !self.dispatcher.is_seqlock_resource(resource_id)
+                    && !self.dispatcher.is_snapshot_resource(resource_id)
+            })
+            .cloned()
+            .collect();
+
+        lock_holders.acquired(self.id, std::any::type_name::<T>(), &resources);
+        Some((self.id, resources, lock_holders.clone()))
+    }
+
+    // Also remembers which system was holding the resource (if lock holders are tracked), since
+    // that's who we're waiting on
+    fn begin_resource_wait(&mut self, resource_id: &ResourceId) {
+        if self.dispatcher.tracks_contention() {
+            let blocker = self
+                .dispatcher
+                .lock_holders()
+                .and_then(|lock_holders| lock_holders.holder(resource_id))
+                .map(|(_, system, _)| system);
+            self.resource_wait_start =
+                Some((resource_id.clone(), std::time::Instant::now(), blocker));
+        }
+    }
+
+    // Starts the timeout for the resource we're about to wait on, if it has one. If we were already
+    // waiting on the same resource (we were woken, but lost it to another task again), the original
+    // deadline still applies
+    fn begin_resource_timeout(&mut self, resource_id: &ResourceId) {
+        if let Some(resource_timeout) = &self.resource_timeout {
+            if resource_timeout.resource_id == *resource_id {
+                return;
+            }
+        }
+
+        self.resource_timeout = self
+            .dispatcher
+            .resource_timeout(resource_id)
+            .map(|timeout| ResourceTimeout {
+                resource_id: resource_id.clone(),
+                deadline: std::time::Instant::now() + timeout,
+                delay: DefaultRuntime::delay(timeout),
+            });
+    }
+
+    // Returns true if we were waiting on a resource with a timeout and gave up. Otherwise, makes
+    // sure we're woken at the deadline
+    fn poll_resource_timeout(&mut self) -> bool {
+        let resource_id = match &mut self.resource_timeout {
+            Some(resource_timeout) => {
+                if !resource_timeout.has_expired() {
+                    return false;
+                }
+
+                resource_timeout.resource_id.clone()
+            }
+            None => return false,
+        };
+
+        self.time_out(resource_id);
+        true
+    }
+
+    fn time_out(&mut self, resource_id: ResourceId) {
+        error!(
+            "<{}> Timed out waiting for {} ({:?})",
+            self.id,
+            self.dispatcher
+                .resource_name(&resource_id)
+                .unwrap_or("unknown"),
+            resource_id
+        );
+
+        self.set_pending(None);
+        self.resource_timeout = None;
+        self.set_status(AcquireStatus::TimedOut(resource_id));
+        self.abandon_replay_turn();
+
+        // We're queued on the lock, so it needs to be handed back once it's released
+        let state = std::mem::replace(&mut self.state, AcquireResourcesState::Finished);
+        if let AcquireResourcesState::WaitForResource(lock) = state {
+            release_when_available(lock);
+        }
+    }
+
+    // Once end_game_loop has been called, nothing should be left waiting on a resource that might
+    // never be released. If the dispatcher is terminating, fails the acquisition and returns true.
+    // Locks are only taken all at once, so there are never any partially acquired guards to release
+    fn poll_terminated(&mut self) -> bool {
+        if !self.dispatcher.should_terminate() {
+            return false;
+        }
+
+        match self.dispatcher.contended_shutdown_policy() {
+            ContendedShutdownPolicy::WaitForHolder => return false,
+            ContendedShutdownPolicy::FailWaiters => {
+                debug!("<{}> Dispatcher is terminating, giving up", self.id)
+            }
+            ContendedShutdownPolicy::LogAndAbandon => {
+                let resource_name = match &self.status {
+                    AcquireStatus::WaitForResource(resource_id) => self
+                        .dispatcher
+                        .resource_name(resource_id)
+                        .map(|name| name.to_string())
+                        .unwrap_or_else(|| format!("{:?}", resource_id)),
+                    _ => "the dispatch lock".to_string(),
+                };
+
+                warn!(
+                    "<{}> Abandoned {} while it was waiting for {}, the dispatcher is terminating",
+                    self.id,
+                    std::any::type_name::<T>(),
+                    resource_name
+                );
+            }
+        }
+
+        self.set_pending(None);
+        self.resource_timeout = None;
+        self.dispatcher.expedite_queue().remove(self.id);
+        self.set_status(AcquireStatus::Terminated);
+        self.abandon_replay_turn();
+
+        // Same as time_out, we may be queued on the lock
+        let state = std::mem::replace(&mut self.state, AcquireResourcesState::Finished);
+        if let AcquireResourcesState::WaitForResource(lock) = state {
+            release_when_available(lock);
+        }
+
+        true
+    }
+
+    // A replay waits for each recorded task's turn, so once this acquisition has failed or been
+    // dropped without getting its resources, let the replay move past it
+    fn abandon_replay_turn(&self) {
+        if let Some(replay) = self.dispatcher.replay() {
+            replay.abandoned(self.id);
+        }
+    }
+
+    // Why the acquisition failed, once time_out or poll_terminated has failed it
+    fn failure(&self) -> DispatchError {
+        match &self.status {
+            AcquireStatus::TimedOut(resource_id) => DispatchError::TimedOut(resource_id.clone()),
+            _ => DispatchError::Terminated,
+        }
+    }
+
+    // Called before returning NotReady while waiting on a resource. Registers us to be woken if the
+    // dispatcher starts terminating while we're parked, unless we'd keep waiting anyway. Returns
+    // true if it already has, in which case the acquisition has failed (see poll_terminated)
+    fn park_on_resource(&mut self) -> bool {
+        if !self.parked
+            && self.dispatcher.contended_shutdown_policy() != ContendedShutdownPolicy::WaitForHolder
+        {
+            self.parked = true;
+            self.dispatcher.resource_waiters().park(self.id);
+        }
+
+        // Checked after registering, so an end_game_loop in between isn't missed
+        self.poll_terminated()
+    }
+
+    // Updates which resource we are waiting on, so that resource policies can account for us
+    fn set_pending(&mut self, pending: Option<(ResourceId, ResourceAccess)>) {
+        if let Some((resource_id, access)) = self.pending.take() {
+            if let Some(state) = self.dispatcher.resource_policy_state(&resource_id) {
+                state.remove_pending(access);
+            }
+        }
+
+        if let Some((resource_id, access)) = pending {
+            if let Some(state) = self.dispatcher.resource_policy_state(&resource_id) {
+                state.add_pending(access);
+                self.pending = Some((resource_id, access));
+            }
+        }
+    }
+
+    // Checks the policy of every resource we need. If any of them say we should let another waiting
+    // task go first, returns that resource (and the current task will be woken when it changes)
+    fn find_resource_to_yield_on(&self) -> Option<ResourceId> {
+        let reads = self
+            .required_reads
+            .iter()
+            .map(|resource_id| (resource_id, ResourceAccess::Read));
+        let writes = self
+            .required_writes
+            .iter()
+            .map(|resource_id| (resource_id, ResourceAccess::Write));
+
+        for (resource_id, access) in reads.chain(writes) {
+            if let Some(state) = self.dispatcher.resource_policy_state(resource_id) {
+                let own_pending = match &self.pending {
+                    Some((pending_id, pending_access)) if pending_id == resource_id => {
+                        Some(*pending_access)
+                    }
+                    _ => None,
+                };
+
+                if state.try_yield(access, own_pending) {
+                    return Some(resource_id.clone());
+                }
+            }
+        }
+
+        None
     }
 }
 
-enum TryTakeLocksResult {
+impl<T, L: AsyncResourceLock> Drop for AcquireResources<T, L> {
+    fn drop(&mut self) {
+        self.set_pending(None);
+        self.dispatcher.expedite_queue().remove(self.id);
+        if self.parked {
+            self.dispatcher.resource_waiters().unpark(self.id);
+        }
+
+        if self.status != AcquireStatus::Finished {
+            self.abandon_replay_turn();
+        }
+
+        match std::mem::replace(&mut self.state, AcquireResourcesState::Finished) {
+            AcquireResourcesState::WaitForDispatch(lock)
+            | AcquireResourcesState::WaitForResource(lock) => release_when_available(lock),
+            AcquireResourcesState::Finished => {}
+        }
+    }
+}
+
+fn dispatch_wait_start<L: AsyncResourceLock>(
+    dispatcher: &Dispatcher<L>,
+) -> Option<std::time::Instant> {
+    if dispatcher.tracks_dispatch_lock_waits() {
+        Some(std::time::Instant::now())
+    } else {
+        None
+    }
+}
+
+pub(super) enum TryTakeLocksResult<L: AsyncResourceLock> {
     // All locks were successfully taken, contains the guards for those acquired locks
-    Success(Vec<tokio::sync::lock::LockGuard<()>>),
+    Success(LockGuardList<L>),
 
     // A lock was not able to be captured, the lock here is the lock we need to await
-    Failure(ResourceId, tokio::sync::lock::Lock<()>),
+    Failure(ResourceId, L),
 }
 
-impl<T> AcquireResources<T> {
-    // Tries to take all locks. If successful, returns a Vec of lock guards. Otherwise, returns the
-    // lock that failed (and needs to be awaited before trying to dispatch again)
-    fn try_take_locks(&self, required_resources: &Vec<ResourceId>) -> TryTakeLocksResult {
-        let mut guards = vec![];
-        for resource in required_resources {
-            // We expect every resource type that we will try to fetch already has a lock set up
-            let mut lock = self
-                .dispatcher
-                .resource_locks()
-                .get(&resource)
-                .expect("A resource lock does not exist for a certain type.")
-                .clone();
-
-            match lock.poll_lock() {
-                futures::Async::Ready(guard) => guards.push(guard),
-                futures::Async::NotReady => {
-                    return TryTakeLocksResult::Failure(resource.clone(), lock)
+// Polls the lock, retrying up to spin_retries more times if it isn't available
+fn poll_lock_with_spin<L: AsyncResourceLock>(
+    lock: &mut L,
+    spin_retries: usize,
+) -> futures::Async<L::Guard> {
+    let mut result = lock.poll_lock();
+    for _ in 0..spin_retries {
+        if result.is_ready() {
+            break;
+        }
+
+        std::hint::spin_loop();
+        result = lock.poll_lock();
+    }
+
+    result
+}
+
+// Tries to take all locks. If successful, returns a Vec of lock guards. Otherwise, returns the
+// lock that failed (and needs to be awaited before trying to dispatch again)
+pub(super) fn try_take_locks<L: AsyncResourceLock>(
+    dispatcher: &Dispatcher<L>,
+    required_resources: &[ResourceId],
+) -> TryTakeLocksResult<L> {
+    let mut guards = LockGuardList::<L>::new();
+    for resource in required_resources {
+        // SeqLock resources are safe to read at any time, so they don't have a lock
+        if dispatcher.is_seqlock_resource(resource) {
+            continue;
+        }
+
+        // We expect every resource type that we will try to fetch already has a lock set up
+        let mut lock = dispatcher
+            .resource_lock(resource)
+            .expect("A resource lock does not exist for a certain type.");
+
+        match poll_lock_with_spin(&mut lock, dispatcher.spin_retries()) {
+            futures::Async::Ready(guard) => guards.push(guard),
+            futures::Async::NotReady => return TryTakeLocksResult::Failure(resource.clone(), lock),
+        }
+    }
+
+    TryTakeLocksResult::Success(guards)
+}
+
+enum TryTakeOrderedLocksResult<L: AsyncResourceLock> {
+    // All locks were taken, contains the read guards, the write guards and the guards for
+    // snapshot resources (which are reads that are released early)
+    Success(LockGuardList<L>, LockGuardList<L>, LockGuardList<L>),
+
+    // Same as TryTakeLocksResult::Failure, plus how the resource was going to be accessed
+    Failure(ResourceId, ResourceAccess, L),
+}
+
+// Like try_take_locks, but for reads and writes together in the given order. On failure, also
+// records the failure for AcquisitionOrder::MostContendedFirst
+fn try_take_ordered_locks<L: AsyncResourceLock>(
+    dispatcher: &Dispatcher<L>,
+    resources: &[(ResourceId, ResourceAccess)],
+) -> TryTakeOrderedLocksResult<L> {
+    let mut read_guards = LockGuardList::<L>::new();
+    let mut write_guards = LockGuardList::<L>::new();
+    let mut snapshot_guards = LockGuardList::<L>::new();
+    for (resource, access) in resources {
+        if dispatcher.is_seqlock_resource(resource) {
+            continue;
+        }
+
+        let mut lock = dispatcher
+            .resource_lock(resource)
+            .expect("A resource lock does not exist for a certain type.");
+
+        match poll_lock_with_spin(&mut lock, dispatcher.spin_retries()) {
+            futures::Async::Ready(guard) => match access {
+                ResourceAccess::Read if dispatcher.is_snapshot_resource(resource) => {
+                    snapshot_guards.push(guard)
+                }
+                ResourceAccess::Read => read_guards.push(guard),
+                ResourceAccess::Write => write_guards.push(guard),
+            },
+            futures::Async::NotReady => {
+                if let Some(lock_failures) = dispatcher.lock_failures() {
+                    lock_failures.record_failure(resource);
                 }
+
+                return TryTakeOrderedLocksResult::Failure(resource.clone(), *access, lock);
             }
         }
-
-        TryTakeLocksResult::Success(guards)
     }
+
+    TryTakeOrderedLocksResult::Success(read_guards, write_guards, snapshot_guards)
 }
 
-impl<T> futures::future::Future for AcquireResources<T> {
-    type Item = AcquiredResourcesLockGuards<T>;
-    type Error = ();
+impl<T, L: AsyncResourceLock> futures::future::Future for AcquireResources<T, L> {
+    type Item = AcquiredResourcesLockGuards<T, L>;
+    type Error = DispatchError;
 
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        // Remember which task is driving us so that an external waker can notify it
+        if let Some(status_handle) = &self.status_handle {
+            status_handle.set_task(futures::task::current());
+
+            #[cfg(feature = "metrics")]
+            status_handle.record_poll();
+        }
+
+        #[cfg(feature = "metrics")]
+        if self.phase.is_some() && self.first_poll.is_none() {
+            self.first_poll = Some(std::time::Instant::now());
+        }
+
         trace!(
             "<{}> Task woke up in state {}",
             self.id,
@@ -112,7 +904,48 @@ impl<T> futures::future::Future for AcquireResources<T> {
             }
         );
 
+        // A system that doesn't need any resources (i.e. one that only calls end_game_loop) has
+        // nothing to wait for, so don't make it contend for the dispatch lock with real work
+        if self.required_reads.is_empty() && self.required_writes.is_empty() {
+            trace!("<{}> No resources required, skipping dispatch", self.id);
+            self.dispatcher.expedite_queue().remove(self.id);
+            self.state = AcquireResourcesState::Finished;
+            self.set_status(AcquireStatus::Finished);
+            return Ok(futures::Async::Ready(
+                AcquiredResourcesLockGuards::<T, L>::new(
+                    LockGuardList::<L>::new(),
+                    LockGuardList::<L>::new(),
+                    LockGuardList::<L>::new(),
+                    None,
+                    None,
+                    None,
+                    self.dispatcher.clone(),
+                ),
+            ));
+        }
+
+        // Ordered on the first poll rather than in new, since a system's snapshot resources are
+        // only registered by its setup, which runs after its future is created. The dispatch lock
+        // wait also starts here, time spent queued before the first poll isn't waiting on it
+        if self.acquisition_order.is_empty() {
+            self.dispatch_wait_start = dispatch_wait_start(&self.dispatcher);
+            let dispatcher = &self.dispatcher;
+            self.acquisition_order = ordered_resources(
+                dispatcher.acquisition_order(),
+                &self.required_reads,
+                &self.required_writes,
+                |resource_id| dispatcher.snapshot_source(resource_id),
+            );
+        }
+
         loop {
+            // Don't go back to waiting on a resource if the dispatcher is terminating
+            if let AcquireResourcesState::WaitForResource(_) = &self.state {
+                if self.poll_terminated() {
+                    return Err(DispatchError::Terminated);
+                }
+            }
+
             match &mut self.state {
                 // This state will wait for a lock on the main dispatch lock, and then try to
                 // take a lock on all resources it needs to progress. This is deadlock-safe since
@@ -121,54 +954,170 @@ impl<T> futures::future::Future for AcquireResources<T> {
                     let lock_result = {
                         // Wait until we get an exclusive lock to acquire resources. This is necessary since
                         // we're going to try to grabbing multiple locks at a time to avoid deadlocks.
-                        trace!("<{}> Poll dispatch lock", self.id);
-                        let _dispatch_guard = match dispatch_lock.poll_lock() {
+                        // With shared read dispatch, read-only acquisitions only need the gate
+                        let shared = self.required_writes.is_empty()
+                            && self.dispatcher.has_shared_read_dispatch();
+                        let _dispatch_guard = if shared {
+                            None
+                        } else {
+                            trace!("<{}> Poll dispatch lock", self.id);
+                            match dispatch_lock.poll_lock() {
+                                futures::Async::Ready(guard) => Some(guard),
+                                futures::Async::NotReady => {
+                                    trace!("<{}> Not able to dispatch", self.id);
+                                    return Ok(futures::Async::NotReady);
+                                }
+                            }
+                        };
+
+                        let _gate_guard = match self.dispatcher.poll_dispatch_gate(shared) {
                             futures::Async::Ready(guard) => guard,
                             futures::Async::NotReady => {
-                                trace!("<{}> Not able to dispatch", self.id);
+                                trace!("<{}> Waiting to enter the dispatch gate", self.id);
                                 return Ok(futures::Async::NotReady);
                             }
                         };
 
-                        // At this point we have exclusive permission to check if existing resources
-                        // are available
-                        trace!("<{}> Check resource locks", self.id);
+                        if let Some(dispatch_wait_start) = self.dispatch_wait_start.take() {
+                            self.dispatcher
+                                .record_dispatch_lock_wait(dispatch_wait_start.elapsed());
+                        }
 
-                        // Try to get read access where needed
-                        let read_guards = match self.try_take_locks(&self.required_reads) {
-                            TryTakeLocksResult::Success(guards) => guards,
-                            TryTakeLocksResult::Failure(resource_id, lock) => {
-                                trace!(
-                                    "<{}> Failed to acquire read access for {:?}",
-                                    self.id,
-                                    resource_id
-                                );
-                                self.state = AcquireResourcesState::WaitForResource(lock);
+                        // If we are replaying a recording, wait until it's our turn to be granted
+                        if let Some(replay) = self.dispatcher.replay() {
+                            if !replay.poll_turn(self.id) {
+                                trace!("<{}> Waiting for replay turn", self.id);
                                 return Ok(futures::Async::NotReady);
                             }
-                        };
+                        }
 
-                        // Try to get write access where needed
-                        let write_guards = match self.try_take_locks(&self.required_writes) {
-                            TryTakeLocksResult::Success(guards) => guards,
-                            TryTakeLocksResult::Failure(resource_id, lock) => {
-                                trace!(
-                                    "<{}> Failed to acquire write access for {:?}",
-                                    self.id,
-                                    resource_id
-                                );
-                                self.state = AcquireResourcesState::WaitForResource(lock);
-                                return Ok(futures::Async::NotReady);
+                        // If another acquisition has been expedited, let it go first
+                        if !self.dispatcher.expedite_queue().poll_turn(self.id) {
+                            trace!("<{}> Waiting for an expedited task", self.id);
+                            return Ok(futures::Async::NotReady);
+                        }
+
+                        // Under fault injection, sometimes let whoever is next in line go first.
+                        // Notifying ourselves puts us back in line behind them
+                        #[cfg(feature = "fault-injection")]
+                        {
+                            if let Some(fault_injector) = self.dispatcher.fault_injector() {
+                                if fault_injector.should_yield() {
+                                    trace!("<{}> Injected yield of the dispatch lock", self.id);
+                                    futures::task::current().notify();
+                                    return Ok(futures::Async::NotReady);
+                                }
                             }
-                        };
+                        }
+
+                        // If a resource's policy says someone else waiting on it should go
+                        // first, step aside. We stop counting as pending while yielding so that
+                        // two yielding tasks can't end up waiting on each other
+                        if let Some(resource_id) = self.find_resource_to_yield_on() {
+                            trace!(
+                                "<{}> Yielding to other tasks waiting on {:?}",
+                                self.id,
+                                resource_id
+                            );
+                            self.set_pending(None);
+                            return Ok(futures::Async::NotReady);
+                        }
+
+                        // At this point we have exclusive permission to check if existing resources
+                        // are available
+                        trace!("<{}> Check resource locks", self.id);
+
+                        if let Some(lock_failures) = self.dispatcher.lock_failures() {
+                            lock_failures.sort(&mut self.acquisition_order);
+                        }
+
+                        // Try to get read and write access where needed
+                        let (read_guards, write_guards, snapshot_guards) =
+                            match try_take_ordered_locks(&self.dispatcher, &self.acquisition_order)
+                            {
+                                TryTakeOrderedLocksResult::Success(reads, writes, snapshots) => {
+                                    (reads, writes, snapshots)
+                                }
+                                TryTakeOrderedLocksResult::Failure(resource_id, access, lock) => {
+                                    trace!(
+                                        "<{}> Failed to acquire {:?} access for {:?}",
+                                        self.id,
+                                        access,
+                                        resource_id
+                                    );
+                                    self.set_pending(Some((resource_id.clone(), access)));
+                                    #[cfg(feature = "metrics")]
+                                    if let Some(phase) = self.phase {
+                                        self.dispatcher
+                                            .phase_metrics_tracker()
+                                            .resource_wait(phase);
+                                    }
+                                    self.begin_resource_wait(&resource_id);
+                                    self.begin_resource_timeout(&resource_id);
+                                    self.set_status(AcquireStatus::WaitForResource(resource_id));
+                                    self.state = AcquireResourcesState::WaitForResource(lock);
+                                    if self.poll_resource_timeout() || self.park_on_resource() {
+                                        return Err(self.failure());
+                                    }
+
+                                    return Ok(futures::Async::NotReady);
+                                }
+                            };
 
                         trace!("<{}> Resource locks acquired", self.id);
+                        self.set_pending(None);
+                        self.resource_timeout = None;
+                        self.dispatcher.expedite_queue().remove(self.id);
+
+                        if let Some(replay) = self.dispatcher.replay() {
+                            replay.granted(self.id);
+                        }
+
+                        let release_record = self.dispatcher.recorder().map(|recorder| {
+                            let resources: Vec<ResourceId> = self
+                                .required_reads
+                                .iter()
+                                .chain(self.required_writes.iter())
+                                .cloned()
+                                .collect();
+                            recorder.record(self.id, &resources, AcquisitionEventKind::Acquire);
+                            (self.id, resources, recorder.clone())
+                        });
 
                         // As long as this result is held, it will be safe to fetch the data from shred
-                        AcquiredResourcesLockGuards::<T>::new(read_guards, write_guards)
+                        let release_callback = self.notify_acquired();
+                        let holder_record = self.record_holder();
+                        #[cfg(feature = "audit")]
+                        self.record_audit();
+                        #[cfg(feature = "metrics")]
+                        {
+                            self.dispatcher
+                                .access_counts()
+                                .acquired(&self.required_reads, &self.required_writes);
+                            if let (Some(phase), Some(first_poll)) = (self.phase, self.first_poll) {
+                                self.dispatcher
+                                    .phase_metrics_tracker()
+                                    .acquired(phase, first_poll.elapsed());
+                            }
+                        }
+                        let mut guards = AcquiredResourcesLockGuards::<T, L>::new(
+                            read_guards,
+                            write_guards,
+                            snapshot_guards,
+                            release_record,
+                            release_callback,
+                            holder_record,
+                            self.dispatcher.clone(),
+                        );
+                        guards.version_record = self
+                            .dispatcher
+                            .write_versions()
+                            .map(|w| (self.required_writes.to_vec(), w.clone()));
+                        guards
                     };
 
                     self.state = AcquireResourcesState::Finished;
+                    self.set_status(AcquireStatus::Finished);
                     return Ok(futures::Async::Ready(lock_result));
                 }
                 AcquireResourcesState::WaitForResource(resource_lock) => {
@@ -180,6 +1129,18 @@ impl<T> futures::future::Future for AcquireResources<T> {
                                 "<{}> Woke while waiting for resource but it's still not ready",
                                 self.id
                             );
+
+                            #[cfg(feature = "metrics")]
+                            {
+                                if let Some(status_handle) = &self.status_handle {
+                                    status_handle.record_not_ready_wake();
+                                }
+                            }
+
+                            if self.poll_resource_timeout() || self.park_on_resource() {
+                                return Err(self.failure());
+                            }
+
                             return Ok(futures::Async::NotReady);
                         }
                     }
@@ -188,9 +1149,23 @@ impl<T> futures::future::Future for AcquireResources<T> {
                         "<{}> Woke while waiting for resource, now trying to dispatch",
                         self.id
                     );
+
+                    if let Some((resource_id, wait_start, blocker)) =
+                        self.resource_wait_start.take()
+                    {
+                        self.dispatcher.record_resource_wait(
+                            &resource_id,
+                            std::any::type_name::<T>(),
+                            blocker,
+                            wait_start.elapsed(),
+                        );
+                    }
+
                     self.state = AcquireResourcesState::WaitForDispatch(
                         self.dispatcher.dispatch_lock().clone(),
                     );
+                    self.dispatch_wait_start = dispatch_wait_start(&self.dispatcher);
+                    self.set_status(AcquireStatus::WaitForDispatch);
                 }
 
                 // This state is here to catch if we try to poll in a completed state
@@ -0,0 +1,101 @@
+use hashbrown::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use shred::ResourceId;
+
+use crate::resource_policy::ResourceAccess;
+
+// The order an acquisition tries to take its resources' locks in. Since a task releases
+// everything it took as soon as one lock fails, the order doesn't affect correctness, only how
+// much taking and releasing happens before a task finds out it has to wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AcquisitionOrder {
+    // Reads and then writes, each in the order the system declared them
+    Declaration,
+
+    // Sorted by ResourceId, so every task tries shared resources in the same order
+    #[default]
+    Sorted,
+
+    // The resources that have most often been found locked are tried first, so that a task that
+    // is going to fail usually fails before taking anything. Ties are sorted by ResourceId.
+    MostContendedFirst,
+}
+
+// The resources an acquisition needs, in the order it will try them
+pub(super) type AcquisitionOrderList = smallvec::SmallVec<[(ResourceId, ResourceAccess); 8]>;
+
+// snapshot_source gives the resource whose lock a snapshot resource shares. A snapshot whose source
+// is also required is left out, since the source's access is at least a read and taking the same
+// lock twice would always fail the second time
+pub(super) fn ordered_resources<F>(
+    order: AcquisitionOrder,
+    reads: &[ResourceId],
+    writes: &[ResourceId],
+    snapshot_source: F,
+) -> AcquisitionOrderList
+where
+    F: Fn(&ResourceId) -> Option<ResourceId>,
+{
+    let mut resources: AcquisitionOrderList = reads
+        .iter()
+        .filter(|resource_id| match snapshot_source(resource_id) {
+            Some(source_id) => !reads.contains(&source_id) && !writes.contains(&source_id),
+            None => true,
+        })
+        .map(|resource_id| (resource_id.clone(), ResourceAccess::Read))
+        .chain(
+            writes
+                .iter()
+                .map(|resource_id| (resource_id.clone(), ResourceAccess::Write)),
+        )
+        .collect();
+
+    if order != AcquisitionOrder::Declaration {
+        resources.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    resources
+}
+
+// How many times each resource's lock was found already taken. Only kept when the dispatcher
+// uses AcquisitionOrder::MostContendedFirst
+pub(super) struct LockFailureCounts {
+    counts: HashMap<ResourceId, AtomicUsize>,
+}
+
+impl LockFailureCounts {
+    pub(super) fn new<'a>(resource_ids: impl Iterator<Item = &'a ResourceId>) -> Self {
+        LockFailureCounts {
+            counts: resource_ids
+                .map(|resource_id| (resource_id.clone(), AtomicUsize::new(0)))
+                .collect(),
+        }
+    }
+
+    pub(super) fn record_failure(&self, resource_id: &ResourceId) {
+        // Resources created lazily by a system's setup aren't counted, they just sort last
+        if let Some(count) = self.counts.get(resource_id) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn count(&self, resource_id: &ResourceId) -> usize {
+        self.counts
+            .get(resource_id)
+            .map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    // Reorders the list so the most contended resources come first
+    pub(super) fn sort(&self, resources: &mut AcquisitionOrderList) {
+        resources
+            .sort_by(|(a, _), (b, _)| self.count(b).cmp(&self.count(a)).then_with(|| a.cmp(b)));
+    }
+
+    pub(super) fn clear(&self) {
+        for count in self.counts.values() {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use shred::ResourceId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcquisitionEventKind {
+    Acquire,
+    Release,
+}
+
+impl AcquisitionEventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            AcquisitionEventKind::Acquire => "acquire",
+            AcquisitionEventKind::Release => "release",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "acquire" => Some(AcquisitionEventKind::Acquire),
+            "release" => Some(AcquisitionEventKind::Release),
+            _ => None,
+        }
+    }
+}
+
+// A single lifecycle event for a resource lock. The resource is only kept as a debug string since
+// a ResourceId (which wraps a TypeId) can't be carried across runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcquisitionEvent {
+    pub task_id: u64,
+    pub resource: String,
+    pub kind: AcquisitionEventKind,
+}
+
+// Records every acquire/release of a resource lock, in the order they happened. Pass it to
+// DispatcherBuilder::with_recorder, then serialize it after the run to get a log that can be fed
+// back in with AcquisitionReplay
+#[derive(Default)]
+pub struct AcquisitionRecorder {
+    events: Mutex<Vec<AcquisitionEvent>>,
+}
+
+impl AcquisitionRecorder {
+    pub fn new() -> Self {
+        AcquisitionRecorder {
+            events: Mutex::new(vec![]),
+        }
+    }
+
+    pub(super) fn record(
+        &self,
+        task_id: u64,
+        resources: &[ResourceId],
+        kind: AcquisitionEventKind,
+    ) {
+        let mut events = self.events.lock().unwrap();
+        for resource in resources {
+            events.push(AcquisitionEvent {
+                task_id,
+                resource: format!("{:?}", resource),
+                kind,
+            });
+        }
+    }
+
+    // Returns a copy of all events recorded so far
+    pub fn events(&self) -> Vec<AcquisitionEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    // Serializes the events as one "<task id> <acquire|release> <resource>" line per event
+    pub fn serialize(&self) -> String {
+        let mut serialized = String::new();
+        for event in self.events.lock().unwrap().iter() {
+            serialized.push_str(&format!(
+                "{} {} {}\n",
+                event.task_id,
+                event.kind.as_str(),
+                event.resource
+            ));
+        }
+        serialized
+    }
+
+    // Parses a log produced by serialize(). Returns None if any line is malformed
+    pub fn deserialize(serialized: &str) -> Option<Vec<AcquisitionEvent>> {
+        let mut events = vec![];
+        for line in serialized.lines().filter(|line| !line.trim().is_empty()) {
+            let mut parts = line.splitn(3, ' ');
+            let task_id = parts.next()?.parse().ok()?;
+            let kind = AcquisitionEventKind::from_str(parts.next()?)?;
+            let resource = parts.next().unwrap_or("").to_string();
+            events.push(AcquisitionEvent {
+                task_id,
+                resource,
+                kind,
+            });
+        }
+
+        Some(events)
+    }
+}
+
+struct AcquisitionReplayState {
+    next_grant_index: usize,
+    waiting_tasks: Vec<futures::task::Task>,
+
+    // Recorded tasks that finished without being granted (i.e. they timed out or were dropped).
+    // Their turn is skipped, otherwise every task recorded after them would wait forever
+    abandoned_task_ids: HashSet<u64>,
+}
+
+// Forces acquisitions to be granted in the same order as a previous recording. Tasks are matched by
+// task id, so this only reproduces a run if tasks are created in the same order as they were when
+// recording. Tasks that don't appear in the recording (or that arrive after the recording has been
+// fully replayed) are not constrained. A recorded task that fails or is dropped before it's granted
+// gives up its turn.
+pub struct AcquisitionReplay {
+    grant_order: Vec<u64>,
+    recorded_task_ids: HashSet<u64>,
+    state: Mutex<AcquisitionReplayState>,
+}
+
+impl AcquisitionReplay {
+    pub fn new(events: &[AcquisitionEvent]) -> Self {
+        // Each acquisition produces one acquire event per resource, collapse those into a single
+        // grant for the task
+        let mut grant_order = vec![];
+        for event in events {
+            if event.kind == AcquisitionEventKind::Acquire
+                && grant_order.last() != Some(&event.task_id)
+            {
+                grant_order.push(event.task_id);
+            }
+        }
+
+        let recorded_task_ids = grant_order.iter().cloned().collect();
+
+        AcquisitionReplay {
+            grant_order,
+            recorded_task_ids,
+            state: Mutex::new(AcquisitionReplayState {
+                next_grant_index: 0,
+                waiting_tasks: vec![],
+                abandoned_task_ids: HashSet::new(),
+            }),
+        }
+    }
+
+    // Returns true if the given task is allowed to try to acquire now. If not, the current task is
+    // parked and will be notified when the next grant happens
+    pub(super) fn poll_turn(&self, task_id: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match self.grant_order.get(state.next_grant_index) {
+            None => true,
+            Some(_) if !self.recorded_task_ids.contains(&task_id) => true,
+            Some(next_task_id) if *next_task_id == task_id => true,
+            Some(_) => {
+                state.waiting_tasks.push(futures::task::current());
+                false
+            }
+        }
+    }
+
+    // Called after a task acquires its resources. Advances the replay and wakes anything waiting
+    // on its turn
+    pub(super) fn granted(&self, task_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if self.grant_order.get(state.next_grant_index) == Some(&task_id) {
+            state.next_grant_index += 1;
+            self.advance(&mut state);
+        }
+    }
+
+    // Called when a task finishes without acquiring its resources. If the task was recorded, its
+    // turn is skipped, right away if it's next, otherwise once the replay reaches it
+    pub(super) fn abandoned(&self, task_id: u64) {
+        if !self.recorded_task_ids.contains(&task_id) {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.abandoned_task_ids.insert(task_id);
+        self.advance(&mut state);
+    }
+
+    // Skips past any abandoned turns and wakes anything waiting on its turn
+    fn advance(&self, state: &mut AcquisitionReplayState) {
+        while let Some(next_task_id) = self.grant_order.get(state.next_grant_index) {
+            if !state.abandoned_task_ids.contains(next_task_id) {
+                break;
+            }
+
+            state.next_grant_index += 1;
+        }
+
+        for task in state.waiting_tasks.drain(..) {
+            task.notify();
+        }
+    }
+
+    // True once every recorded grant has been replayed
+    pub fn is_complete(&self) -> bool {
+        self.state.lock().unwrap().next_grant_index >= self.grant_order.len()
+    }
+}
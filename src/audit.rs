@@ -0,0 +1,16 @@
+use shred::ResourceId;
+
+// One acquisition, as given to the sink passed to DispatcherBuilder::with_audit_sink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub task_id: u64,
+    // The type name of the system the resources were acquired for. Acquisitions that aren't for a
+    // system (i.e. Dispatcher::acquire_blocking) show up as "()"
+    pub system: &'static str,
+    // The frame the acquisition finished in
+    pub frame: u64,
+    pub reads: Vec<ResourceId>,
+    pub writes: Vec<ResourceId>,
+}
+
+pub(super) type AuditSinkFn = dyn Fn(AuditRecord) + Send + Sync;
@@ -0,0 +1,56 @@
+use std::sync::RwLockReadGuard;
+
+use hashbrown::HashSet;
+use shred::ResourceId;
+
+use super::AsyncResourceLock;
+
+// The resources that Dispatcher::snapshot_available found free, held until this is dropped.
+// Resources that something else was holding at the time can't be read, they're listed in skipped
+pub struct AvailableResources<'a, L: AsyncResourceLock> {
+    // None if the world itself was being changed (i.e. during maintenance), in which case every
+    // resource is skipped
+    world: Option<RwLockReadGuard<'a, shred::World>>,
+    available: HashSet<ResourceId>,
+    skipped: Vec<ResourceId>,
+    _guards: Vec<L::Guard>,
+}
+
+impl<'a, L: AsyncResourceLock> AvailableResources<'a, L> {
+    pub(super) fn new(
+        world: Option<RwLockReadGuard<'a, shred::World>>,
+        available: HashSet<ResourceId>,
+        skipped: Vec<ResourceId>,
+        guards: Vec<L::Guard>,
+    ) -> Self {
+        AvailableResources {
+            world,
+            available,
+            skipped,
+            _guards: guards,
+        }
+    }
+
+    // Runs f with the resource, or returns None if it was skipped or isn't in the world
+    pub fn read<R, F, RetT>(&self, f: F) -> Option<RetT>
+    where
+        R: shred::Resource,
+        F: FnOnce(&R) -> RetT,
+    {
+        if !self.is_available(&ResourceId::new::<R>()) {
+            return None;
+        }
+
+        let resource = self.world.as_ref()?.try_fetch::<R>()?;
+        Some(f(&resource))
+    }
+
+    pub fn is_available(&self, resource_id: &ResourceId) -> bool {
+        self.world.is_some() && self.available.contains(resource_id)
+    }
+
+    // The resources that were held by someone else, so they couldn't be read
+    pub fn skipped(&self) -> &[ResourceId] {
+        &self.skipped
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+type ChildFuture<ErrorT> = dyn futures::future::Future<Item = (), Error = ErrorT> + Send;
+
+// A queue of work that persists across frames. Each frame, call run() to get a future that executes
+// queued futures in sequence until the time budget is used up. Anything that didn't get started is
+// left in the queue for the next frame.
+pub struct BudgetedStage<ErrorT> {
+    budget: Duration,
+    queue: Arc<Mutex<VecDeque<Box<ChildFuture<ErrorT>>>>>,
+}
+
+// Not derived since that would require ErrorT: Clone
+impl<ErrorT> Clone for BudgetedStage<ErrorT> {
+    fn clone(&self) -> Self {
+        BudgetedStage {
+            budget: self.budget,
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<ErrorT> BudgetedStage<ErrorT> {
+    pub fn new(budget: Duration) -> Self {
+        BudgetedStage {
+            budget,
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // Add work to the end of the queue. It will be started during a future frame's run()
+    pub fn queue(&self, future: Box<ChildFuture<ErrorT>>) {
+        self.queue.lock().unwrap().push_back(future);
+    }
+
+    // The number of futures waiting to be started
+    pub fn queued_count(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    // Returns a future representing this frame's share of the queued work
+    pub fn run(&self) -> ExecuteBudgeted<ErrorT> {
+        ExecuteBudgeted {
+            budget: self.budget,
+            queue: self.queue.clone(),
+            current: None,
+            start_time: None,
+            completed_count: 0,
+        }
+    }
+}
+
+// Executes queued futures in sequence. After each one completes, the elapsed time is checked and if
+// the budget has been exceeded, no more futures are started. At least one future is always started
+// so that a small budget can't starve the queue. If a future results in an error, we stop and
+// return that error.
+pub struct ExecuteBudgeted<ErrorT> {
+    budget: Duration,
+    queue: Arc<Mutex<VecDeque<Box<ChildFuture<ErrorT>>>>>,
+    current: Option<Box<ChildFuture<ErrorT>>>,
+    start_time: Option<Instant>,
+    completed_count: usize,
+}
+
+impl<ErrorT> futures::future::Future for ExecuteBudgeted<ErrorT> {
+    type Item = ();
+    type Error = ErrorT;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let start_time = *self.start_time.get_or_insert_with(Instant::now);
+
+        loop {
+            if self.current.is_none() {
+                if self.completed_count > 0 && start_time.elapsed() >= self.budget {
+                    trace!(
+                        "Budget exceeded after {} futures, deferring the rest",
+                        self.completed_count
+                    );
+                    return Ok(futures::Async::Ready(()));
+                }
+
+                self.current = self.queue.lock().unwrap().pop_front();
+                if self.current.is_none() {
+                    return Ok(futures::Async::Ready(()));
+                }
+            }
+
+            let result = self.current.as_mut().unwrap().poll();
+            match result {
+                Err(e) => {
+                    self.current = None;
+                    return Err(e);
+                }
+                Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                Ok(futures::Async::Ready(_)) => {
+                    self.current = None;
+                    self.completed_count += 1;
+                }
+            }
+        }
+    }
+}
+
+impl<ErrorT> Drop for ExecuteBudgeted<ErrorT> {
+    fn drop(&mut self) {
+        // If this frame's future is dropped partway through a child, put the child back at the
+        // front of the queue so that it can be resumed next frame
+        if let Some(current) = self.current.take() {
+            if let Ok(mut queue) = self.queue.lock() {
+                queue.push_front(current);
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use hashbrown::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+// Keys of systems created with Dispatcher::create_future_coalesced that are still waiting for
+// their resources
+pub(super) struct PendingKeys {
+    keys: Mutex<HashSet<u64>>,
+}
+
+impl PendingKeys {
+    pub(super) fn new() -> Self {
+        PendingKeys {
+            keys: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // Marks the key as pending until the returned guard is dropped. Returns None if it's already
+    // pending
+    pub(super) fn try_insert(self: &Arc<Self>, key: u64) -> Option<PendingKey> {
+        if !self.keys.lock().unwrap().insert(key) {
+            return None;
+        }
+
+        Some(PendingKey {
+            pending_keys: self.clone(),
+            key,
+        })
+    }
+}
+
+pub(super) struct PendingKey {
+    pending_keys: Arc<PendingKeys>,
+    key: u64,
+}
+
+impl Drop for PendingKey {
+    fn drop(&mut self) {
+        self.pending_keys.keys.lock().unwrap().remove(&self.key);
+    }
+}
@@ -0,0 +1,142 @@
+use hashbrown::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use shred::ResourceId;
+
+// How much waiting a single resource caused during a frame
+#[derive(Debug, Clone)]
+pub struct ResourceContention {
+    pub resource_name: String,
+    pub wait_count: usize,
+    pub total_wait: Duration,
+    // The systems that had to wait for the resource, each listed once
+    pub blocked_systems: Vec<&'static str>,
+}
+
+// A system that held a resource while another system waited for it, see
+// Dispatcher::contention_pairs
+#[derive(Debug, Clone)]
+pub struct BlockingSystem {
+    pub system: &'static str,
+    pub wait_count: usize,
+    pub total_wait: Duration,
+}
+
+// Every resource that something had to wait on during a frame, most total waiting first
+#[derive(Debug, Clone)]
+pub struct FrameContention {
+    pub frame_index: u64,
+    pub resources: Vec<ResourceContention>,
+}
+
+#[derive(Default)]
+struct ContentionState {
+    current_frame: HashMap<ResourceId, ResourceContention>,
+    last_frame: Option<FrameContention>,
+    // Waiting system -> holding system -> (wait count, total wait). Kept across frames
+    blocked_by: HashMap<&'static str, HashMap<&'static str, (usize, Duration)>>,
+}
+
+// Accumulates resource waits for the frame in progress. At the end of a frame the accumulated
+// waits become the last frame's report and the next frame starts empty
+pub(super) struct ContentionTracker {
+    state: Mutex<ContentionState>,
+}
+
+impl ContentionTracker {
+    pub(super) fn new() -> Self {
+        ContentionTracker {
+            state: Mutex::new(ContentionState::default()),
+        }
+    }
+
+    pub(super) fn record_wait(
+        &self,
+        resource_id: &ResourceId,
+        resource_name: impl FnOnce() -> String,
+        system_name: &'static str,
+        duration: Duration,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let contention = state
+            .current_frame
+            .entry(resource_id.clone())
+            .or_insert_with(|| ResourceContention {
+                resource_name: resource_name(),
+                wait_count: 0,
+                total_wait: Duration::default(),
+                blocked_systems: vec![],
+            });
+
+        contention.wait_count += 1;
+        contention.total_wait += duration;
+        if !contention.blocked_systems.contains(&system_name) {
+            contention.blocked_systems.push(system_name);
+        }
+    }
+
+    pub(super) fn record_blocked(
+        &self,
+        system_name: &'static str,
+        blocker: &'static str,
+        duration: Duration,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let blocked = state
+            .blocked_by
+            .entry(system_name)
+            .or_default()
+            .entry(blocker)
+            .or_insert((0, Duration::default()));
+
+        blocked.0 += 1;
+        blocked.1 += duration;
+    }
+
+    pub(super) fn end_frame(&self, frame_index: u64) {
+        let mut state = self.state.lock().unwrap();
+        let mut resources: Vec<_> = state
+            .current_frame
+            .drain()
+            .map(|(_, contention)| contention)
+            .collect();
+        resources.sort_by_key(|contention| std::cmp::Reverse(contention.total_wait));
+
+        state.last_frame = Some(FrameContention {
+            frame_index,
+            resources,
+        });
+    }
+
+    pub(super) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.current_frame.clear();
+        state.last_frame = None;
+        state.blocked_by.clear();
+    }
+
+    pub(super) fn last_frame(&self) -> Option<FrameContention> {
+        self.state.lock().unwrap().last_frame.clone()
+    }
+
+    pub(super) fn contention_pairs(&self) -> HashMap<&'static str, Vec<BlockingSystem>> {
+        let state = self.state.lock().unwrap();
+        state
+            .blocked_by
+            .iter()
+            .map(|(system_name, blockers)| {
+                let mut blockers: Vec<_> = blockers
+                    .iter()
+                    .map(|(blocker, (wait_count, total_wait))| BlockingSystem {
+                        system: blocker,
+                        wait_count: *wait_count,
+                        total_wait: *total_wait,
+                    })
+                    .collect();
+                blockers.sort_by_key(|blocker| std::cmp::Reverse(blocker.total_wait));
+                (*system_name, blockers)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use shred::ResourceId;
+
+use super::acquire_resources::try_take_locks;
+use super::acquire_resources::TryTakeLocksResult;
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use crate::resource_lock::release_when_available;
+
+// Holds the locks for all resources acquired across several dispatchers. As long as this is held,
+// it is safe to fetch the acquired resources from each dispatcher's world
+pub struct CrossAcquiredResourcesLockGuards<L: AsyncResourceLock = DefaultResourceLock> {
+    _guards: Vec<L::Guard>,
+}
+
+// A dispatcher and the reads/writes to acquire from it
+pub type CrossDispatcherRequest<'a, L = DefaultResourceLock> =
+    (&'a Arc<Dispatcher<L>>, Vec<ResourceId>, Vec<ResourceId>);
+
+// A single dispatcher and the resources we need from it
+struct CrossDispatcherEntry<L: AsyncResourceLock> {
+    dispatcher: Arc<Dispatcher<L>>,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+}
+
+// Entry point for acquiring resources from multiple dispatchers as a single operation
+pub struct CrossDispatcher;
+
+impl CrossDispatcher {
+    // Returns a future that acquires the given reads/writes from every dispatcher at once. Dispatch
+    // locks are always taken in the same global order (by dispatcher address) so that two cross
+    // acquisitions can't each hold a dispatch lock the other needs
+    pub fn acquire<L: AsyncResourceLock>(
+        requests: &[CrossDispatcherRequest<L>],
+    ) -> CrossAcquireResources<L> {
+        let mut entries: Vec<CrossDispatcherEntry<L>> = vec![];
+        for (dispatcher, reads, writes) in requests {
+            // If the same dispatcher is passed more than once, merge the requests so that we
+            // don't try to take its dispatch lock twice
+            match entries
+                .iter_mut()
+                .find(|entry| Arc::ptr_eq(&entry.dispatcher, dispatcher))
+            {
+                Some(entry) => {
+                    entry.reads.extend(reads.iter().cloned());
+                    entry.writes.extend(writes.iter().cloned());
+                }
+                None => entries.push(CrossDispatcherEntry {
+                    dispatcher: (*dispatcher).clone(),
+                    reads: reads.clone(),
+                    writes: writes.clone(),
+                }),
+            }
+        }
+
+        entries.sort_by_key(|entry| Arc::as_ptr(&entry.dispatcher) as usize);
+        for entry in &mut entries {
+            entry.reads.sort();
+            entry.reads.dedup();
+            entry.writes.sort();
+            entry.writes.dedup();
+
+            // A resource that one request reads and another writes only needs the write, taking
+            // its lock for both would fail on the second take every time
+            let writes = &entry.writes;
+            entry
+                .reads
+                .retain(|resource_id| writes.binary_search(resource_id).is_err());
+        }
+
+        let id = entries
+            .first()
+            .map(|entry| entry.dispatcher.take_task_id())
+            .unwrap_or(0);
+
+        CrossAcquireResources {
+            id,
+            entries,
+            state: CrossAcquireResourcesState::WaitForDispatch,
+        }
+    }
+}
+
+enum CrossAcquireResourcesState<L: AsyncResourceLock> {
+    // We think we can acquire all required locks and are waiting for our turn to try
+    WaitForDispatch,
+
+    // A dispatch lock was held by someone else, this is the lock we need to await
+    WaitForDispatchLock(L),
+
+    // We were not able to acquire a resource lock we needed
+    WaitForResource(L),
+
+    // We acquired the resources
+    Finished,
+}
+
+// Waits until all dispatch locks can be taken together, and then tries to take the resource locks
+// from every dispatcher. If anything fails, everything is dropped and we wait on the lock that
+// failed
+pub struct CrossAcquireResources<L: AsyncResourceLock = DefaultResourceLock> {
+    id: u64,
+    entries: Vec<CrossDispatcherEntry<L>>,
+    state: CrossAcquireResourcesState<L>,
+}
+
+impl<L: AsyncResourceLock> Drop for CrossAcquireResources<L> {
+    fn drop(&mut self) {
+        // We may be queued on this lock, so it needs to be handed back once it's released
+        match std::mem::replace(&mut self.state, CrossAcquireResourcesState::Finished) {
+            CrossAcquireResourcesState::WaitForDispatchLock(lock)
+            | CrossAcquireResourcesState::WaitForResource(lock) => release_when_available(lock),
+            _ => {}
+        }
+    }
+}
+
+impl<L: AsyncResourceLock> CrossAcquireResources<L> {
+    fn try_acquire(&self) -> Result<Vec<L::Guard>, CrossAcquireResourcesState<L>> {
+        // Take every dispatch lock in order. If we fail to get one, release the ones we have so
+        // that other dispatches can proceed while we wait
+        let mut dispatch_guards = Vec::with_capacity(self.entries.len());
+        let mut gate_guards = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            let mut dispatch_lock = entry.dispatcher.dispatch_lock().clone();
+            match dispatch_lock.poll_lock() {
+                futures::Async::Ready(guard) => dispatch_guards.push(guard),
+                futures::Async::NotReady => {
+                    trace!("<{}> Not able to dispatch", self.id);
+                    return Err(CrossAcquireResourcesState::WaitForDispatchLock(
+                        dispatch_lock,
+                    ));
+                }
+            }
+
+            // We're notified once the readers leave the gate
+            match entry.dispatcher.poll_dispatch_gate(false) {
+                futures::Async::Ready(guard) => gate_guards.push(guard),
+                futures::Async::NotReady => {
+                    trace!("<{}> Waiting to enter the dispatch gate", self.id);
+                    return Err(CrossAcquireResourcesState::WaitForDispatch);
+                }
+            }
+        }
+
+        // At this point we have exclusive permission to check resources in all dispatchers
+        trace!("<{}> Check resource locks across dispatchers", self.id);
+        let mut guards = vec![];
+        for entry in &self.entries {
+            for required in &[&entry.reads, &entry.writes] {
+                match try_take_locks(&entry.dispatcher, required) {
+                    TryTakeLocksResult::Success(acquired) => guards.extend(acquired),
+                    TryTakeLocksResult::Failure(resource_id, lock) => {
+                        trace!("<{}> Failed to acquire {:?}", self.id, resource_id);
+                        return Err(CrossAcquireResourcesState::WaitForResource(lock));
+                    }
+                }
+            }
+        }
+
+        trace!("<{}> Resource locks acquired across dispatchers", self.id);
+        Ok(guards)
+    }
+}
+
+impl<L: AsyncResourceLock> futures::future::Future for CrossAcquireResources<L> {
+    type Item = CrossAcquiredResourcesLockGuards<L>;
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        loop {
+            match &mut self.state {
+                CrossAcquireResourcesState::WaitForDispatch => match self.try_acquire() {
+                    Ok(guards) => {
+                        self.state = CrossAcquireResourcesState::Finished;
+                        return Ok(futures::Async::Ready(CrossAcquiredResourcesLockGuards {
+                            _guards: guards,
+                        }));
+                    }
+                    Err(state) => {
+                        self.state = state;
+                        return Ok(futures::Async::NotReady);
+                    }
+                },
+                CrossAcquireResourcesState::WaitForDispatchLock(lock)
+                | CrossAcquireResourcesState::WaitForResource(lock) => {
+                    // If we don't poll the lock after waiting for it, we will get stuck
+                    match lock.poll_lock() {
+                        futures::Async::Ready(_) => {}
+                        futures::Async::NotReady => return Ok(futures::Async::NotReady),
+                    }
+
+                    trace!("<{}> Woke while waiting, now trying to dispatch", self.id);
+                    self.state = CrossAcquireResourcesState::WaitForDispatch;
+                }
+
+                // This state is here to catch if we try to poll in a completed state
+                CrossAcquireResourcesState::Finished => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DispatcherBuilder;
+    use futures::Future;
+
+    struct Counter;
+
+    #[test]
+    fn same_dispatcher_reading_and_writing_acquires() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter).build());
+            let counter_id = ResourceId::new::<Counter>();
+
+            let guards = CrossDispatcher::acquire(&[
+                (&dispatcher, vec![counter_id.clone()], vec![]),
+                (&dispatcher, vec![], vec![counter_id]),
+            ])
+            .wait();
+            tx.send(guards.is_ok()).unwrap();
+        });
+
+        let acquired = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The acquisition never finished");
+        assert!(acquired);
+    }
+}
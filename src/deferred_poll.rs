@@ -0,0 +1,139 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+// How many ExecuteSequentials can be polled inside each other before the innermost stops polling
+// its child itself and leaves it for the outermost one
+pub(super) const MAX_INLINE_DEPTH: usize = 64;
+
+type ChildFuture<ErrorT> = dyn futures::future::Future<Item = (), Error = ErrorT> + Send;
+
+thread_local! {
+    // How many ExecuteSequentials are being polled on this thread right now
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    // Children that were too deep to poll inline, waiting for the outermost ExecuteSequential
+    static DEFERRED: RefCell<Vec<Arc<dyn PollDeferred>>> = const { RefCell::new(vec![]) };
+}
+
+trait PollDeferred {
+    // Returns true if the child finished
+    fn poll_deferred(&self) -> bool;
+}
+
+// Marks an ExecuteSequential as being polled, see enter
+pub(super) struct DepthGuard {
+    depth: usize,
+}
+
+impl DepthGuard {
+    pub(super) fn is_outermost(&self) -> bool {
+        self.depth == 0
+    }
+
+    pub(super) fn is_too_deep(&self) -> bool {
+        self.depth >= MAX_INLINE_DEPTH
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(self.depth));
+
+        // Anything still queued belongs to a poll that panicked, it mustn't be polled on behalf of
+        // whatever task runs on this thread next
+        if self.depth == 0 {
+            DEFERRED.with(|deferred| deferred.borrow_mut().clear());
+        }
+    }
+}
+
+// Called at the start of ExecuteSequential::poll. Nested ExecuteSequentials poll into each other,
+// so a deeply nested one (i.e. built programmatically from data) would otherwise need a stack frame
+// per level. Past MAX_INLINE_DEPTH the child is put in a DeferredChild instead and polled by the
+// outermost ExecuteSequential, which starts over at the top of the stack.
+pub(super) fn enter() -> DepthGuard {
+    let depth = DEPTH.with(|depth| {
+        let current = depth.get();
+        depth.set(current + 1);
+        current
+    });
+
+    DepthGuard { depth }
+}
+
+// Polls every deferred child, including ones that are deferred while doing so. Only the outermost
+// ExecuteSequential calls this. Returns true if any of them finished, in which case whoever was
+// waiting on it has to be polled again to see it
+pub(super) fn poll_deferred() -> bool {
+    let mut any_finished = false;
+    while let Some(deferred) = DEFERRED.with(|deferred| deferred.borrow_mut().pop()) {
+        any_finished |= deferred.poll_deferred();
+    }
+
+    any_finished
+}
+
+struct DeferredState<ErrorT> {
+    future: Option<Box<ChildFuture<ErrorT>>>,
+    result: Option<Result<(), ErrorT>>,
+    queued: bool,
+}
+
+// A child of an ExecuteSequential that is polled by the outermost ExecuteSequential instead of by
+// its parent. Everything happens within the same poll of the same task, so the child is woken the
+// same as it would be if it was polled inline
+pub(super) struct DeferredChild<ErrorT> {
+    state: Mutex<DeferredState<ErrorT>>,
+}
+
+impl<ErrorT: 'static> DeferredChild<ErrorT> {
+    pub(super) fn new(future: Box<ChildFuture<ErrorT>>) -> Arc<Self> {
+        Arc::new(DeferredChild {
+            state: Mutex::new(DeferredState {
+                future: Some(future),
+                result: None,
+                queued: false,
+            }),
+        })
+    }
+
+    // Returns the child's result if it has finished, otherwise asks the outermost
+    // ExecuteSequential to poll it
+    pub(super) fn poll(this: &Arc<Self>) -> Option<Result<(), ErrorT>> {
+        let mut state = this.state.lock().unwrap();
+        if let Some(result) = state.result.take() {
+            return Some(result);
+        }
+
+        if !state.queued {
+            state.queued = true;
+            let deferred = this.clone() as Arc<dyn PollDeferred>;
+            DEFERRED.with(|queue| queue.borrow_mut().push(deferred));
+        }
+
+        None
+    }
+}
+
+impl<ErrorT> PollDeferred for DeferredChild<ErrorT> {
+    fn poll_deferred(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.queued = false;
+        let result = match &mut state.future {
+            Some(future) => future.poll(),
+            None => return false,
+        };
+
+        let result = match result {
+            Ok(futures::Async::NotReady) => return false,
+            Ok(futures::Async::Ready(())) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        // Drop it now so that anything it holds (like resource locks) is released right away
+        state.future = None;
+        state.result = Some(result);
+        true
+    }
+}
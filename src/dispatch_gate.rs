@@ -0,0 +1,84 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
+struct DispatchGateState {
+    // Read-only acquisitions that are trying to take their locks right now
+    readers: usize,
+    // Set while an acquisition that writes is trying to take its locks, or is waiting for the
+    // readers to finish so that it can
+    writer: bool,
+    waiting_tasks: Vec<futures::task::Task>,
+}
+
+// Lets read-only acquisitions try to take their locks at the same time as each other while
+// acquisitions that write still do it alone. See DispatcherBuilder::with_shared_read_dispatch.
+// Acquisitions that write also hold the dispatch lock while they're in the gate, so at most one of
+// them is ever trying at a time. Once one is waiting, no new readers are let in. Nobody waits while
+// they're inside the gate, it's only held for one attempt at taking locks.
+pub(super) struct DispatchGate {
+    state: Mutex<DispatchGateState>,
+}
+
+// Leaves the gate when dropped
+pub(super) struct DispatchGateGuard {
+    gate: Arc<DispatchGate>,
+    shared: bool,
+}
+
+impl DispatchGate {
+    pub(super) fn new() -> Self {
+        DispatchGate {
+            state: Mutex::new(DispatchGateState {
+                readers: 0,
+                writer: false,
+                waiting_tasks: vec![],
+            }),
+        }
+    }
+
+    // Enters the gate, shared for a read-only acquisition or exclusive otherwise. If it can't be
+    // entered, the current task is notified when it's worth trying again
+    pub(super) fn poll_enter(this: &Arc<Self>, shared: bool) -> futures::Async<DispatchGateGuard> {
+        let mut state = this.state.lock().unwrap();
+        let entered = if shared {
+            if !state.writer {
+                state.readers += 1;
+            }
+
+            !state.writer
+        } else {
+            // Keeps new readers out while we wait for the current ones to leave
+            state.writer = true;
+            state.readers == 0
+        };
+
+        if !entered {
+            state.waiting_tasks.push(futures::task::current());
+            return futures::Async::NotReady;
+        }
+
+        futures::Async::Ready(DispatchGateGuard {
+            gate: this.clone(),
+            shared,
+        })
+    }
+}
+
+impl Drop for DispatchGateGuard {
+    fn drop(&mut self) {
+        let mut state = self.gate.state.lock().unwrap();
+        if self.shared {
+            state.readers -= 1;
+            if state.readers > 0 {
+                return;
+            }
+        }
+
+        // The writer that was waiting (if any) has to enter again, and may have gone away. Clearing
+        // the flag means readers that were kept out aren't stuck if it never comes back
+        state.writer = false;
+        for task in state.waiting_tasks.drain(..) {
+            task.notify();
+        }
+    }
+}
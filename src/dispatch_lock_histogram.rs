@@ -0,0 +1,90 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+// Bucket 0 holds waits under 1us. After that, bucket i holds waits in [2^(i-1), 2^i) microseconds,
+// and the last bucket holds everything longer (about 17 minutes and up)
+const BUCKET_COUNT: usize = 32;
+
+fn bucket_index(duration: Duration) -> usize {
+    let micros = duration.as_micros();
+    if micros == 0 {
+        return 0;
+    }
+
+    let index = (128 - micros.leading_zeros()) as usize;
+    index.min(BUCKET_COUNT - 1)
+}
+
+// A snapshot of how long acquisitions waited to get the dispatch lock, bucketed by powers of two
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DispatchLockWaitHistogram {
+    counts: [u64; BUCKET_COUNT],
+}
+
+impl DispatchLockWaitHistogram {
+    pub fn bucket_count(&self) -> usize {
+        BUCKET_COUNT
+    }
+
+    // The range of wait times counted by the given bucket. The last bucket has no upper bound
+    pub fn bucket_range(&self, index: usize) -> (Duration, Option<Duration>) {
+        assert!(index < BUCKET_COUNT);
+        let min = if index == 0 {
+            Duration::from_micros(0)
+        } else {
+            Duration::from_micros(1 << (index - 1))
+        };
+
+        let max = if index == BUCKET_COUNT - 1 {
+            None
+        } else {
+            Some(Duration::from_micros(1 << index))
+        };
+
+        (min, max)
+    }
+
+    // The number of waits that fell in the given bucket
+    pub fn count(&self, index: usize) -> u64 {
+        self.counts[index]
+    }
+
+    // The number of waits recorded across all buckets
+    pub fn total_count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+// Records dispatch lock waits. This is only a few atomic adds so it's cheap enough to leave on
+// while benchmarking
+pub(super) struct DispatchLockWaits {
+    counts: [AtomicU64; BUCKET_COUNT],
+}
+
+impl DispatchLockWaits {
+    pub(super) fn new() -> Self {
+        DispatchLockWaits {
+            counts: Default::default(),
+        }
+    }
+
+    pub(super) fn record(&self, duration: Duration) {
+        self.counts[bucket_index(duration)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn clear(&self) {
+        for count in &self.counts {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub(super) fn histogram(&self) -> DispatchLockWaitHistogram {
+        let mut counts = [0; BUCKET_COUNT];
+        for (count, recorded) in counts.iter_mut().zip(&self.counts) {
+            *count = recorded.load(Ordering::Relaxed);
+        }
+
+        DispatchLockWaitHistogram { counts }
+    }
+}
@@ -1,167 +1,2748 @@
 use hashbrown::HashMap;
+use hashbrown::HashSet;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
 
 use shred::ResourceId;
 
+use super::AcquireStatusHandle;
+use super::AcquisitionOrder;
+use super::AcquisitionRecorder;
+use super::AcquisitionReplay;
+use super::AsyncResourceLock;
+use super::AtFrame;
+#[cfg(feature = "audit")]
+use super::AuditRecord;
+use super::AvailableResources;
+use super::BackBuffer;
+use super::BlockingSystem;
+use super::DefaultResourceLock;
+use super::DependsOn;
+use super::DispatchLockWaitHistogram;
+use super::ExecuteParallel;
+use super::ExternalWaker;
+use super::FrameContention;
+use super::FrameStats;
+use super::FrameTiming;
+use super::HealthReport;
+use super::LogConfig;
+use super::LoopHandle;
+use super::ManualLoop;
+#[cfg(feature = "metrics")]
+use super::PhaseMetrics;
+use super::PlannedSystem;
+use super::PlannedSystemFuture;
+use super::PrefetchableSystem;
+use super::ReacquireGuard;
+use super::ReadFront;
+use super::Replicate;
+use super::ResourceBundle;
+use super::ResourceDeps;
+use super::ResourceLockPolicy;
+use super::ResourceScope;
+use super::ResumableSystem;
+use super::ScopedDispatcher;
+use super::SeqLock;
+use super::StreamingSystem;
+use super::StuckResource;
+use super::SystemId;
+use super::SystemSet;
+use super::SystemStream;
+use super::Transaction;
+use super::WeakDispatcher;
+use super::WorldView;
+#[cfg(feature = "metrics")]
+use crate::access_counts::AccessCounts;
+use crate::acquire_resources::AcquiredResourcesLockGuards;
+use crate::acquisition_order::LockFailureCounts;
+#[cfg(feature = "audit")]
+use crate::audit::AuditSinkFn;
+use crate::coalesce::PendingKeys;
+use crate::contention::ContentionTracker;
+use crate::dispatch_gate::DispatchGate;
+use crate::dispatch_gate::DispatchGateGuard;
+use crate::dispatch_lock_histogram::DispatchLockWaits;
+use crate::double_buffer::back_buffer_resource_id;
+use crate::double_buffer::front_buffer_resource_id;
+use crate::double_buffer::insert_buffers;
+use crate::double_buffer::DoubleBufferedResource;
+use crate::double_buffer::FrontBuffer;
+use crate::drop_order::InsertedResources;
+use crate::expedite::ExpediteQueue;
+#[cfg(feature = "fault-injection")]
+use crate::fault_injection::FaultInjector;
+use crate::frame_counter::FrameCounter;
+use crate::frame_history::write_folded;
+use crate::frame_history::FrameHistory;
+use crate::health::LockHolders;
+use crate::in_flight::InFlightTasks;
+use crate::in_flight::WaitForInFlight;
+use crate::keyed_resource::keyed_resource_id;
+use crate::lazy_resource::LazyLocker;
+use crate::log_config::TransitionLogger;
+#[cfg(feature = "metrics")]
+use crate::phase::with_phase;
+#[cfg(feature = "metrics")]
+use crate::phase::PhaseMetricsTracker;
+use crate::queued_bytes::QueuedBytes;
+use crate::replicate::ReplicatedResource;
+use crate::replicate::Replication;
+use crate::resource_policy::ResourcePolicyState;
+use crate::resource_waiters::ResourceWaiters;
+use crate::resumable_system::create_resumable_future;
+use crate::runtime::DefaultRuntime;
+use crate::runtime::Runtime;
+use crate::schedule_explanation::conflicting_resources;
+use crate::schedule_explanation::largest_independent_set;
+use crate::seed::system_seed;
+use crate::snapshot::SnapshotSources;
+use crate::write_versions::WriteVersions;
+
+type MaintainFn = dyn Fn(&mut shred::World) + Send + Sync;
+type TaskIdSourceFn = dyn Fn() -> u64 + Send + Sync;
+
+// The body of a system whose resources are only known at runtime, see
+// Dispatcher::create_dynamic_future
+pub type DynamicSystemFn = dyn FnMut(&shred::World) + Send;
+
 // This allows the user to add all the resources that will be used during execution
-pub struct DispatcherBuilder {
+pub struct DispatcherBuilder<L: AsyncResourceLock = DefaultResourceLock> {
     world: shred::World,
-    resource_locks: HashMap<ResourceId, tokio::sync::lock::Lock<()>>,
+    resource_locks: HashMap<ResourceId, L>,
+    resource_names: HashMap<ResourceId, &'static str>,
+    resource_policies: HashMap<ResourceId, ResourcePolicyState>,
+    resource_timeouts: HashMap<ResourceId, std::time::Duration>,
+    // Resources that are read without locks, SeqLocks and the fronts of double buffered resources
+    seqlock_resources: HashSet<ResourceId>,
+    double_buffered: Vec<DoubleBufferedResource>,
+    inserted_resources: InsertedResources,
+    drop_order: Vec<ResourceId>,
+    recorder: Option<Arc<AcquisitionRecorder>>,
+    replay: Option<AcquisitionReplay>,
+    frame_history_capacity: Option<usize>,
+    track_dispatch_lock_waits: bool,
+    shutdown_policy: ShutdownPolicy,
+    contended_shutdown_policy: ContendedShutdownPolicy,
+    spin_retries: usize,
+    acquisition_order: AcquisitionOrder,
+    maintain: Option<Box<MaintainFn>>,
+    track_contention: bool,
+    queued_bytes_budget: Option<usize>,
+    task_id_source: Option<Box<TaskIdSourceFn>>,
+    shared_read_dispatch: bool,
+    track_lock_holders: bool,
+    track_write_versions: bool,
+    seed: u64,
+    log_config: Option<LogConfig>,
+    replicated: Vec<ReplicatedResource>,
+    on_acquire: Option<fn(&ResourceId)>,
+    on_release: Option<fn(&ResourceId)>,
+    #[cfg(feature = "fault-injection")]
+    fault_injection_seed: Option<u64>,
+    #[cfg(feature = "audit")]
+    audit_sink: Option<Arc<AuditSinkFn>>,
+}
+
+impl Default for DispatcherBuilder {
+    fn default() -> Self {
+        DispatcherBuilder::new()
+    }
 }
 
 impl DispatcherBuilder {
-    // Create an empty dispatcher builder
+    // Create an empty dispatcher builder that uses the default lock
     pub fn new() -> Self {
+        DispatcherBuilder::with_lock_type()
+    }
+}
+
+impl<L: AsyncResourceLock> DispatcherBuilder<L> {
+    // Create an empty dispatcher builder that uses a custom lock implementation. Use as
+    // DispatcherBuilder::<MyLock>::with_lock_type()
+    pub fn with_lock_type() -> Self {
         DispatcherBuilder {
             world: shred::World::empty(),
             resource_locks: HashMap::new(),
+            resource_names: HashMap::new(),
+            resource_policies: HashMap::new(),
+            resource_timeouts: HashMap::new(),
+            seqlock_resources: HashSet::new(),
+            double_buffered: vec![],
+            inserted_resources: InsertedResources::new(),
+            drop_order: vec![],
+            recorder: None,
+            replay: None,
+            frame_history_capacity: None,
+            track_dispatch_lock_waits: false,
+            shutdown_policy: ShutdownPolicy::default(),
+            contended_shutdown_policy: ContendedShutdownPolicy::default(),
+            spin_retries: 0,
+            acquisition_order: AcquisitionOrder::default(),
+            maintain: None,
+            track_contention: false,
+            queued_bytes_budget: None,
+            task_id_source: None,
+            shared_read_dispatch: false,
+            track_lock_holders: false,
+            track_write_versions: false,
+            seed: 0,
+            log_config: None,
+            replicated: vec![],
+            on_acquire: None,
+            on_release: None,
+            #[cfg(feature = "fault-injection")]
+            fault_injection_seed: None,
+            #[cfg(feature = "audit")]
+            audit_sink: None,
         }
     }
 
     // Insert a resource that will be available once the dispatcher is running. This will create
-    // locks for each resource to be used during dispatch
-    pub fn insert<R>(mut self, r: R) -> Self
+    // locks for each resource to be used during dispatch. Panics if the resource was already
+    // inserted, use try_insert to handle that case
+    pub fn insert<R>(self, r: R) -> Self
+    where
+        R: shred::Resource,
+    {
+        match self.try_insert(r) {
+            Ok(builder) => builder,
+            Err(duplicate) => panic!(
+                "Resource {} was inserted more than once",
+                duplicate.resource_name
+            ),
+        }
+    }
+
+    // Same as insert, but returns an error rather than panicking if the resource was already
+    // inserted. The builder is consumed either way
+    pub fn try_insert<R>(mut self, r: R) -> Result<Self, DuplicateResource>
     where
         R: shred::Resource,
     {
         let resource_id = ResourceId::new::<R>();
+        self.check_not_inserted(&resource_id, std::any::type_name::<R>())?;
+
         // We could possibly do this just-in-time since we global lock to dispatch anyways, but
         // it would require wrapping in an RwLock so that we can get a mut ref
-        self.resource_locks
-            .insert(resource_id.clone(), tokio::sync::lock::Lock::new(()));
+        self.resource_locks.insert(resource_id.clone(), L::new());
+        self.resource_names
+            .insert(resource_id.clone(), std::any::type_name::<R>());
+        self.inserted_resources.push::<R>(resource_id.clone());
+
+        self.world.insert_by_id(resource_id, r);
+        Ok(self)
+    }
+
+    // Same as insert, but first inserts any of R's dependencies (see DependsOn) that haven't been
+    // inserted yet, using their Default values. Panics if the dependencies are circular
+    pub fn insert_with_dependencies<R>(self, r: R) -> Self
+    where
+        R: shred::Resource + DependsOn,
+    {
+        let resource_id = ResourceId::new::<R>();
+        let mut stack = vec![(resource_id, std::any::type_name::<R>())];
+        let builder = R::Deps::insert_defaults(self, &mut stack);
+        builder.insert(r)
+    }
+
+    // Insert every resource in the bundle. Panics if any of them was already inserted, same as
+    // insert
+    pub fn insert_bundle<B>(self, bundle: B) -> Self
+    where
+        B: ResourceBundle,
+    {
+        bundle.insert_into(self)
+    }
+
+    // Same as insert, but also sets the policy used to decide who gets the resource next when it's
+    // contended
+    pub fn insert_with_policy<R>(mut self, r: R, policy: ResourceLockPolicy) -> Self
+    where
+        R: shred::Resource,
+    {
+        self = self.insert(r);
+
+        // Fifo is the lock's own behavior, so there's no need to track anything for it
+        let resource_id = ResourceId::new::<R>();
+        if policy == ResourceLockPolicy::Fifo {
+            self.resource_policies.remove(&resource_id);
+        } else {
+            self.resource_policies
+                .insert(resource_id, ResourcePolicyState::new(policy));
+        }
+
+        self
+    }
+
+    // Same as insert, but an acquisition that waits longer than the timeout for this resource fails
+    // instead of waiting forever. The error is logged and the acquisition's status becomes
+    // AcquireStatus::TimedOut. This is meant for resources that should only ever be held briefly,
+    // where a long wait means something is wrong
+    pub fn insert_with_timeout<R>(mut self, r: R, timeout: std::time::Duration) -> Self
+    where
+        R: shred::Resource,
+    {
+        self = self.insert(r);
+        self.resource_timeouts
+            .insert(ResourceId::new::<R>(), timeout);
+        self
+    }
+
+    // Insert one of several instances of the same resource type, told apart by key. Systems access
+    // it with KeyedRead/KeyedWrite. Each instance has its own lock, so systems touching different
+    // keys can run at the same time (see Dispatcher::create_fanout_future)
+    pub fn insert_keyed<R>(mut self, key: u64, r: R) -> Self
+    where
+        R: shred::Resource,
+    {
+        let resource_id = keyed_resource_id::<R>(key);
+        if let Err(duplicate) = self.check_not_inserted(&resource_id, std::any::type_name::<R>()) {
+            panic!(
+                "Resource {} with key {} was inserted more than once",
+                duplicate.resource_name, key
+            );
+        }
+
+        self.resource_locks.insert(resource_id.clone(), L::new());
+        self.resource_names
+            .insert(resource_id.clone(), std::any::type_name::<R>());
+        self.inserted_resources.push::<R>(resource_id.clone());
 
         self.world.insert_by_id(resource_id, r);
         self
     }
 
+    // Runs each system's setup, the way shred's own DispatcherBuilder does, so that resources that
+    // systems fetch with Read<T> and Write<T> are defaulted (and component storages are
+    // registered) up front instead of the first time each system runs. Every resource the systems
+    // declare that exists afterwards gets a lock. Resources that were already inserted are left
+    // as they are
+    pub fn setup_systems<S: SystemSet>(mut self, systems: S) -> Self {
+        let mut resources = vec![];
+        systems.setup(&mut self.world, &mut resources);
+        for resource_id in resources {
+            if self.world.has_value_raw(resource_id.clone())
+                && !self.seqlock_resources.contains(&resource_id)
+            {
+                self.resource_locks
+                    .entry(resource_id)
+                    .or_insert_with(L::new);
+            }
+        }
+
+        self
+    }
+
+    // Register a lock that has no value in the world, to serialize access to data the dispatcher
+    // doesn't own (i.e. objects in a separate arena). It's acquired like any other resource (i.e.
+    // listed in a RequiredResources or passed to Dispatcher::with_resources), and whoever holds it
+    // accesses the data however they manage it. The world won't contain anything for this id, so a
+    // system must not try to fetch it. Panics if the id was already inserted or registered
+    pub fn register_lock(mut self, resource_id: ResourceId) -> Self {
+        if self.is_inserted(&resource_id) {
+            panic!("Lock {:?} was registered more than once", resource_id);
+        }
+
+        self.resource_locks.insert(resource_id, L::new());
+        self
+    }
+
+    // Insert a resource that one system writes and many read, without the writer and the readers
+    // ever waiting on each other. Systems write it through WriteBack<R>, which is locked like any
+    // other resource, and read it through ReadFront<R>, which has no lock and sees the resource as
+    // it was at the end of the last frame. At the end of every frame (after maintenance) the back is
+    // cloned to become the new front, so R should be cheap enough to clone once a frame. Outside of
+    // the game loop, use Dispatcher::create_publish_future
+    pub fn insert_double_buffered<R>(mut self, r: R) -> Self
+    where
+        R: Clone + shred::Resource,
+    {
+        let front_resource_id = front_buffer_resource_id::<R>();
+        let back_resource_id = back_buffer_resource_id::<R>();
+        for resource_id in &[&front_resource_id, &back_resource_id] {
+            if let Err(duplicate) = self.check_not_inserted(resource_id, std::any::type_name::<R>())
+            {
+                panic!(
+                    "Resource {} was inserted more than once",
+                    duplicate.resource_name
+                );
+            }
+        }
+
+        self.resource_locks
+            .insert(back_resource_id.clone(), L::new());
+        self.resource_names.insert(
+            back_resource_id.clone(),
+            std::any::type_name::<BackBuffer<R>>(),
+        );
+        self.resource_names.insert(
+            front_resource_id.clone(),
+            std::any::type_name::<ReadFront<R>>(),
+        );
+        self.seqlock_resources.insert(front_resource_id.clone());
+        self.inserted_resources
+            .push::<FrontBuffer<R>>(front_resource_id);
+        self.inserted_resources
+            .push::<BackBuffer<R>>(back_resource_id);
+        self.double_buffered
+            .push(DoubleBufferedResource::new::<R>());
+
+        insert_buffers(&mut self.world, r);
+        self
+    }
+
+    // Same as insert, but the resource is also copied to standbys by Dispatcher::replicate_to
+    pub fn insert_replicated<R>(mut self, r: R) -> Self
+    where
+        R: Replicate,
+    {
+        self.replicated.push(ReplicatedResource::new::<R>());
+        self.insert(r)
+    }
+
+    // Insert a small Copy resource that systems can read without taking a lock. It's stored as a
+    // SeqLock<R>, so systems must declare it as shred::ReadExpect<SeqLock<R>> and write it with
+    // SeqLock::set. Declaring it as a Write will panic when the system is queued.
+    pub fn insert_seqlock<R>(mut self, r: R) -> Self
+    where
+        R: Copy + Send + 'static,
+    {
+        let resource_id = ResourceId::new::<SeqLock<R>>();
+        if let Err(duplicate) =
+            self.check_not_inserted(&resource_id, std::any::type_name::<SeqLock<R>>())
+        {
+            panic!(
+                "Resource {} was inserted more than once",
+                duplicate.resource_name
+            );
+        }
+
+        self.resource_names
+            .insert(resource_id.clone(), std::any::type_name::<SeqLock<R>>());
+        self.seqlock_resources.insert(resource_id.clone());
+        self.inserted_resources
+            .push::<SeqLock<R>>(resource_id.clone());
+
+        self.world.insert_by_id(resource_id, SeqLock::new(r));
+        self
+    }
+
+    // Sets the order resources are dropped in by drop_world, for resources whose Drop needs another
+    // resource to still be around. The given resources are dropped first, in the order given, then
+    // every other resource inserted with the builder in reverse of the order it was inserted (so by
+    // default, something inserted after a resource it depends on is dropped before it). Panics in
+    // build if a resource wasn't inserted with the builder. The world is only dropped in this
+    // order when it's passed to drop_world
+    pub fn with_drop_order(mut self, drop_order: &[ResourceId]) -> Self {
+        self.drop_order = drop_order.to_vec();
+        self
+    }
+
+    // Replacing an inserted resource would also replace its lock, and anything already holding the
+    // old lock would no longer be excluding anyone
+    fn check_not_inserted(
+        &self,
+        resource_id: &ResourceId,
+        resource_name: &'static str,
+    ) -> Result<(), DuplicateResource> {
+        if self.is_inserted(resource_id) {
+            return Err(DuplicateResource {
+                resource_id: resource_id.clone(),
+                resource_name,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn is_inserted(&self, resource_id: &ResourceId) -> bool {
+        self.resource_locks.contains_key(resource_id)
+            || self.seqlock_resources.contains(resource_id)
+    }
+
+    pub(super) fn world_mut(&mut self) -> &mut shred::World {
+        &mut self.world
+    }
+
+    // Returns true if the resource will be available once the dispatcher is running. Resources
+    // that were put in the world directly (i.e. a default inserted by a system's setup) get a lock
+    // here
+    pub(super) fn register_existing_resource(&mut self, resource_id: &ResourceId) -> bool {
+        if self.resource_locks.contains_key(resource_id)
+            || self.seqlock_resources.contains(resource_id)
+        {
+            return true;
+        }
+
+        if self.world.has_value_raw(resource_id.clone()) {
+            self.resource_locks.insert(resource_id.clone(), L::new());
+            return true;
+        }
+
+        false
+    }
+
+    // Record every resource acquire/release into the given recorder
+    pub fn with_recorder(mut self, recorder: Arc<AcquisitionRecorder>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    // Force resources to be granted in the same order as a previous recording
+    pub fn with_replay(mut self, replay: AcquisitionReplay) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    // Keep the timings of the last frame_count frames, readable with Dispatcher::frame_history
+    pub fn with_frame_history(mut self, frame_count: usize) -> Self {
+        self.frame_history_capacity = Some(frame_count);
+        self
+    }
+
+    // Record how long each acquisition waits for the dispatch lock, readable with
+    // Dispatcher::dispatch_lock_wait_histogram. This only counts the dispatch lock, not the time
+    // spent waiting for resources
+    pub fn with_dispatch_lock_wait_histogram(mut self) -> Self {
+        self.track_dispatch_lock_waits = true;
+        self
+    }
+
+    // Choose what happens if something still holds the dispatcher when the game loop ends
+    pub fn with_shutdown_policy(mut self, shutdown_policy: ShutdownPolicy) -> Self {
+        self.shutdown_policy = shutdown_policy;
+        self
+    }
+
+    // Choose what happens to acquisitions that are waiting on a resource when end_game_loop is
+    // called. The default is ContendedShutdownPolicy::WaitForHolder
+    pub fn with_contended_shutdown_policy(
+        mut self,
+        contended_shutdown_policy: ContendedShutdownPolicy,
+    ) -> Self {
+        self.contended_shutdown_policy = contended_shutdown_policy;
+        self
+    }
+
+    // When a resource lock isn't available, try it again up to this many times (with a spin hint in
+    // between) before giving up and waiting to be woken. For locks that are only held briefly this
+    // can be faster than parking, at the cost of some CPU. The default is 0, never spin
+    pub fn with_spin_retries(mut self, spin_retries: usize) -> Self {
+        self.spin_retries = spin_retries;
+        self
+    }
+
+    // Sets the order an acquisition tries to take its locks in. The default is
+    // AcquisitionOrder::Sorted
+    pub fn with_acquisition_order(mut self, acquisition_order: AcquisitionOrder) -> Self {
+        self.acquisition_order = acquisition_order;
+        self
+    }
+
+    // Call f with exclusive access to the world at the end of every frame, i.e. to apply deferred
+    // changes with specs' World::maintain (|world| world.maintain()). Also used by
+    // Dispatcher::create_maintain_future
+    pub fn with_maintain<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&mut shred::World) + Send + Sync + 'static,
+    {
+        self.maintain = Some(Box::new(f));
+        self
+    }
+
+    // Keep track of which resources were waited on during each frame, and which systems were
+    // waiting. Readable at the end of a frame with Dispatcher::last_frame_contention. Along with
+    // with_lock_holder_tracking, this also records which systems were holding the resources (see
+    // Dispatcher::contention_pairs)
+    pub fn with_contention_report(mut self) -> Self {
+        self.track_contention = true;
+        self
+    }
+
+    // Limit the estimated memory used by futures from create_future (or create_future_with_result)
+    // that are still waiting for their resources. Once the limit is reached, new futures fail
+    // immediately instead of being queued. This is a defense against floods of work, i.e. from
+    // untrusted clients. The estimate only counts the size of the future and the system, not
+    // anything they point to
+    pub fn with_queued_bytes_budget(mut self, bytes: usize) -> Self {
+        self.queued_bytes_budget = Some(bytes);
+        self
+    }
+
+    // Use f to allocate task ids instead of counting up from 0, i.e. so that they match span ids
+    // from a tracing system. Every call must return a different id, since ids are used to tell
+    // acquisitions apart. Dispatcher::reset can't restart a custom source, and an
+    // AcquisitionReplay only lines up if f hands out the same ids as the recorded run
+    pub fn with_task_id_source<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        self.task_id_source = Some(Box::new(f));
+        self
+    }
+
+    // Remember which task holds each resource's lock and since when, so that
+    // Dispatcher::healthcheck can tell which locks have been held for too long and by whom
+    pub fn with_lock_holder_tracking(mut self) -> Self {
+        self.track_lock_holders = true;
+        self
+    }
+
+    // Count how many times each resource's write lock is released, so that a task that lets go of
+    // its resources and acquires them again can tell whether anyone else wrote them in between.
+    // See Dispatcher::reacquire_guard
+    pub fn with_write_versions(mut self) -> Self {
+        self.track_write_versions = true;
+        self
+    }
+
+    // The base seed for Dispatcher::seed_for. Without this it's 0
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    // Log the acquisition state transitions that log_config turns on, at the level it gives them.
+    // See LogConfig
+    pub fn with_log_config(mut self, log_config: LogConfig) -> Self {
+        self.log_config = Some(log_config);
+        self
+    }
+
+    // Call f with each resource whose lock an acquisition takes, once it has all of them. This is
+    // meant for bumping counters in an external metrics system, so it's a plain function that's
+    // called inline and must be cheap. Seqlock resources have no lock and aren't reported. Locks
+    // taken for PlannedSystem extras or by a CrossDispatcher aren't reported either
+    pub fn on_acquire(mut self, f: fn(&ResourceId)) -> Self {
+        self.on_acquire = Some(f);
+        self
+    }
+
+    // Call f with each resource reported to on_acquire when its lock is released
+    pub fn on_release(mut self, f: fn(&ResourceId)) -> Self {
+        self.on_release = Some(f);
+        self
+    }
+
+    // Let acquisitions that only read try to take their locks at the same time as each other,
+    // rather than one at a time like everything else. Acquisitions that write (and PlannedSystem
+    // extras and CrossDispatcher acquisitions) still try alone, with no readers trying alongside
+    // them. This only helps if the read-only acquisitions mostly need different resources, since
+    // each resource's lock is exclusive even for reads.
+    //
+    // This can't deadlock for the same reason the exclusive dispatch lock can't: nothing ever waits
+    // while holding a resource lock. Taking locks is a single attempt that either gets everything
+    // or releases everything before waiting on the lock that failed, and nobody waits while inside
+    // the gate. So nobody that holds a lock is ever waiting on anything, and there can't be a
+    // cycle. What's lost is the guarantee that a failed lock is held by a task that already has
+    // everything it needs. Two readers trying at once can make each other fail, and both wait for
+    // a lock that is released as soon as the other gives up. They then try again, so this costs
+    // an extra attempt rather than progress.
+    pub fn with_shared_read_dispatch(mut self) -> Self {
+        self.shared_read_dispatch = true;
+        self
+    }
+
+    // For tests: sometimes make an acquisition that won the dispatch lock give it up and wait
+    // again, so that tasks are granted their resources in orders they normally wouldn't be. This
+    // shakes out systems that only work because of the order they happen to run in. Which grants
+    // are given up is decided by a generator seeded with seed, so on a single threaded runtime a
+    // failing seed reproduces the same interleaving. Multithreaded runtimes add their own
+    // nondeterminism. Requires the fault-injection feature
+    #[cfg(feature = "fault-injection")]
+    pub fn with_fault_injection(mut self, seed: u64) -> Self {
+        self.fault_injection_seed = Some(seed);
+        self
+    }
+
+    // Call f with every acquisition's resources and the system they were acquired for, i.e. to
+    // keep a trail of what untrusted scripted systems touched. Unlike on_acquire this is about
+    // accountability, so every acquisition is reported in full, including ones that only take
+    // seqlock resources. f is called inline while the locks are held, so a sink that does I/O
+    // should hand the record off to another thread. Locks taken for PlannedSystem extras or by a
+    // CrossDispatcher aren't reported. Requires the audit feature
+    #[cfg(feature = "audit")]
+    pub fn with_audit_sink<F>(mut self, f: F) -> Self
+    where
+        F: Fn(AuditRecord) + Send + Sync + 'static,
+    {
+        self.audit_sink = Some(Arc::new(f));
+        self
+    }
+
     // Create the dispatcher
-    pub fn build(self) -> Dispatcher {
-        return Dispatcher {
-            next_task_id: std::sync::atomic::AtomicUsize::new(0),
-            world: Arc::new(self.world),
-            dispatch_lock: tokio::sync::lock::Lock::new(()),
+    pub fn build(self) -> Dispatcher<L> {
+        let lock_failures = if self.acquisition_order == AcquisitionOrder::MostContendedFirst {
+            Some(LockFailureCounts::new(self.resource_locks.keys()))
+        } else {
+            None
+        };
+
+        let dispatch_lock = L::new();
+        let lazy_resource_locks = Arc::new(Mutex::new(HashMap::new()));
+        let mut world = self.world;
+        world.insert(self.inserted_resources.into_drop_order(&self.drop_order));
+        world.insert(LazyLocker::new(
+            dispatch_lock.clone(),
+            self.resource_locks.clone(),
+            lazy_resource_locks.clone(),
+            self.seqlock_resources.clone(),
+        ));
+
+        Dispatcher {
+            next_task_id: std::sync::atomic::AtomicU64::new(0),
+            task_id_source: self.task_id_source,
+            world: Arc::new(RwLock::new(world)),
+            dispatch_lock,
             resource_locks: self.resource_locks,
+            lazy_resource_locks,
+            snapshot_resources: Arc::new(RwLock::new(HashMap::new())),
+            resource_names: self.resource_names,
+            resource_policies: self.resource_policies,
+            resource_timeouts: self.resource_timeouts,
+            seqlock_resources: self.seqlock_resources,
+            double_buffered: Arc::new(self.double_buffered),
             should_terminate: std::sync::atomic::AtomicBool::new(false),
-        };
+            loop_running: std::sync::atomic::AtomicBool::new(false),
+            in_flight: Arc::new(InFlightTasks::new()),
+            expedite_queue: Arc::new(ExpediteQueue::new()),
+            recorder: self.recorder,
+            replay: self.replay,
+            frame_counter: Arc::new(FrameCounter::new()),
+            frame_history: self.frame_history_capacity.map(FrameHistory::new),
+            dispatch_lock_waits: if self.track_dispatch_lock_waits {
+                Some(DispatchLockWaits::new())
+            } else {
+                None
+            },
+            shutdown_policy: self.shutdown_policy,
+            contended_shutdown_policy: self.contended_shutdown_policy,
+            resource_waiters: ResourceWaiters::new(),
+            spin_retries: self.spin_retries,
+            lock_failures,
+            acquisition_order: self.acquisition_order,
+            maintain: self.maintain,
+            contention: if self.track_contention {
+                Some(ContentionTracker::new())
+            } else {
+                None
+            },
+            queued_bytes: Arc::new(QueuedBytes::new(self.queued_bytes_budget)),
+            pending_keys: Arc::new(PendingKeys::new()),
+            #[cfg(debug_assertions)]
+            checked_systems: Mutex::new(HashSet::new()),
+            on_acquire: self.on_acquire,
+            on_release: self.on_release,
+            lock_holders: if self.track_lock_holders {
+                Some(Arc::new(LockHolders::new()))
+            } else {
+                None
+            },
+            seed: self.seed,
+            replication: Arc::new(Replication::new(self.replicated)),
+            transition_logger: self
+                .log_config
+                .map(|log_config| Arc::new(TransitionLogger::new(log_config))),
+            write_versions: if self.track_write_versions {
+                Some(Arc::new(WriteVersions::new()))
+            } else {
+                None
+            },
+            prefetch_slot: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            dispatch_gate: if self.shared_read_dispatch {
+                Some(Arc::new(DispatchGate::new()))
+            } else {
+                None
+            },
+            #[cfg(feature = "fault-injection")]
+            fault_injector: self.fault_injection_seed.map(FaultInjector::new),
+            #[cfg(feature = "audit")]
+            audit_sink: self.audit_sink,
+            #[cfg(feature = "metrics")]
+            access_counts: Arc::new(AccessCounts::new()),
+            #[cfg(feature = "metrics")]
+            phase_metrics: Arc::new(PhaseMetricsTracker::new()),
+            parent: None,
+        }
     }
 }
 
+// Returned by DispatcherBuilder::try_insert when the resource was already inserted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateResource {
+    pub resource_id: ResourceId,
+    pub resource_name: &'static str,
+}
+
+// What enter_game_loop does if the dispatcher is still referenced (i.e. by a detached task) once
+// the loop has ended and it needs to take the world back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShutdownPolicy {
+    // Panic
+    #[default]
+    Panic,
+
+    // Log an error, move the world out and leak the dispatcher. Anything that still holds the
+    // dispatcher will see an empty world
+    LogAndLeak,
+
+    // Wait up to the given duration for the other references to be dropped, and then panic
+    WaitWithTimeout(std::time::Duration),
+}
+
+// What happens to acquisitions that are waiting on a resource another task holds when
+// end_game_loop is called. This is separate from ShutdownPolicy, which only decides what happens
+// once the loop has ended. Acquisitions that are waiting for the dispatch lock aren't affected,
+// they're never held up for long
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContendedShutdownPolicy {
+    // Wake every waiter right away and fail it (see DispatchError::Terminated), so a holder that
+    // never finishes can't keep the loop from ending
+    FailWaiters,
+
+    // Let waiters keep waiting, and run once the holder is done. No queued work is dropped, but
+    // the loop doesn't end until every holder has finished
+    #[default]
+    WaitForHolder,
+
+    // Same as FailWaiters, but log a warning for each waiter naming the system and the resource it
+    // was waiting for, so the work that was dropped can be traced
+    LogAndAbandon,
+}
+
+// See Dispatcher::status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopStatus {
+    // enter_game_loop hasn't been called, or has returned
+    Stopped,
+
+    // Frames are running
+    Running,
+
+    // end_game_loop was called. The current frame will finish, and then the loop waits for tasks
+    // spawned through the dispatcher before it stops
+    Terminating,
+}
+
+// The state of a resource's lock at the time it was checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Unlocked,
+    Locked,
+}
+
 // Create using DispatcherBuilder. This keeps track of which tasks are wanting to read/write to
 // the shred world and provides locks to them in a way that does not deadlock. This is done
 // by only allowing a single task to try to acquire locks at the same time. If a task fails to
 // acquire a task, it drops any locks it has already acquired and awaits the lock it couldn't get.
 // This way it's not blocking any other tasks that are able to proceed, and it's not spinning while
 // it's waiting.
-pub struct Dispatcher {
-    next_task_id: std::sync::atomic::AtomicUsize,
-    world: Arc<shred::World>,
-    dispatch_lock: tokio::sync::lock::Lock<()>,
+pub struct Dispatcher<L: AsyncResourceLock = DefaultResourceLock> {
+    next_task_id: std::sync::atomic::AtomicU64,
+    task_id_source: Option<Box<TaskIdSourceFn>>,
+    // Systems only ever take the read lock, since the resource locks are what make running them
+    // safe. The write lock is only taken to reset the world, which needs exclusive access
+    world: Arc<RwLock<shred::World>>,
+    dispatch_lock: L,
     //TODO: Change this to a RwLock, but waiting until I have something more "real" to test with
-    resource_locks: HashMap<ResourceId, tokio::sync::lock::Lock<()>>,
+    resource_locks: HashMap<ResourceId, L>,
+    // Locks for resources that weren't inserted with the DispatcherBuilder but were created by a
+    // system's setup (i.e. shred's Read<T> inserting T::default())
+    lazy_resource_locks: Arc<Mutex<HashMap<ResourceId, L>>>,
+    // Ids declared by Snapshot<R>. Each has an entry in lazy_resource_locks that shares R's lock
+    snapshot_resources: Arc<RwLock<HashMap<ResourceId, ResourceId>>>,
+    resource_names: HashMap<ResourceId, &'static str>,
+    resource_policies: HashMap<ResourceId, ResourcePolicyState>,
+    resource_timeouts: HashMap<ResourceId, std::time::Duration>,
+    // Resources that are read without locks (see DispatcherBuilder::insert_seqlock and
+    // insert_double_buffered)
+    seqlock_resources: HashSet<ResourceId>,
+    double_buffered: Arc<Vec<DoubleBufferedResource>>,
     should_terminate: std::sync::atomic::AtomicBool,
+    // Set while enter_game_loop (or a scoped dispatcher's loop future) is running
+    loop_running: std::sync::atomic::AtomicBool,
+    in_flight: Arc<InFlightTasks>,
+    expedite_queue: Arc<ExpediteQueue>,
+    recorder: Option<Arc<AcquisitionRecorder>>,
+    replay: Option<AcquisitionReplay>,
+    frame_counter: Arc<FrameCounter>,
+    frame_history: Option<FrameHistory>,
+    dispatch_lock_waits: Option<DispatchLockWaits>,
+    shutdown_policy: ShutdownPolicy,
+    contended_shutdown_policy: ContendedShutdownPolicy,
+    resource_waiters: ResourceWaiters,
+    spin_retries: usize,
+    acquisition_order: AcquisitionOrder,
+    lock_failures: Option<LockFailureCounts>,
+    maintain: Option<Box<MaintainFn>>,
+    contention: Option<ContentionTracker>,
+    queued_bytes: Arc<QueuedBytes>,
+    // See create_future_coalesced
+    pending_keys: Arc<PendingKeys>,
+    // System types whose fetches were already checked, see assert_fetched_resources_declared
+    #[cfg(debug_assertions)]
+    checked_systems: Mutex<HashSet<std::any::TypeId>>,
+    on_acquire: Option<fn(&ResourceId)>,
+    on_release: Option<fn(&ResourceId)>,
+    lock_holders: Option<Arc<LockHolders>>,
+    write_versions: Option<Arc<WriteVersions>>,
+    seed: u64,
+    replication: Arc<Replication>,
+    transition_logger: Option<Arc<TransitionLogger>>,
+    // Only with DispatcherBuilder::with_shared_read_dispatch
+    dispatch_gate: Option<Arc<DispatchGate>>,
+    // Set while an ExecutePrefetched is prefetching, see ExecutePrefetched
+    prefetch_slot: Arc<std::sync::atomic::AtomicBool>,
+    #[cfg(feature = "fault-injection")]
+    fault_injector: Option<FaultInjector>,
+    #[cfg(feature = "audit")]
+    audit_sink: Option<Arc<AuditSinkFn>>,
+    #[cfg(feature = "metrics")]
+    access_counts: Arc<AccessCounts>,
+    #[cfg(feature = "metrics")]
+    phase_metrics: Arc<PhaseMetricsTracker>,
+    // Set for a scoped dispatcher. Keeping the parent alive means its loop can't end while a
+    // child still shares its world (see ShutdownPolicy)
+    parent: Option<Arc<Dispatcher<L>>>,
 }
 
-impl Dispatcher {
-    pub(super) fn dispatch_lock(&self) -> &tokio::sync::lock::Lock<()> {
+impl<L: AsyncResourceLock> Dispatcher<L> {
+    pub(super) fn set_loop_running(&self, loop_running: bool) {
+        self.loop_running.store(loop_running, Ordering::Release);
+    }
+
+    pub(super) fn dispatch_lock(&self) -> &L {
         &self.dispatch_lock
     }
 
-    pub(super) fn resource_locks(&self) -> &HashMap<ResourceId, tokio::sync::lock::Lock<()>> {
-        &self.resource_locks
+    pub(super) fn lock_holders(&self) -> Option<&Arc<LockHolders>> {
+        self.lock_holders.as_ref()
     }
 
-    pub(super) fn take_task_id(&self) -> usize {
-        // Relaxed because we only care that every call of this function returns a different value,
-        // we don't care about the ordering
-        self.next_task_id
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    pub(super) fn write_versions(&self) -> Option<&Arc<WriteVersions>> {
+        self.write_versions.as_ref()
     }
 
-    pub fn end_game_loop(&self) {
-        self.should_terminate.swap(true, Ordering::Release);
+    pub(super) fn transition_logger(&self) -> Option<&Arc<TransitionLogger>> {
+        self.transition_logger.as_ref()
     }
 
-    // Call this to kick off processing.
-    pub fn enter_game_loop<F, FutureT>(self, f: F) -> shred::World
-    where
-        F: Fn(Arc<Dispatcher>) -> FutureT + Send + Sync + Copy + 'static,
-        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
-    {
-        // Put the dispatcher in an Arc so it can be shared among tasks
-        let dispatcher = Arc::new(self);
+    pub(super) fn on_acquire(&self) -> Option<fn(&ResourceId)> {
+        self.on_acquire
+    }
 
-        let dispatcher_clone = dispatcher.clone();
+    pub(super) fn on_release(&self) -> Option<fn(&ResourceId)> {
+        self.on_release
+    }
 
-        let loop_future = futures::future::loop_fn((), move |_| {
-            // This clone is so that we can pass it to the inner closure
-            let dispatcher_clone2 = dispatcher_clone.clone();
+    // True if read-only acquisitions skip the dispatch lock and only enter the gate
+    pub(super) fn has_shared_read_dispatch(&self) -> bool {
+        self.dispatch_gate.is_some()
+    }
 
-            // Get a future that represents this frame's work
-            (f.clone())(dispatcher_clone.clone()).map(move |_| {
-                return if dispatcher_clone2.should_terminate.load(Ordering::Acquire) {
-                    futures::future::Loop::Break(())
-                } else {
-                    futures::future::Loop::Continue(())
-                };
-            })
-        });
+    // Must be called while holding the dispatch lock unless shared is true. Ready(None) if there's
+    // no gate, in which case the dispatch lock is all that's needed
+    pub(super) fn poll_dispatch_gate(
+        &self,
+        shared: bool,
+    ) -> futures::Async<Option<DispatchGateGuard>> {
+        match &self.dispatch_gate {
+            Some(dispatch_gate) => DispatchGate::poll_enter(dispatch_gate, shared).map(Some),
+            None => futures::Async::Ready(None),
+        }
+    }
 
-        // Kick off the process
-        debug!("Calling tokio run");
-        tokio::run(loop_future);
+    pub(super) fn is_seqlock_resource(&self, resource_id: &ResourceId) -> bool {
+        !self.seqlock_resources.is_empty() && self.seqlock_resources.contains(resource_id)
+    }
 
-        // After execution ends, unwrap the dispatcher arc
-        let dispatcher = Arc::try_unwrap(dispatcher).unwrap_or_else(|_| {
-            unreachable!();
-        });
+    // Whether end_game_loop has been called
+    pub(super) fn should_terminate(&self) -> bool {
+        self.should_terminate.load(Ordering::Acquire)
+    }
 
-        // Then unwrap the world inside it
-        let world = Arc::try_unwrap(dispatcher.world).unwrap_or_else(|_| {
-            unreachable!();
-        });
+    pub(super) fn spin_retries(&self) -> usize {
+        self.spin_retries
+    }
 
-        // Return the world
-        world
+    pub(super) fn contended_shutdown_policy(&self) -> ContendedShutdownPolicy {
+        self.contended_shutdown_policy
     }
 
-    pub fn run_system<T>(&self, mut system: T) -> T
-    where
-        T: for<'b> shred::System<'b> + Send + 'static,
-    {
-        use shred::RunNow;
-        system.run_now(&self.world);
-        system
+    pub(super) fn resource_waiters(&self) -> &ResourceWaiters {
+        &self.resource_waiters
     }
 
-    // Queues up a system to run. This code will acquire the appropriate resources first, then
-    // run the given system
-    pub fn create_future_with_result<T>(
-        dispatcher: &Arc<Dispatcher>,
+    pub(super) fn lock_failures(&self) -> Option<&LockFailureCounts> {
+        self.lock_failures.as_ref()
+    }
+
+    // Returns the lock for the given resource, if the resource exists
+    pub(super) fn resource_lock(&self, resource_id: &ResourceId) -> Option<L> {
+        if let Some(lock) = self.resource_locks.get(resource_id) {
+            return Some(lock.clone());
+        }
+
+        self.lazy_resource_locks
+            .lock()
+            .unwrap()
+            .get(resource_id)
+            .cloned()
+    }
+
+    // If the system uses any resource that has no lock, returns a future that runs the system's
+    // setup so that shred can insert defaults for them (Read<T> inserts T::default(), ReadExpect
+    // inserts nothing) and creates locks for whatever exists afterwards, then resolves to the
+    // system. The world is taken by acquiring every resource, so this waits for any running systems
+    // to finish and must not be awaited from inside a system.
+    fn setup_missing_resources<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
         system: T,
-    ) -> Box<impl futures::Future<Item = T, Error = ()>>
+    ) -> impl futures::Future<Item = T, Error = ()>
     where
         T: for<'b> shred::System<'b> + Send + 'static,
     {
-        let dispatcher = dispatcher.clone();
-        let required_resources = super::RequiredResources::from_system(&system);
-        use futures::Future;
-        Box::new(
-            super::AcquireResources::<T>::new(dispatcher.clone(), required_resources).and_then(
-                move |_result| {
-                    let system = dispatcher.run_system(system);
-                    Ok(system)
-                },
-            ),
-        )
+        use shred::Accessor;
+
+        let missing: Vec<ResourceId> = {
+            let accessor = system.accessor();
+            accessor
+                .reads()
+                .into_iter()
+                .chain(accessor.writes())
+                .filter(|resource_id| {
+                    !dispatcher.is_seqlock_resource(resource_id)
+                        && dispatcher.resource_lock(resource_id).is_none()
+                })
+                .collect()
+        };
+
+        if missing.is_empty() {
+            return futures::future::Either::A(futures::future::ok(system));
+        }
+
+        let setup_dispatcher = dispatcher.clone();
+        futures::future::Either::B(Dispatcher::create_exclusive_world_future(
+            dispatcher,
+            move |world| {
+                let mut system = system;
+                setup_dispatcher.setup_resources(&mut system, world, missing);
+                system
+            },
+        ))
     }
 
-    // Queues up a system to run. This code will acquire the appropriate resources first, then
-    // run the given system
-    pub fn create_future<T>(
-        dispatcher: &Arc<Dispatcher>,
-        system: T,
-    ) -> Box<impl futures::Future<Item = (), Error = ()>>
+    fn setup_resources<T>(&self, system: &mut T, world: &mut shred::World, missing: Vec<ResourceId>)
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        // Another task may have set these up while we were waiting for the world
+        let mut lazy_resource_locks = self.lazy_resource_locks.lock().unwrap();
+        if missing
+            .iter()
+            .all(|resource_id| lazy_resource_locks.contains_key(resource_id))
+        {
+            return;
+        }
+
+        system.setup(world);
+        let snapshot_sources = world.try_fetch::<SnapshotSources>();
+        for resource_id in missing {
+            if world.has_value_raw(resource_id.clone()) {
+                trace!("Created a lock for defaulted resource {:?}", resource_id);
+                lazy_resource_locks
+                    .entry(resource_id)
+                    .or_insert_with(L::new);
+                continue;
+            }
+
+            // A snapshot shares the lock of the resource it copies
+            let source_id = snapshot_sources
+                .as_ref()
+                .and_then(|snapshot_sources| snapshot_sources.source(&resource_id));
+            let source_lock = source_id.and_then(|source_id| {
+                self.resource_locks
+                    .get(source_id)
+                    .or_else(|| lazy_resource_locks.get(source_id))
+                    .cloned()
+                    .map(|source_lock| (source_id.clone(), source_lock))
+            });
+
+            if let Some((source_id, source_lock)) = source_lock {
+                trace!("Registered snapshot resource {:?}", resource_id);
+                self.snapshot_resources
+                    .write()
+                    .unwrap()
+                    .insert(resource_id.clone(), source_id);
+                lazy_resource_locks.insert(resource_id, source_lock);
+            }
+        }
+    }
+
+    // Snapshot resources are read locked only while the system's data is fetched
+    pub(super) fn is_snapshot_resource(&self, resource_id: &ResourceId) -> bool {
+        let snapshot_resources = self.snapshot_resources.read().unwrap();
+        !snapshot_resources.is_empty() && snapshot_resources.contains_key(resource_id)
+    }
+
+    // The resource a snapshot resource copies, whose lock it shares
+    pub(super) fn snapshot_source(&self, resource_id: &ResourceId) -> Option<ResourceId> {
+        let snapshot_resources = self.snapshot_resources.read().unwrap();
+        if snapshot_resources.is_empty() {
+            return None;
+        }
+
+        snapshot_resources.get(resource_id).cloned()
+    }
+
+    // Returns the type name of a resource that was inserted with the DispatcherBuilder
+    pub fn resource_name(&self, resource_id: &ResourceId) -> Option<&'static str> {
+        self.resource_names.get(resource_id).cloned()
+    }
+
+    // Returns every registered resource's name and whether its lock is currently held, sorted by
+    // name. This is meant for debug consoles. The locks are probed while holding the dispatch lock
+    // (if it's available) so that no task is partway through acquiring while we look.
+    pub fn list_resources(&self) -> Vec<(String, LockState)> {
+        let _dispatch_guard = self.dispatch_lock.try_lock();
+
+        let lazy_resource_locks = self.lazy_resource_locks.lock().unwrap();
+        let mut resources: Vec<_> = self
+            .resource_locks
+            .iter()
+            .chain(lazy_resource_locks.iter())
+            .filter(|(resource_id, _)| !self.is_snapshot_resource(resource_id))
+            .map(|(resource_id, lock)| {
+                let name = self
+                    .resource_name(resource_id)
+                    .map(|name| name.to_string())
+                    .unwrap_or_else(|| format!("{:?}", resource_id));
+
+                let state = match lock.try_lock() {
+                    Some(_guard) => LockState::Unlocked,
+                    None => LockState::Locked,
+                };
+
+                (name, state)
+            })
+            .collect();
+
+        resources.sort_by(|a, b| a.0.cmp(&b.0));
+        resources
+    }
+
+    // Runs f while holding the dispatch lock (and the dispatch gate, if reads are dispatched
+    // shared), so that no acquisition can make progress until it returns. Meant for reconfiguring
+    // several things at once without any acquisition seeing them halfway. Resources that are
+    // already held stay held, so this is not the same as having every resource.
+    //
+    // f must not acquire resources or wait on anything that does (i.e. Dispatcher::acquire_blocking
+    // or waiting on a future from create_future). Every acquisition needs the dispatch lock, so it
+    // would never finish. f also stops every acquisition on the dispatcher (and its scoped
+    // dispatchers) for as long as it runs, so it must be fast
+    pub fn with_dispatch_lock<F, O>(&self, f: F) -> impl futures::Future<Item = O, Error = ()>
+    where
+        F: FnOnce() -> O,
+    {
+        let mut dispatch_lock = self.dispatch_lock.clone();
+        let dispatch_gate = self.dispatch_gate.clone();
+        let mut f = Some(f);
+        futures::future::poll_fn(move || {
+            let _dispatch_guard = match dispatch_lock.poll_lock() {
+                futures::Async::Ready(guard) => guard,
+                futures::Async::NotReady => return Ok(futures::Async::NotReady),
+            };
+
+            let _gate_guard = match &dispatch_gate {
+                Some(dispatch_gate) => match DispatchGate::poll_enter(dispatch_gate, false) {
+                    futures::Async::Ready(guard) => Some(guard),
+                    futures::Async::NotReady => return Ok(futures::Async::NotReady),
+                },
+                None => None,
+            };
+
+            let f = f
+                .take()
+                .expect("Polled with_dispatch_lock after it completed");
+            Ok(futures::Async::Ready(f()))
+        })
+    }
+
+    // Locks whichever resources are free right now and passes them to f, skipping any that something
+    // else is holding. This never waits, so it's meant for observability (i.e. a monitoring
+    // dashboard) that would rather show part of the world than stall, not for reads that need to
+    // be complete or consistent. Like list_resources, the locks are probed while holding the
+    // dispatch lock if it's available. The resources stay locked until f returns, so f should be
+    // quick. Seqlock resources are always available since they have no lock
+    pub fn snapshot_available<F, RetT>(&self, f: F) -> RetT
+    where
+        F: FnOnce(&AvailableResources<L>) -> RetT,
+    {
+        let dispatch_guard = self.dispatch_lock.try_lock();
+
+        let mut available: HashSet<ResourceId> = self.seqlock_resources.iter().cloned().collect();
+        let mut skipped = vec![];
+        let mut guards = vec![];
+        let lazy_resource_locks = self.lazy_resource_locks.lock().unwrap();
+        for (resource_id, lock) in self.resource_locks.iter().chain(lazy_resource_locks.iter()) {
+            // Snapshot resources share their resource's lock, which we may already hold
+            if self.is_snapshot_resource(resource_id) {
+                continue;
+            }
+
+            match lock.try_lock() {
+                Some(guard) => {
+                    available.insert(resource_id.clone());
+                    guards.push(guard);
+                }
+                None => skipped.push(resource_id.clone()),
+            }
+        }
+
+        drop(lazy_resource_locks);
+        drop(dispatch_guard);
+
+        // Waiting for the world would block while it's being changed (i.e. during maintenance or
+        // while a system's missing resources are set up), so in that case nothing can be read
+        let world = self.world.try_read().ok();
+        let available_resources = AvailableResources::new(world, available, skipped, guards);
+        f(&available_resources)
+    }
+
+    // Remembers how many times the given resources have been written so far. Call it right after
+    // dropping the guards for a yield-and-resume, and check ReacquireGuard::changed once the
+    // resources are acquired again to find out whether another system wrote them in between
+    pub fn reacquire_guard(&self, resources: &[ResourceId]) -> ReacquireGuard {
+        ReacquireGuard::new(resources, self.write_versions.as_ref())
+    }
+
+    // Checks that no resource lock is stuck, i.e. because a guard was leaked or a system holding it
+    // never finishes. Like list_resources, the locks are probed while holding the dispatch lock if
+    // it's available. A lock that's held counts as stuck if the same task has held it for longer
+    // than threshold, which is only known if the dispatcher was built with
+    // DispatcherBuilder::with_lock_holder_tracking. Locks taken for PlannedSystem extras or by a
+    // CrossDispatcher aren't tracked. Meant to be polled periodically, i.e. from a health endpoint
+    pub fn healthcheck(&self, threshold: std::time::Duration) -> HealthReport {
+        let _dispatch_guard = self.dispatch_lock.try_lock();
+
+        let mut report = HealthReport::default();
+        let lazy_resource_locks = self.lazy_resource_locks.lock().unwrap();
+        for (resource_id, lock) in self.resource_locks.iter().chain(lazy_resource_locks.iter()) {
+            if self.is_snapshot_resource(resource_id) {
+                continue;
+            }
+
+            report.checked += 1;
+            if lock.try_lock().is_some() {
+                continue;
+            }
+
+            report.held += 1;
+            let (task_id, _system, since) = match self
+                .lock_holders
+                .as_ref()
+                .and_then(|lock_holders| lock_holders.holder(resource_id))
+            {
+                Some(holder) => holder,
+                None => continue,
+            };
+
+            let held_for = since.elapsed();
+            if held_for >= threshold {
+                report.stuck.push(StuckResource {
+                    resource_name: self
+                        .resource_name(resource_id)
+                        .map(|name| name.to_string())
+                        .unwrap_or_else(|| format!("{:?}", resource_id)),
+                    task_id,
+                    held_for,
+                });
+            }
+        }
+
+        report
+            .stuck
+            .sort_by_key(|stuck| std::cmp::Reverse(stuck.held_for));
+        report
+    }
+
+    // Estimates how many of the given systems could run at the same time, as the size of the
+    // largest group of them where no two conflict on a resource (one of them writes it). Each entry
+    // is a system's reads and writes, i.e. from shred::Accessor. Seqlock resources never conflict
+    // since they aren't locked. This is exact but exponential in the worst case, so it's meant for
+    // a frame's worth of systems, not thousands. It doesn't account for how long each system runs
+    pub fn estimate_parallelism(&self, systems: &[(&[ResourceId], &[ResourceId])]) -> usize {
+        let locked = |resources: &[ResourceId]| -> Vec<ResourceId> {
+            resources
+                .iter()
+                .filter(|resource_id| !self.is_seqlock_resource(resource_id))
+                .cloned()
+                .collect()
+        };
+
+        let systems: Vec<(Vec<ResourceId>, Vec<ResourceId>)> = systems
+            .iter()
+            .map(|(reads, writes)| (locked(reads), locked(writes)))
+            .collect();
+
+        let conflicts: Vec<Vec<bool>> = systems
+            .iter()
+            .map(|first| {
+                systems
+                    .iter()
+                    .map(|second| {
+                        !conflicting_resources((&first.0, &first.1), (&second.0, &second.1))
+                            .is_empty()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        largest_independent_set(&conflicts, (0..systems.len()).collect())
+    }
+
+    pub(super) fn resource_policy_state(
+        &self,
+        resource_id: &ResourceId,
+    ) -> Option<&ResourcePolicyState> {
+        self.resource_policies.get(resource_id)
+    }
+
+    pub(super) fn resource_timeout(&self, resource_id: &ResourceId) -> Option<std::time::Duration> {
+        if self.resource_timeouts.is_empty() {
+            return None;
+        }
+
+        self.resource_timeouts.get(resource_id).cloned()
+    }
+
+    // Runs f with the given resource if nothing is using it right now, otherwise returns None. This
+    // is meant for inspecting the world from outside of a system (i.e. between steps of a
+    // SteppableSequential), systems should declare the resource instead
+    pub fn read_resource<R, F, RetT>(&self, f: F) -> Option<RetT>
+    where
+        R: shred::Resource,
+        F: FnOnce(&R) -> RetT,
+    {
+        let resource_id = ResourceId::new::<R>();
+        let _guard = if self.is_seqlock_resource(&resource_id) {
+            None
+        } else {
+            Some(self.resource_lock(&resource_id)?.try_lock()?)
+        };
+
+        let world = self.world();
+        let resource = world.try_fetch::<R>()?;
+        Some(f(&resource))
+    }
+
+    // Returns a handle for reading the world from outside of any system, without holding on to the
+    // dispatcher. Resources read one after the other may come from different frames, so it's only
+    // for best-effort debug reads. See WorldView before using it, and prefer read_resource
+    pub fn world_view(&self) -> WorldView {
+        WorldView::new(Arc::downgrade(&self.world))
+    }
+
+    // Returns the policy used when the given resource is contended
+    pub fn resource_policy(&self, resource_id: &ResourceId) -> ResourceLockPolicy {
+        self.resource_policies
+            .get(resource_id)
+            .map(|state| state.policy())
+            .unwrap_or_default()
+    }
+
+    pub(super) fn expedite_queue(&self) -> &Arc<ExpediteQueue> {
+        &self.expedite_queue
+    }
+
+    pub(super) fn recorder(&self) -> Option<&Arc<AcquisitionRecorder>> {
+        self.recorder.as_ref()
+    }
+
+    pub(super) fn replay(&self) -> Option<&AcquisitionReplay> {
+        self.replay.as_ref()
+    }
+
+    #[cfg(feature = "fault-injection")]
+    pub(super) fn fault_injector(&self) -> Option<&FaultInjector> {
+        self.fault_injector.as_ref()
+    }
+
+    #[cfg(feature = "audit")]
+    pub(super) fn audit_sink(&self) -> Option<&Arc<AuditSinkFn>> {
+        self.audit_sink.as_ref()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(super) fn access_counts(&self) -> &AccessCounts {
+        &self.access_counts
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(super) fn phase_metrics_tracker(&self) -> &PhaseMetricsTracker {
+        &self.phase_metrics
+    }
+
+    pub(super) fn try_claim_prefetch_slot(&self) -> bool {
+        self.prefetch_slot
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub(super) fn release_prefetch_slot(&self) {
+        self.prefetch_slot.store(false, Ordering::Release);
+    }
+
+    pub(super) fn take_task_id(&self) -> u64 {
+        if let Some(task_id_source) = &self.task_id_source {
+            return task_id_source();
+        }
+
+        // Relaxed because we only care that every call of this function returns a different value,
+        // we don't care about the ordering
+        self.next_task_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    }
+
+    // Reinitialize the world in place so that the dispatcher can be reused (i.e. between matches).
+    // This acquires every resource, waiting for any running systems to finish, and then gives
+    // exclusive access to the world. The terminate flag, the frame count, the frame history, the
+    // dispatch lock wait histogram, the contention report, the lock failure counts and the fault
+    // injection generator are also reset. Task ids keep counting up so they stay unique. This
+    // blocks the calling thread, so it must not be called from a task running on the dispatcher's
+    // runtime (use create_reset_future there), and never from inside a system since it would wait
+    // on itself.
+    // Only resources that were inserted with the DispatcherBuilder have locks, so f should replace
+    // existing resources rather than add new ones. Panics if the resources can't be acquired (i.e.
+    // the dispatcher is terminating and waiters are failed, see ContendedShutdownPolicy)
+    pub fn reset<F>(self: &Arc<Self>, f: F)
+    where
+        F: FnOnce(&mut shred::World),
+    {
+        use futures::Future;
+        Dispatcher::create_reset_future(self, f)
+            .wait()
+            .expect("reset couldn't acquire every resource")
+    }
+
+    // Same as reset, but returns a future instead of blocking, so that it can be used from inside
+    // the runtime (i.e. as a step in a schedule)
+    pub fn create_reset_future<F>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        f: F,
+    ) -> Box<impl futures::Future<Item = (), Error = ()>>
+    where
+        F: FnOnce(&mut shred::World),
+    {
+        use futures::Future;
+        let dispatcher = dispatcher.clone();
+        Box::new(
+            Dispatcher::create_exclusive_world_future(&dispatcher.clone(), f)
+                .map(move |_| dispatcher.reset_counters()),
+        )
+    }
+
+    // The task id counter isn't reset since acquisitions created before the reset may still be
+    // alive, and ids are used to find them (i.e. in the expedite queue and the resource waiters)
+    fn reset_counters(&self) {
+        self.should_terminate.store(false, Ordering::Release);
+        self.frame_counter.reset();
+
+        if let Some(frame_history) = &self.frame_history {
+            frame_history.clear();
+        }
+
+        if let Some(dispatch_lock_waits) = &self.dispatch_lock_waits {
+            dispatch_lock_waits.clear();
+        }
+
+        if let Some(lock_failures) = &self.lock_failures {
+            lock_failures.clear();
+        }
+
+        #[cfg(feature = "fault-injection")]
+        {
+            if let Some(fault_injector) = &self.fault_injector {
+                fault_injector.reset();
+            }
+        }
+
+        if let Some(contention) = &self.contention {
+            contention.clear();
+        }
+    }
+
+    // Spawn a task that is tied to the dispatcher's lifecycle. Unlike a raw tokio::spawn, the game
+    // loop will not finish until every task spawned this way has completed
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let in_flight_guard = self.in_flight.begin();
+        DefaultRuntime::spawn(Box::new(f.then(move |result| {
+            drop(in_flight_guard);
+            result
+        })));
+    }
+
+    // The number of tasks spawned with spawn() that haven't completed yet
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.count()
+    }
+
+    // Returns a future that completes once every task spawned with spawn() has completed
+    pub fn wait_for_in_flight(&self) -> WaitForInFlight {
+        WaitForInFlight::new(self.in_flight.clone())
+    }
+
+    // The order acquisitions try to take their locks in, see
+    // DispatcherBuilder::with_acquisition_order
+    pub fn acquisition_order(&self) -> AcquisitionOrder {
+        self.acquisition_order
+    }
+
+    // The number of frames the game loop has completed
+    pub fn frame_count(&self) -> u64 {
+        self.frame_counter.frame()
+    }
+
+    // A seed for the given system's RNG in the current frame, derived from the seed given to
+    // DispatcherBuilder::with_seed, the frame count and the system's id. A system that seeds its
+    // RNG with this every frame gets the same numbers on every run no matter how the systems were
+    // interleaved, so Monte Carlo style systems can run in parallel and still be replayed. The
+    // frame count only advances between frames, so every system in a frame sees the same one
+    pub fn seed_for(&self, system_id: SystemId) -> u64 {
+        system_seed(self.seed, self.frame_count(), system_id.index() as u64)
+    }
+
+    // Returns a future that completes once the game loop has completed the given number of frames
+    // (immediately if it already has). This is meant for timeline-style logic that would otherwise
+    // need a system checking the frame count every frame
+    pub fn at_frame(&self, frame: u64) -> AtFrame {
+        AtFrame::new(self.frame_counter.clone(), frame)
+    }
+
+    // Returns the timings of the most recent frames, oldest first. This is empty unless the
+    // dispatcher was built with DispatcherBuilder::with_frame_history
+    pub fn frame_history(&self) -> Vec<FrameTiming> {
+        self.frame_history
+            .as_ref()
+            .map(|frame_history| frame_history.frames())
+            .unwrap_or_default()
+    }
+
+    // Writes the timings kept by DispatcherBuilder::with_frame_history as folded stacks, which
+    // inferno or flamegraph.pl can render directly. Each system is a child of "frame", weighted by
+    // the microseconds it ran for across every frame in the history. Writes nothing without a
+    // frame history
+    pub fn export_profile<W: std::io::Write>(&self, writer: W) -> std::io::Result<()> {
+        write_folded(&self.frame_history(), writer)
+    }
+
+    // Returns how many times each resource has been acquired for reading and for writing, as
+    // (reads, writes). Resources that are mostly read are the ones that would gain the most from
+    // allowing concurrent readers. Scoped dispatchers add to their parent's counts. Requires the
+    // metrics feature
+    #[cfg(feature = "metrics")]
+    pub fn access_ratios(&self) -> HashMap<ResourceId, (u64, u64)> {
+        self.access_counts.counts()
+    }
+
+    // Calls f, tagging every acquisition it creates (i.e. with create_future) with the phase, so that
+    // their metrics are reported under it by phase_metrics. The tag is per thread and is taken when
+    // the future is created, not when it runs, so wrap the code that builds a frame's futures:
+    // dispatcher.phase("simulation", || ExecuteSequential::new(...)). Requires the metrics feature
+    #[cfg(feature = "metrics")]
+    pub fn phase<F, RetT>(&self, phase: &'static str, f: F) -> RetT
+    where
+        F: FnOnce() -> RetT,
+    {
+        with_phase(phase, f)
+    }
+
+    // Acquisition metrics for each phase given to Dispatcher::phase, since the dispatcher was
+    // built. Acquisitions created outside of a phase aren't counted. Requires the metrics feature
+    #[cfg(feature = "metrics")]
+    pub fn phase_metrics(&self) -> HashMap<&'static str, PhaseMetrics> {
+        self.phase_metrics.metrics()
+    }
+
+    // Returns how long acquisitions have waited for the dispatch lock. This is empty unless the
+    // dispatcher was built with DispatcherBuilder::with_dispatch_lock_wait_histogram
+    pub fn dispatch_lock_wait_histogram(&self) -> DispatchLockWaitHistogram {
+        self.dispatch_lock_waits
+            .as_ref()
+            .map(|dispatch_lock_waits| dispatch_lock_waits.histogram())
+            .unwrap_or_default()
+    }
+
+    // Returns the resources that were waited on during the most recently completed frame. This is
+    // None unless the dispatcher was built with DispatcherBuilder::with_contention_report
+    pub fn last_frame_contention(&self) -> Option<FrameContention> {
+        self.contention
+            .as_ref()
+            .and_then(|contention| contention.last_frame())
+    }
+
+    // For each system that has had to wait for a resource, the systems that were holding it, the
+    // one that caused the most waiting first. These are the pairs worth decoupling to speed up the
+    // waiting system. Unlike last_frame_contention, this covers every frame since the dispatcher
+    // was built (or reset). Empty unless the dispatcher was built with both
+    // DispatcherBuilder::with_contention_report and with_lock_holder_tracking
+    pub fn contention_pairs(&self) -> HashMap<&'static str, Vec<BlockingSystem>> {
+        self.contention
+            .as_ref()
+            .map(|contention| contention.contention_pairs())
+            .unwrap_or_default()
+    }
+
+    // The estimated memory used by futures from create_future that are waiting for resources
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes.current()
+    }
+
+    // The limit set with DispatcherBuilder::with_queued_bytes_budget, if any
+    pub fn queued_bytes_budget(&self) -> Option<usize> {
+        self.queued_bytes.budget()
+    }
+
+    pub(super) fn tracks_contention(&self) -> bool {
+        self.contention.is_some()
+    }
+
+    pub(super) fn record_resource_wait(
+        &self,
+        resource_id: &ResourceId,
+        system_name: &'static str,
+        blocker: Option<&'static str>,
+        duration: std::time::Duration,
+    ) {
+        if let Some(contention) = &self.contention {
+            let resource_name = || {
+                self.resource_name(resource_id)
+                    .map(|name| name.to_string())
+                    .unwrap_or_else(|| format!("{:?}", resource_id))
+            };
+
+            contention.record_wait(resource_id, resource_name, system_name, duration);
+            if let Some(blocker) = blocker {
+                contention.record_blocked(system_name, blocker, duration);
+            }
+        }
+    }
+
+    pub(super) fn tracks_dispatch_lock_waits(&self) -> bool {
+        self.dispatch_lock_waits.is_some()
+    }
+
+    pub(super) fn record_dispatch_lock_wait(&self, duration: std::time::Duration) {
+        if let Some(dispatch_lock_waits) = &self.dispatch_lock_waits {
+            dispatch_lock_waits.record(duration);
+        }
+    }
+
+    // Returns a handle that can be used to wake the given acquisition from an event source that
+    // isn't driven by tokio
+    pub fn register_external_waker(&self, acquisition: &AcquireStatusHandle) -> ExternalWaker {
+        acquisition.external_waker()
+    }
+
+    // Create a child dispatcher that shares this one's world and resource locks, but has its own
+    // dispatch lock, task ids, frame count and loop (see ScopedDispatcher). Resource policies,
+    // maintenance and the optional diagnostics aren't carried over
+    pub fn scoped(dispatcher: &Arc<Dispatcher<L>>) -> ScopedDispatcher<L> {
+        let lock_failures = if dispatcher.acquisition_order == AcquisitionOrder::MostContendedFirst
+        {
+            Some(LockFailureCounts::new(dispatcher.resource_locks.keys()))
+        } else {
+            None
+        };
+
+        // Cloning a lock gives another handle to the same lock, so acquisitions in the parent and
+        // the child exclude each other
+        let child = Dispatcher {
+            next_task_id: std::sync::atomic::AtomicU64::new(0),
+            task_id_source: None,
+            world: dispatcher.world.clone(),
+            dispatch_lock: L::new(),
+            resource_locks: dispatcher.resource_locks.clone(),
+            lazy_resource_locks: dispatcher.lazy_resource_locks.clone(),
+            snapshot_resources: dispatcher.snapshot_resources.clone(),
+            resource_names: dispatcher.resource_names.clone(),
+            resource_policies: HashMap::new(),
+            resource_timeouts: dispatcher.resource_timeouts.clone(),
+            seqlock_resources: dispatcher.seqlock_resources.clone(),
+            double_buffered: dispatcher.double_buffered.clone(),
+            should_terminate: std::sync::atomic::AtomicBool::new(false),
+            loop_running: std::sync::atomic::AtomicBool::new(false),
+            in_flight: Arc::new(InFlightTasks::new()),
+            expedite_queue: Arc::new(ExpediteQueue::new()),
+            recorder: None,
+            replay: None,
+            frame_counter: Arc::new(FrameCounter::new()),
+            frame_history: None,
+            dispatch_lock_waits: None,
+            shutdown_policy: dispatcher.shutdown_policy,
+            contended_shutdown_policy: dispatcher.contended_shutdown_policy,
+            resource_waiters: ResourceWaiters::new(),
+            spin_retries: dispatcher.spin_retries,
+            lock_failures,
+            acquisition_order: dispatcher.acquisition_order,
+            maintain: None,
+            contention: None,
+            queued_bytes: Arc::new(QueuedBytes::new(None)),
+            pending_keys: Arc::new(PendingKeys::new()),
+            #[cfg(debug_assertions)]
+            checked_systems: Mutex::new(HashSet::new()),
+            on_acquire: dispatcher.on_acquire,
+            on_release: dispatcher.on_release,
+            lock_holders: dispatcher.lock_holders.clone(),
+            write_versions: dispatcher.write_versions.clone(),
+            seed: dispatcher.seed,
+            replication: dispatcher.replication.clone(),
+            transition_logger: dispatcher.transition_logger.clone(),
+            prefetch_slot: dispatcher.prefetch_slot.clone(),
+            dispatch_gate: dispatcher
+                .dispatch_gate
+                .as_ref()
+                .map(|_| Arc::new(DispatchGate::new())),
+            #[cfg(feature = "fault-injection")]
+            fault_injector: None,
+            #[cfg(feature = "audit")]
+            audit_sink: dispatcher.audit_sink.clone(),
+            #[cfg(feature = "metrics")]
+            access_counts: dispatcher.access_counts.clone(),
+            #[cfg(feature = "metrics")]
+            phase_metrics: dispatcher.phase_metrics.clone(),
+            parent: Some(dispatcher.clone()),
+        };
+
+        ScopedDispatcher::new(Arc::new(child))
+    }
+
+    // The dispatcher this one was created from with Dispatcher::scoped, if any
+    pub fn parent(&self) -> Option<&Arc<Dispatcher<L>>> {
+        self.parent.as_ref()
+    }
+
+    // Returns a reference to the dispatcher that doesn't keep it alive. See WeakDispatcher
+    pub fn weak_handle(dispatcher: &Arc<Dispatcher<L>>) -> WeakDispatcher<L> {
+        WeakDispatcher::new(dispatcher)
+    }
+
+    pub fn end_game_loop(&self) {
+        self.should_terminate.swap(true, Ordering::Release);
+
+        // Anything parked on a resource would otherwise only find out once the resource is released
+        self.resource_waiters.wake_all();
+    }
+
+    // Whether the game loop is running. Code outside the loop (i.e. a network thread) can use this
+    // to decide whether it's still worth queueing work
+    pub fn status(&self) -> LoopStatus {
+        if !self.loop_running.load(Ordering::Acquire) {
+            LoopStatus::Stopped
+        } else if self.should_terminate.load(Ordering::Acquire) {
+            LoopStatus::Terminating
+        } else {
+            LoopStatus::Running
+        }
+    }
+
+    // Call this to kick off processing.
+    pub fn enter_game_loop<F, FutureT>(self, f: F) -> shred::World
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        self.run_game_loop(None, futures::future::empty(), f).0
+    }
+
+    // Same as enter_game_loop, but each frame is a std future, so it can be written as an async
+    // block. The dispatcher's futures are futures 0.1, so await them with .compat() (from
+    // futures 0.3's Future01CompatExt). Futures awaited in an async block all run on the frame's
+    // task, use ExecuteParallel (which spawns) for work that should run in parallel
+    #[cfg(feature = "std-futures")]
+    pub fn enter_game_loop_async<F, FutureT>(self, f: F) -> shred::World
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: std::future::Future<Output = Result<(), ()>> + Send + 'static,
+    {
+        self.enter_game_loop(move |dispatcher| {
+            futures03::compat::Compat::new(Box::pin(f(dispatcher)))
+        })
+    }
+
+    // Same as enter_game_loop, but the loop also ends once stop resolves (either way), i.e. a
+    // future for Ctrl-C or a lost connection. This works like calling end_game_loop when stop
+    // resolves: the current frame finishes (acquisitions still waiting on resources fail, see
+    // AcquireStatus::Terminated) and then the world is returned. If the loop ends first, stop is
+    // dropped
+    pub fn enter_game_loop_until<S, F, FutureT>(self, stop: S, f: F) -> shred::World
+    where
+        S: futures::future::Future + Send + 'static,
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        self.run_game_loop(None, stop.then(|_| Ok(())), f).0
+    }
+
+    // Same as enter_game_loop, but the loop runs on a new thread and this returns right away, for
+    // when the calling thread has other things to do (i.e. drive a GUI's event loop). Use the
+    // handle to stop the loop and to get the world back
+    pub fn enter_game_loop_detached<F, FutureT>(self, f: F) -> LoopHandle
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        use futures::future::Either;
+        use futures::Future;
+
+        // If the handle is dropped without calling stop, the loop keeps going
+        let (stop_tx, stop_rx) = futures::sync::oneshot::channel();
+        let stop = stop_rx.then(|result| match result {
+            Ok(()) => Either::A(futures::future::ok(())),
+            Err(_) => Either::B(futures::future::empty()),
+        });
+
+        let thread = std::thread::Builder::new()
+            .name("game loop".to_string())
+            .spawn(move || self.run_game_loop(None, stop, f).0)
+            .expect("Failed to spawn the game loop thread");
+
+        LoopHandle::new(stop_tx, thread)
+    }
+
+    // Same as enter_game_loop, but nothing runs until the returned loop is polled, for embedding
+    // the dispatcher in a loop that something else owns (i.e. a GUI's event loop). See ManualLoop
+    pub fn into_manual_loop<F, FutureT>(self, f: F) -> ManualLoop<F, L>
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Copy + Send + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        ManualLoop::new(Arc::new(self), f)
+    }
+
+    // Same as enter_game_loop, but ends the loop on its own after count frames (or earlier if
+    // end_game_loop is called) and also returns how long the frames took. Meant for benchmarks and
+    // warming up
+    pub fn run_frames<F, FutureT>(self, count: usize, f: F) -> (shred::World, FrameStats)
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        // The loop always runs at least one frame
+        if count == 0 {
+            return (
+                Dispatcher::into_world(Arc::new(self)),
+                FrameStats::default(),
+            );
+        }
+
+        self.run_game_loop(Some(count), futures::future::empty(), f)
+    }
+
+    // Same as run_frames, but panics if the slowest frame took longer than deadline, so that a test
+    // fails when frames get slower (i.e. a new system contends for a lock that others were using in
+    // parallel). The frames all run before this checks, so the panic reports the whole run's stats
+    pub fn run_frames_asserting_deadline<F, FutureT>(
+        self,
+        count: usize,
+        deadline: std::time::Duration,
+        f: F,
+    ) -> (shred::World, FrameStats)
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let (world, frame_stats) = self.run_frames(count, f);
+        assert!(
+            frame_stats.max <= deadline,
+            "A frame took {:?}, which is over the deadline of {:?} ({:?})",
+            frame_stats.max,
+            deadline,
+            frame_stats
+        );
+
+        (world, frame_stats)
+    }
+
+    // Runs the frame loop until it ends on its own or stop resolves
+    fn run_game_loop<S, F, FutureT>(
+        self,
+        frame_limit: Option<usize>,
+        stop: S,
+        f: F,
+    ) -> (shred::World, FrameStats)
+    where
+        S: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        use futures::future::Either;
+        use futures::Future;
+
+        // Put the dispatcher in an Arc so it can be shared among tasks
+        let dispatcher = Arc::new(self);
+        dispatcher.loop_running.store(true, Ordering::Release);
+
+        let frame_stats = Arc::new(Mutex::new(FrameStats::default()));
+        // The frame loop checks stop between frames. It's also raced against the loop so that it's
+        // noticed during a frame that's waiting on something, in which case the loop is ended the
+        // same way end_game_loop does and keeps running until the current frame is done
+        let stop = stop.shared();
+        let frame_loop = Dispatcher::frame_loop(
+            &dispatcher,
+            frame_limit,
+            frame_stats.clone(),
+            stop.clone().then(|_| Ok(())),
+            f,
+        );
+
+        let dispatcher_clone = dispatcher.clone();
+        let stop = stop.then(move |_| {
+            dispatcher_clone.end_game_loop();
+            Ok::<(), ()>(())
+        });
+        let loop_future = frame_loop.select2(stop).then(|result| match result {
+            Ok(Either::A(_)) => Either::A(futures::future::ok(())),
+            Ok(Either::B((_, frame_loop))) => Either::B(frame_loop),
+            Err(Either::A(_)) | Err(Either::B(_)) => Either::A(futures::future::err(())),
+        });
+
+        // Kick off the process
+        debug!("Calling runtime run");
+        DefaultRuntime::run(Box::new(loop_future));
+        dispatcher.loop_running.store(false, Ordering::Release);
+
+        // After execution ends, unwrap the dispatcher arc and return the world inside it
+        let frame_stats = frame_stats.lock().unwrap().clone();
+        (Dispatcher::into_world(dispatcher), frame_stats)
+    }
+
+    // Runs frames until end_game_loop is called, frame_limit frames have run or stop has resolved,
+    // and then waits for any tasks that were spawned through the dispatcher
+    pub(super) fn frame_loop<S, F, FutureT>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        frame_limit: Option<usize>,
+        frame_stats: Arc<Mutex<FrameStats>>,
+        stop: S,
+        f: F,
+    ) -> impl futures::Future<Item = (), Error = ()>
+    where
+        S: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        use futures::Future;
+
+        let dispatcher_clone = dispatcher.clone();
+        let wait_for_in_flight = dispatcher.wait_for_in_flight();
+
+        let loop_future = futures::future::loop_fn(stop, move |mut stop| {
+            // These clones are so that we can pass them to the inner closure
+            let dispatcher_clone2 = dispatcher_clone.clone();
+            let frame_stats_clone = frame_stats.clone();
+
+            Dispatcher::frame(&dispatcher_clone, f).map(move |frame_duration| {
+                let mut frame_stats = frame_stats_clone.lock().unwrap();
+                frame_stats.record_frame(frame_duration);
+                let reached_frame_limit = frame_limit
+                    .map(|frame_limit| frame_stats.frame_count >= frame_limit)
+                    .unwrap_or(false);
+
+                // Frames that complete right away all run within one poll, so stop has to be
+                // checked here too
+                if !matches!(stop.poll(), Ok(futures::Async::NotReady)) {
+                    dispatcher_clone2.end_game_loop();
+                }
+
+                if reached_frame_limit || dispatcher_clone2.should_terminate.load(Ordering::Acquire)
+                {
+                    futures::future::Loop::Break(())
+                } else {
+                    futures::future::Loop::Continue(stop)
+                }
+            })
+        });
+
+        // Once the loop ends, wait for any tasks that were spawned through the dispatcher
+        loop_future.and_then(|_| wait_for_in_flight)
+    }
+
+    // A single frame: f's future followed by maintenance. Resolves to how long the frame took, once
+    // the frame history, the contention report and the frame count have been updated
+    pub(super) fn frame<F, FutureT>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        f: F,
+    ) -> impl futures::Future<Item = std::time::Duration, Error = ()>
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT,
+        FutureT: futures::future::Future<Item = (), Error = ()>,
+    {
+        use futures::Future;
+
+        let dispatcher_clone = dispatcher.clone();
+        let dispatcher_clone2 = dispatcher.clone();
+        let frame_start = std::time::Instant::now();
+        let maintain_future = Dispatcher::create_maintain_future(dispatcher);
+        let publish_future = Dispatcher::create_publish_future(dispatcher);
+        (f)(dispatcher.clone())
+            .or_else(move |_| {
+                // Acquisitions that were waiting fail once the loop is terminating (see
+                // AcquireStatus::Terminated). That's expected, so finish the frame normally
+                if dispatcher_clone.should_terminate() {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            })
+            .and_then(|_| maintain_future)
+            .and_then(|_| publish_future)
+            .map(move |_| {
+                let frame_duration = frame_start.elapsed();
+                if let Some(frame_history) = &dispatcher_clone2.frame_history {
+                    frame_history.end_frame(frame_duration);
+                }
+
+                if let Some(contention) = &dispatcher_clone2.contention {
+                    contention.end_frame(dispatcher_clone2.frame_counter.frame());
+                }
+
+                dispatcher_clone2.frame_counter.advance();
+                frame_duration
+            })
+    }
+
+    pub(super) fn into_world(dispatcher: Arc<Dispatcher<L>>) -> shred::World {
+        if let ShutdownPolicy::WaitWithTimeout(timeout) = dispatcher.shutdown_policy {
+            let start = std::time::Instant::now();
+            while Arc::strong_count(&dispatcher) > 1 && start.elapsed() < timeout {
+                std::thread::yield_now();
+            }
+        }
+
+        let dispatcher = match Arc::try_unwrap(dispatcher) {
+            Ok(dispatcher) => dispatcher,
+            Err(shared_dispatcher) => {
+                if shared_dispatcher.shutdown_policy != ShutdownPolicy::LogAndLeak {
+                    panic!(
+                        "The dispatcher still has {} references after the game loop ended",
+                        Arc::strong_count(&shared_dispatcher)
+                    );
+                }
+
+                error!(
+                    "The dispatcher still has {} references after the game loop ended, leaking it",
+                    Arc::strong_count(&shared_dispatcher)
+                );
+
+                // This waits for any system that is still running to finish
+                let world = std::mem::replace(
+                    &mut *shared_dispatcher.world.write().unwrap(),
+                    shred::World::empty(),
+                );
+                std::mem::forget(shared_dispatcher);
+                return world;
+            }
+        };
+
+        // Only the dispatcher holds the world, so this can't fail
+        Arc::try_unwrap(dispatcher.world)
+            .unwrap_or_else(|_| {
+                unreachable!();
+            })
+            .into_inner()
+            .unwrap()
+    }
+
+    // Runs the system now on the calling thread. The system's resources are acquired first, the
+    // same way a queued system acquires them, blocking the thread until they are. This makes it
+    // safe to call while the game loop is running, but like acquire_blocking, it must never be
+    // called from inside the runtime (a task or a system), since it blocks the thread it's called
+    // on. Panics if the resources can't be acquired (i.e. the dispatcher is terminating)
+    pub fn run_system<T>(dispatcher: &Arc<Dispatcher<L>>, system: T) -> T
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use futures::Future;
+
+        Dispatcher::create_future_with_result(dispatcher, system)
+            .wait()
+            .expect("run_system couldn't acquire the system's resources")
+    }
+
+    // Same as run_system, but runs the system without acquiring its resources. This races with
+    // anything else using those resources, so it's only safe when nothing else can be running
+    // (i.e. before the game loop starts or after it ends)
+    pub fn run_system_unchecked<T>(&self, system: T) -> T
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        self.run_system_after_fetch(system, || {})
+    }
+
+    // Runs the system with the world, calling after_fetch once the system's data has been fetched
+    // and before it runs (i.e. to release snapshot resources). The caller must be holding the
+    // system's resources
+    fn run_system_after_fetch<T, F>(&self, mut system: T, after_fetch: F) -> T
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+        F: FnOnce(),
+    {
+        use shred::DynamicSystemData;
+        let start = std::time::Instant::now();
+        let world = self.world();
+        let data = <T as shred::System>::SystemData::fetch(&system.accessor(), &world);
+
+        #[cfg(debug_assertions)]
+        self.assert_fetched_resources_declared(&system, &world);
+
+        after_fetch();
+        system.run(data);
+        drop(world);
+
+        self.record_system_timing(std::any::type_name::<T>(), start.elapsed());
+        system
+    }
+
+    // Catches a system fetching a resource that its accessor doesn't declare (which is possible with
+    // a hand-written Accessor), since its lock was never acquired. This is called while the
+    // system's data is fetched. Any resource whose lock is free shouldn't be borrowed, so if it is,
+    // it must be this system that has it. Resources whose locks are held by someone else can't be
+    // checked, and each system type is only checked the first time it runs since this looks at
+    // every resource, so this won't catch every case.
+    #[cfg(debug_assertions)]
+    fn assert_fetched_resources_declared<T>(&self, system: &T, world: &shred::World)
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use shred::Accessor;
+
+        if !self
+            .checked_systems
+            .lock()
+            .unwrap()
+            .insert(std::any::TypeId::of::<T>())
+        {
+            return;
+        }
+
+        let accessor = system.accessor();
+        let reads = accessor.reads();
+        let writes = accessor.writes();
+
+        let lazy_resource_locks = self.lazy_resource_locks.lock().unwrap();
+        for (resource_id, lock) in self.resource_locks.iter().chain(lazy_resource_locks.iter()) {
+            if reads.contains(resource_id) || writes.contains(resource_id) {
+                continue;
+            }
+
+            // Holding the guard keeps anyone else from acquiring the resource while we check it.
+            // A held lock is skipped without queueing on it
+            let _guard = match lock.try_lock() {
+                Some(guard) => guard,
+                None => continue,
+            };
+
+            let borrowed = world
+                .try_fetch_internal(resource_id.clone())
+                .map(|cell| cell.try_borrow_mut().is_err())
+                .unwrap_or(false);
+
+            if borrowed {
+                panic!(
+                    "{} fetched {} without declaring it, so its lock was not acquired",
+                    std::any::type_name::<T>(),
+                    self.resource_name(resource_id)
+                        .map(|name| name.to_string())
+                        .unwrap_or_else(|| format!("{:?}", resource_id))
+                );
+            }
+        }
+    }
+
+    pub(super) fn world(&self) -> RwLockReadGuard<'_, shred::World> {
+        self.world.read().unwrap()
+    }
+
+    pub(super) fn record_system_timing(&self, name: &'static str, duration: std::time::Duration) {
+        if let Some(frame_history) = &self.frame_history {
+            frame_history.record_system(name, duration);
+        }
+    }
+
+    // Runs a system once per key, in parallel. system_factory creates the system for each key, and
+    // each system should only touch that key's instance of a keyed resource (see
+    // DispatcherBuilder::insert_keyed) so that they don't contend with each other
+    pub fn create_fanout_future<T, K, F>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system_factory: F,
+        keys: impl IntoIterator<Item = K>,
+    ) -> ExecuteParallel<()>
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+        F: Fn(K) -> T,
+    {
+        let futures = keys
+            .into_iter()
+            .map(|key| {
+                Dispatcher::create_future(dispatcher, system_factory(key))
+                    as Box<dyn futures::Future<Item = (), Error = ()> + Send>
+            })
+            .collect();
+
+        ExecuteParallel::new(futures)
+    }
+
+    // Acquires the given resources and then calls f with the world. f must only fetch the resources
+    // it asked for. This is for one-off work (i.e. scripts or tests) that doesn't need a System
+    pub fn with_resources<F, RetT>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        reads: &[ResourceId],
+        writes: &[ResourceId],
+        f: F,
+    ) -> Box<impl futures::Future<Item = RetT, Error = ()>>
+    where
+        F: FnOnce(&shred::World) -> RetT + Send + 'static,
+    {
+        use futures::Future;
+
+        let dispatcher = dispatcher.clone();
+        let required_resources = super::RequiredResources::<F>::from_slices(reads, writes);
+        let acquire =
+            super::AcquireResources::new(dispatcher.clone(), required_resources).map_err(|_| ());
+        Box::new(acquire.map(move |_guards| f(&dispatcher.world())))
+    }
+
+    // Acquires R for writing like a system would, swaps in new and resolves to the old value. Since
+    // it goes through the normal acquisition, it waits for any system that's reading or writing R
+    // to finish, and no system sees R until the swap is done. Meant for live reconfiguration (i.e.
+    // hot-swapping a config resource). R must exist and must not be a seqlock resource
+    pub fn replace_resource<R>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        new: R,
+    ) -> Box<impl futures::Future<Item = R, Error = ()>>
+    where
+        R: shred::Resource,
+    {
+        Dispatcher::with_resources(dispatcher, &[], &[ResourceId::new::<R>()], move |world| {
+            let mut resource = world
+                .try_fetch_mut::<R>()
+                .expect("replace_resource was called for a resource that does not exist");
+            std::mem::replace(&mut *resource, new)
+        })
+    }
+
+    // Copies every resource inserted with DispatcherBuilder::insert_replicated to standby, which
+    // must have been built with the same resources (i.e. a warm standby to fail over to). The
+    // resources are read locked here while they're snapshotted and then released, and the snapshots
+    // are applied under standby's write locks, so neither dispatcher has to stop and no system on
+    // standby sees some of a batch without the rest. Resolves to how many resources were copied.
+    //
+    // With DispatcherBuilder::with_write_versions only resources that were written since they were
+    // last copied to this standby are snapshotted. Without it every replicated resource is copied
+    // every time
+    pub fn replicate_to(
+        dispatcher: &Arc<Dispatcher<L>>,
+        standby: &Arc<Dispatcher<L>>,
+    ) -> Box<impl futures::Future<Item = usize, Error = ()>> {
+        use futures::Future;
+
+        let replication = dispatcher.replication.clone();
+        let write_versions = dispatcher.write_versions.clone();
+        let standby = standby.clone();
+        let standby_world = standby.world.clone();
+        let snapshot = Dispatcher::with_resources(
+            dispatcher,
+            &replication.resource_ids(),
+            &[],
+            move |world| {
+                Replication::snapshot_changed(
+                    &replication,
+                    world,
+                    write_versions.as_deref(),
+                    &standby_world,
+                )
+            },
+        );
+
+        Box::new(snapshot.and_then(move |batch| {
+            let writes = batch.resource_ids();
+            Dispatcher::with_resources(&standby, &[], &writes, move |world| batch.apply(world))
+        }))
+    }
+
+    // Acquires the given resources and resolves to a Transaction over them, for changes to several
+    // resources that must be applied all together or not at all. See Transaction
+    pub fn transaction(
+        dispatcher: &Arc<Dispatcher<L>>,
+        reads: &[ResourceId],
+        writes: &[ResourceId],
+    ) -> Box<impl futures::Future<Item = Transaction<L>, Error = ()>> {
+        use futures::Future;
+
+        let dispatcher = dispatcher.clone();
+        let writes = writes.to_vec();
+        let required_resources = super::RequiredResources::<()>::from_slices(reads, &writes);
+        let acquire =
+            super::AcquireResources::new(dispatcher.clone(), required_resources).map_err(|_| ());
+        Box::new(acquire.map(move |guards| Transaction::new(dispatcher, &writes, guards)))
+    }
+
+    // Acquires primary's resources, or if that takes longer than timeout, gives up on them and runs
+    // fallback instead (i.e. a cheaper system that needs fewer of the contended resources, like
+    // rendering at a lower quality). Resolves to the guards if primary's resources were acquired,
+    // so that the caller can run whatever needed them, or to None once fallback has run. fallback
+    // waits for its own resources for as long as it takes
+    pub fn acquire_or_else<T, U>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        primary: super::RequiredResources<T>,
+        timeout: std::time::Duration,
+        fallback: U,
+    ) -> Box<impl futures::Future<Item = Option<AcquiredResourcesLockGuards<T, L>>, Error = ()>>
+    where
+        U: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use futures::future::Either;
+        use futures::Future;
+
+        let dispatcher = dispatcher.clone();
+        let acquire = super::AcquireResources::new(dispatcher.clone(), primary).map_err(|_| ());
+
+        // The delay is created once we're running, since tokio's timer needs to be
+        Box::new(futures::future::lazy(move || {
+            acquire
+                .select2(DefaultRuntime::delay(timeout))
+                .then(move |result| match result {
+                    Ok(Either::A((guards, _delay))) => Either::A(futures::future::ok(Some(guards))),
+                    Ok(Either::B((_, acquire))) => {
+                        trace!(
+                            "Timed out acquiring resources, running {} instead",
+                            std::any::type_name::<U>()
+                        );
+
+                        // Dropping the acquisition gives up its place in line
+                        drop(acquire);
+                        Either::B(
+                            Dispatcher::create_future_with_result(&dispatcher, fallback)
+                                .map(|_| None),
+                        )
+                    }
+                    Err(Either::A(_)) | Err(Either::B(_)) => Either::A(futures::future::err(())),
+                })
+        }))
+    }
+
+    // Acquires the given resources and then runs body, for systems whose resources depend on
+    // runtime data rather than on their type (i.e. nodes in a data-driven graph). body must only
+    // fetch what required_resources declares. Resolves to body so that it can be run again
+    pub fn create_dynamic_future(
+        dispatcher: &Arc<Dispatcher<L>>,
+        required_resources: super::RequiredResources<Box<DynamicSystemFn>>,
+        mut body: Box<DynamicSystemFn>,
+    ) -> Box<impl futures::Future<Item = Box<DynamicSystemFn>, Error = ()>> {
+        use futures::Future;
+
+        let dispatcher = dispatcher.clone();
+        let acquire =
+            super::AcquireResources::new(dispatcher.clone(), required_resources).map_err(|_| ());
+        Box::new(acquire.map(move |_guards| {
+            let start = std::time::Instant::now();
+            body(&dispatcher.world());
+            dispatcher
+                .record_system_timing(std::any::type_name::<DynamicSystemFn>(), start.elapsed());
+            body
+        }))
+    }
+
+    // Read-locks only R, calls predicate with it and releases it again. This is a cheap gate for
+    // deciding whether an expensive system is worth running at all, without waiting for all of
+    // its resources. R may have changed by the time the system runs, so the system must still
+    // cope with the case the gate was meant to filter out
+    pub fn preflight<R, P>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        predicate: P,
+    ) -> Box<impl futures::Future<Item = bool, Error = ()>>
+    where
+        R: shred::Resource,
+        P: FnOnce(&R) -> bool + Send + 'static,
+    {
+        Dispatcher::with_resources(dispatcher, &[ResourceId::new::<R>()], &[], move |world| {
+            predicate(&world.fetch::<R>())
+        })
+    }
+
+    // Blocks the current thread until the given resources are acquired, for code that can't be
+    // written as a future (i.e. an FFI callback). The resources are held until the returned scope
+    // is dropped. Never call this from a task running on the dispatcher's runtime: it blocks a
+    // worker thread, and if the resources are held by a task that needs that thread to finish, it
+    // will never return.
+    pub fn acquire_blocking(
+        dispatcher: &Arc<Dispatcher<L>>,
+        reads: &[ResourceId],
+        writes: &[ResourceId],
+    ) -> ResourceScope<L> {
+        use futures::Future;
+
+        let required_resources = super::RequiredResources::<()>::from_slices(reads, writes);
+        let guards = super::AcquireResources::new(dispatcher.clone(), required_resources)
+            .wait()
+            .expect("Acquiring resources can't fail");
+
+        ResourceScope::new(dispatcher.clone(), guards)
+    }
+
+    // Returns a future that runs the function given to DispatcherBuilder::with_maintain. Every
+    // resource is acquired first, and then the world is locked for writing, so nothing else is
+    // touching the world while it runs. The game loop already does this at the end of every frame,
+    // this is for running it at some other point (i.e. as a step in a schedule). If no function was
+    // given, this does nothing.
+    pub fn create_maintain_future(
+        dispatcher: &Arc<Dispatcher<L>>,
+    ) -> Box<impl futures::Future<Item = (), Error = ()>> {
+        let dispatcher = dispatcher.clone();
+        Box::new(futures::future::lazy(move || {
+            if dispatcher.maintain.is_none() {
+                return futures::future::Either::A(futures::future::ok(()));
+            }
+
+            let maintain_dispatcher = dispatcher.clone();
+            futures::future::Either::B(Dispatcher::create_exclusive_world_future(
+                &dispatcher,
+                move |world| (maintain_dispatcher.maintain.as_ref().unwrap())(world),
+            ))
+        }))
+    }
+
+    // Returns a future that calls f with exclusive access to the world. Every resource is acquired
+    // first like any other acquisition, so nothing holding a resource is using the world, and then
+    // the world is taken for writing. This never blocks the thread on the world's lock: the only
+    // readers left at that point are the ones that don't take locks (i.e. systems that only read
+    // seqlock resources), so if one of them has the world we try again on the next poll
+    fn create_exclusive_world_future<F, O>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        f: F,
+    ) -> impl futures::Future<Item = O, Error = ()>
+    where
+        F: FnOnce(&mut shred::World) -> O,
+    {
+        use futures::Future;
+
+        let dispatcher = dispatcher.clone();
+        futures::future::lazy(move || {
+            // Snapshot resources share their resource's lock, so asking for both would wait on a
+            // lock we already hold
+            let writes: Vec<ResourceId> = {
+                let lazy_resource_locks = dispatcher.lazy_resource_locks.lock().unwrap();
+                dispatcher
+                    .resource_locks
+                    .keys()
+                    .chain(lazy_resource_locks.keys())
+                    .filter(|resource_id| !dispatcher.is_snapshot_resource(resource_id))
+                    .cloned()
+                    .collect()
+            };
+
+            let required_resources = super::RequiredResources::<()>::new(vec![], writes);
+            let acquire = super::AcquireResources::new(dispatcher.clone(), required_resources)
+                .map_err(|_| ());
+            acquire.and_then(move |guards| {
+                let mut f = Some(f);
+                futures::future::poll_fn(move || {
+                    let mut world = match dispatcher.world.try_write() {
+                        Ok(world) => world,
+                        Err(std::sync::TryLockError::WouldBlock) => {
+                            futures::task::current().notify();
+                            return Ok(futures::Async::NotReady);
+                        }
+                        Err(std::sync::TryLockError::Poisoned(error)) => {
+                            panic!("The world's lock was poisoned: {}", error)
+                        }
+                    };
+
+                    let output = (f.take().unwrap())(&mut world);
+                    drop(world);
+                    let _ = &guards;
+                    Ok(futures::Async::Ready(output))
+                })
+            })
+        })
+    }
+
+    // Returns a future that copies the back of every double buffered resource to its front (see
+    // DispatcherBuilder::insert_double_buffered). The game loop already does this at the end of
+    // every frame, this is for dispatchers that aren't driven by it
+    pub fn create_publish_future(
+        dispatcher: &Arc<Dispatcher<L>>,
+    ) -> Box<impl futures::Future<Item = (), Error = ()>> {
+        let double_buffered = dispatcher.double_buffered.clone();
+        let reads: Vec<ResourceId> = double_buffered
+            .iter()
+            .map(|resource| resource.back_resource_id.clone())
+            .collect();
+
+        Dispatcher::with_resources(dispatcher, &reads, &[], move |world| {
+            for resource in double_buffered.iter() {
+                (resource.publish)(world);
+            }
+        })
+    }
+
+    // Wraps the given system for use in an ExecutePrefetched, which decides when it starts
+    // acquiring its resources
+    pub fn create_prefetchable<T>(dispatcher: &Arc<Dispatcher<L>>, system: T) -> PrefetchableSystem
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use futures::Future;
+
+        let required_resources = super::RequiredResources::from_system(&system);
+        let resources = required_resources
+            .reads
+            .iter()
+            .chain(required_resources.writes.iter())
+            .cloned()
+            .collect();
+
+        let dispatcher = dispatcher.clone();
+        let acquire = futures::future::lazy(move || {
+            Dispatcher::setup_missing_resources(&dispatcher, system).and_then(move |system| {
+                super::AcquireResources::<T, L>::new(dispatcher.clone(), required_resources)
+                    .map_err(|_| ())
+                    .map(move |mut guards| {
+                        Box::new(move || {
+                            dispatcher
+                                .run_system_after_fetch(system, || guards.release_snapshots());
+                        }) as Box<dyn FnOnce() + Send>
+                    })
+            })
+        });
+
+        PrefetchableSystem::new(resources, Box::new(acquire))
+    }
+
+    // Returns a future that runs the given system until it finishes, letting go of its resources
+    // and queueing it again whenever it yields. The system is given slice to run each time before
+    // YieldToken::check tells it to yield. See ResumableSystem
+    pub fn create_resumable_future<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+        slice: std::time::Duration,
+    ) -> Box<impl futures::Future<Item = T, Error = ()>>
+    where
+        T: ResumableSystem,
+    {
+        use futures::Future;
+
+        let dispatcher = dispatcher.clone();
+        Box::new(futures::future::lazy(move || {
+            Dispatcher::setup_missing_resources(&dispatcher, system)
+                .and_then(move |system| create_resumable_future(dispatcher, system, slice))
+        }))
+    }
+
+    // Returns a stream of the results a StreamingSystem sends while it runs. Up to buffer results
+    // are queued before the system blocks waiting for the stream to be read. See SystemStream for
+    // how long the system's resources are held
+    pub fn create_stream<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+        buffer: usize,
+    ) -> SystemStream<T::Output, L>
+    where
+        T: StreamingSystem,
+    {
+        SystemStream::new(dispatcher.clone(), system, buffer)
+    }
+
+    // Queues up a system that decides at runtime which extra resources it needs. The system's
+    // SystemData is acquired first, then it plans, then the extras are acquired and it runs
+    pub fn create_planned_future<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+    ) -> Box<impl futures::Future<Item = T, Error = ()>>
+    where
+        T: PlannedSystem,
+    {
+        Box::new(PlannedSystemFuture::new(dispatcher.clone(), system))
+    }
+
+    // Queues up a system to run. This code will acquire the appropriate resources first, then
+    // run the given system
+    pub fn create_future_with_result<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+    ) -> Box<impl futures::Future<Item = T, Error = ()>>
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        let (_status, future) = Dispatcher::create_future_with_status(dispatcher, system);
+        future
+    }
+
+    // Runs the system, then hands it (along with whatever it kept in its fields while running) to f
+    // and runs the future f returns. This is for pipelines where what runs next depends on what a
+    // system found, i.e. running a system with the targets another system picked. The system's
+    // resources are released before f is called
+    pub fn create_then<T, F, FutureT>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+        f: F,
+    ) -> Box<impl futures::Future<Item = FutureT::Item, Error = ()>>
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+        F: FnOnce(T, Arc<Dispatcher<L>>) -> FutureT + Send + 'static,
+        FutureT: futures::future::IntoFuture<Error = ()>,
+    {
+        use futures::future::Future;
+        let next_dispatcher = dispatcher.clone();
+        Box::new(
+            Dispatcher::create_future_with_result(dispatcher, system)
+                .and_then(move |system| f(system, next_dispatcher)),
+        )
+    }
+
+    // Same as create_future_with_result, but also returns a handle that can be used to observe what
+    // the acquisition is currently blocked on
+    pub fn create_future_with_status<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+    ) -> (
+        AcquireStatusHandle,
+        Box<impl futures::Future<Item = T, Error = ()>>,
+    )
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        let dispatcher = dispatcher.clone();
+        let required_resources = super::RequiredResources::from_system(&system);
+        let mut acquire =
+            super::AcquireResources::<T, L>::new(dispatcher.clone(), required_resources);
+        let status = acquire.status_handle();
+        use futures::Future;
+
+        // Counted until the resources are acquired
+        let estimated_bytes =
+            std::mem::size_of::<super::AcquireResources<T, L>>() + std::mem::size_of::<T>();
+        let reservation = dispatcher.queued_bytes.try_reserve(estimated_bytes);
+        if reservation.is_none() {
+            error!(
+                "Rejected {}, queued futures are over the budget of {:?} bytes",
+                std::any::type_name::<T>(),
+                dispatcher.queued_bytes_budget()
+            );
+        }
+
+        // Resources that shred would default are set up when the future first runs rather than
+        // here, since futures are often created from inside a running system. Setting them up
+        // acquires every resource, so it waits like any other acquisition instead of blocking
+        let future = Box::new(futures::future::lazy(move || {
+            let reservation = match reservation {
+                Some(reservation) => reservation,
+                None => return futures::future::Either::A(futures::future::err(())),
+            };
+
+            futures::future::Either::B(
+                Dispatcher::setup_missing_resources(&dispatcher, system).and_then(move |system| {
+                    acquire.map_err(|_| ()).and_then(move |mut guards| {
+                        drop(reservation);
+                        let system = dispatcher
+                            .run_system_after_fetch(system, || guards.release_snapshots());
+                        drop(guards);
+                        Ok(system)
+                    })
+                }),
+            )
+        }));
+
+        (status, future)
+    }
+
+    // Same as create_future, but if a system created with the same key is still waiting for its
+    // resources, this one is dropped instead of queued. This is for idempotent systems that are
+    // triggered by events (i.e. rebuilding a navmesh), where several triggers before it runs only
+    // need one run. Once a system has its resources, a new trigger queues another run, since the
+    // running one may have already read the state. Resolves to whether the system ran
+    pub fn create_future_coalesced<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        key: u64,
+        system: T,
+    ) -> Box<impl futures::Future<Item = bool, Error = ()>>
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use futures::Future;
+
+        let pending_key = match dispatcher.pending_keys.try_insert(key) {
+            Some(pending_key) => pending_key,
+            None => {
+                trace!(
+                    "Coalesced {} into the pending run for key {}",
+                    std::any::type_name::<T>(),
+                    key
+                );
+                return Box::new(futures::future::Either::A(futures::future::ok(false)));
+            }
+        };
+
+        let dispatcher = dispatcher.clone();
+        let required_resources = super::RequiredResources::from_system(&system);
+        let acquire = super::AcquireResources::<T, L>::new(dispatcher.clone(), required_resources)
+            .map_err(|_| ());
+
+        Box::new(futures::future::Either::B(futures::future::lazy(
+            move || {
+                Dispatcher::setup_missing_resources(&dispatcher, system).and_then(move |system| {
+                    acquire.and_then(move |mut guards| {
+                        drop(pending_key);
+                        dispatcher.run_system_after_fetch(system, || guards.release_snapshots());
+                        drop(guards);
+                        Ok(true)
+                    })
+                })
+            },
+        )))
+    }
+
+    // Same as create_future, but the system runs where it can block (tokio's blocking section, or
+    // async-std's blocking pool) so that a slow, CPU-heavy system doesn't hold up other tasks on
+    // the same thread. The system's resources are still held for the entire time it's running
+    pub fn create_future_blocking<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+    ) -> Box<impl futures::Future<Item = (), Error = ()>>
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use futures::Future;
+
+        let dispatcher = dispatcher.clone();
+        let required_resources = super::RequiredResources::from_system(&system);
+        let acquire = super::AcquireResources::<T, L>::new(dispatcher.clone(), required_resources)
+            .map_err(|_| ());
+
+        Box::new(futures::future::lazy(move || {
+            Dispatcher::setup_missing_resources(&dispatcher, system).and_then(move |system| {
+                acquire.and_then(move |mut guards| {
+                    DefaultRuntime::blocking(move || {
+                        dispatcher.run_system_after_fetch(system, || guards.release_snapshots());
+                        drop(guards);
+                    })
+                })
+            })
+        }))
+    }
+
+    // Queues up a system to run. This code will acquire the appropriate resources first, then
+    // run the given system
+    pub fn create_future<T>(
+        dispatcher: &Arc<Dispatcher<L>>,
+        system: T,
+    ) -> Box<impl futures::Future<Item = (), Error = ()>>
     where
         T: for<'b> shred::System<'b> + Send + 'static,
     {
@@ -169,3 +2750,354 @@ impl Dispatcher {
         Box::new(Dispatcher::create_future_with_result(dispatcher, system).map(|_| ()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DispatchError;
+    use crate::Snapshot;
+
+    struct Counter(u32);
+
+    #[derive(Clone)]
+    struct Settings(u32);
+
+    // Reads a copy of Settings while writing Counter
+    struct SnapshotSystem;
+
+    impl<'a> shred::System<'a> for SnapshotSystem {
+        type SystemData = (Snapshot<Settings>, shred::WriteExpect<'a, Counter>);
+
+        fn run(&mut self, (settings, mut counter): Self::SystemData) {
+            counter.0 += settings.0;
+        }
+    }
+
+    #[test]
+    fn maintain_with_snapshot_system_finishes_frame() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let dispatcher = DispatcherBuilder::new()
+                .insert(Counter(0))
+                .insert(Settings(2))
+                .with_maintain(|world| world.fetch_mut::<Counter>().0 += 1)
+                .build();
+
+            let (world, _) = dispatcher.run_frames(1, |dispatcher| {
+                Dispatcher::create_future(&dispatcher, SnapshotSystem)
+            });
+
+            tx.send(world.fetch::<Counter>().0).unwrap();
+        });
+
+        let counter = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The frame never finished");
+        assert_eq!(counter, 3);
+    }
+
+    // Copies Settings and also writes it, so the snapshot and the write share a lock
+    struct SnapshotAndWriteSystem;
+
+    impl<'a> shred::System<'a> for SnapshotAndWriteSystem {
+        type SystemData = (Snapshot<Settings>, shred::WriteExpect<'a, Settings>);
+
+        fn run(&mut self, (snapshot, mut settings): Self::SystemData) {
+            settings.0 = snapshot.0 + 1;
+        }
+    }
+
+    // Copies Settings and also reads it
+    struct SnapshotAndReadSystem;
+
+    impl<'a> shred::System<'a> for SnapshotAndReadSystem {
+        type SystemData = (
+            Snapshot<Settings>,
+            shred::ReadExpect<'a, Settings>,
+            shred::WriteExpect<'a, Counter>,
+        );
+
+        fn run(&mut self, (snapshot, settings, mut counter): Self::SystemData) {
+            counter.0 = snapshot.0 + settings.0;
+        }
+    }
+
+    #[test]
+    fn snapshot_with_its_source_finishes_frame() {
+        use futures::Future;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let dispatcher = DispatcherBuilder::new()
+                .insert(Counter(0))
+                .insert(Settings(2))
+                .build();
+
+            let (world, _) = dispatcher.run_frames(1, |dispatcher| {
+                let read_dispatcher = dispatcher.clone();
+                Dispatcher::create_future(&dispatcher, SnapshotAndWriteSystem).and_then(move |_| {
+                    Dispatcher::create_future(&read_dispatcher, SnapshotAndReadSystem)
+                })
+            });
+
+            tx.send(world.fetch::<Counter>().0).unwrap();
+        });
+
+        let counter = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The frame never finished");
+        assert_eq!(counter, 6);
+    }
+
+    #[test]
+    fn dispatch_lock_wait_starts_at_first_poll() {
+        use futures::Future;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let dispatcher = DispatcherBuilder::new()
+                .insert(Counter(0))
+                .insert(Settings(2))
+                .with_dispatch_lock_wait_histogram()
+                .build();
+
+            let (world, _) = dispatcher.run_frames(1, |dispatcher| {
+                // Time between creating the future and polling it isn't spent waiting on the lock
+                let future = Dispatcher::create_future(&dispatcher, SnapshotSystem);
+                std::thread::sleep(std::time::Duration::from_millis(50));
+
+                let reset_dispatcher = dispatcher.clone();
+                future.and_then(move |_| {
+                    let histogram = reset_dispatcher.dispatch_lock_wait_histogram();
+                    let long_waits: u64 = (0..histogram.bucket_count())
+                        .filter(|&index| match histogram.bucket_range(index).1 {
+                            Some(max) => max > std::time::Duration::from_millis(50),
+                            None => true,
+                        })
+                        .map(|index| histogram.count(index))
+                        .sum();
+
+                    Dispatcher::create_reset_future(&reset_dispatcher, move |world| {
+                        world.fetch_mut::<Counter>().0 = long_waits as u32
+                    })
+                })
+            });
+
+            tx.send(world.fetch::<Counter>().0).unwrap();
+        });
+
+        let counter = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The frame never finished");
+        assert_eq!(
+            counter, 0,
+            "The sleep before the first poll was counted as a wait"
+        );
+    }
+
+    #[test]
+    fn reset_future_runs_inside_frame() {
+        use futures::Future;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let dispatcher = DispatcherBuilder::new()
+                .insert(Counter(0))
+                .insert(Settings(2))
+                .build();
+
+            let (world, _) = dispatcher.run_frames(1, |dispatcher| {
+                let reset_dispatcher = dispatcher.clone();
+                Dispatcher::create_future(&dispatcher, SnapshotSystem).and_then(move |_| {
+                    Dispatcher::create_reset_future(&reset_dispatcher, |world| {
+                        world.fetch_mut::<Counter>().0 += 10
+                    })
+                })
+            });
+
+            tx.send(world.fetch::<Counter>().0).unwrap();
+        });
+
+        let counter = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The frame never finished");
+        assert_eq!(counter, 12);
+    }
+
+    #[derive(Default)]
+    struct Defaulted(u32);
+
+    // Reads a resource that was never inserted, so shred defaults it during setup
+    struct DefaultedSystem;
+
+    impl<'a> shred::System<'a> for DefaultedSystem {
+        type SystemData = (shred::Read<'a, Defaulted>, shred::WriteExpect<'a, Counter>);
+
+        fn run(&mut self, (defaulted, mut counter): Self::SystemData) {
+            counter.0 += defaulted.0 + 1;
+        }
+    }
+
+    #[test]
+    fn missing_resources_are_set_up_inside_frame() {
+        use futures::Future;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let dispatcher = DispatcherBuilder::new()
+                .insert(Counter(0))
+                .insert(Settings(2))
+                .build();
+
+            let (world, _) = dispatcher.run_frames(1, |dispatcher| {
+                Dispatcher::create_future(&dispatcher, DefaultedSystem)
+                    .join(Dispatcher::create_future(&dispatcher, DefaultedSystem))
+                    .map(|_| ())
+            });
+
+            tx.send(world.fetch::<Counter>().0).unwrap();
+        });
+
+        let counter = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The frame never finished");
+        assert_eq!(counter, 2);
+    }
+
+    #[test]
+    fn reset_keeps_task_ids_unique() {
+        let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter(0)).build());
+
+        let required_resources =
+            || crate::RequiredResources::<()>::new(vec![], vec![ResourceId::new::<Counter>()]);
+        let mut before = crate::AcquireResources::new(dispatcher.clone(), required_resources());
+        dispatcher.reset(|_| {});
+        let mut after = crate::AcquireResources::new(dispatcher.clone(), required_resources());
+
+        assert_ne!(
+            before.status_handle().task_id(),
+            after.status_handle().task_id()
+        );
+    }
+
+    struct IncrementSystem;
+
+    impl<'a> shred::System<'a> for IncrementSystem {
+        type SystemData = shred::WriteExpect<'a, Counter>;
+
+        fn run(&mut self, mut counter: Self::SystemData) {
+            counter.0 += 1;
+        }
+    }
+
+    #[test]
+    fn run_system_waits_for_held_resources() {
+        let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter(0)).build());
+
+        let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &[ResourceId::new::<Counter>()]);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let system_dispatcher = dispatcher.clone();
+        std::thread::spawn(move || {
+            Dispatcher::run_system(&system_dispatcher, IncrementSystem);
+            tx.send(()).unwrap();
+        });
+
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+
+        drop(scope);
+        rx.recv_timeout(std::time::Duration::from_secs(10))
+            .expect("run_system never ran after the resources were released");
+        assert_eq!(
+            dispatcher.read_resource(|counter: &Counter| counter.0),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn replay_skips_dropped_task() {
+        // Tasks 0 and then 1 were granted Counter when this was recorded
+        let events: Vec<_> = (0..2)
+            .map(|task_id| crate::AcquisitionEvent {
+                task_id,
+                resource: "Counter".to_string(),
+                kind: crate::AcquisitionEventKind::Acquire,
+            })
+            .collect();
+        let dispatcher = Arc::new(
+            DispatcherBuilder::new()
+                .insert(Counter(0))
+                .with_replay(crate::AcquisitionReplay::new(&events))
+                .build(),
+        );
+
+        // Task 0 is dropped before it's granted, so task 1 must not wait for it
+        let required_resources =
+            crate::RequiredResources::<()>::new(vec![], vec![ResourceId::new::<Counter>()]);
+        drop(crate::AcquireResources::new(
+            dispatcher.clone(),
+            required_resources,
+        ));
+        let rx = acquire_counter_on_thread(&dispatcher);
+
+        let result = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The replay waited on the dropped task");
+        assert_eq!(result, Ok(()));
+        assert!(dispatcher.replay().unwrap().is_complete());
+    }
+
+    // Starts acquiring Counter on another thread and sends back the result
+    fn acquire_counter_on_thread(
+        dispatcher: &Arc<Dispatcher>,
+    ) -> std::sync::mpsc::Receiver<Result<(), DispatchError>> {
+        use futures::Future;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let required_resources =
+            crate::RequiredResources::<()>::new(vec![], vec![ResourceId::new::<Counter>()]);
+        let acquire = crate::AcquireResources::new(dispatcher.clone(), required_resources);
+        std::thread::spawn(move || tx.send(acquire.wait().map(|_guards| ())).unwrap());
+        rx
+    }
+
+    #[test]
+    fn waiters_fail_when_terminating_with_fail_waiters() {
+        let dispatcher = Arc::new(
+            DispatcherBuilder::new()
+                .insert(Counter(0))
+                .with_contended_shutdown_policy(ContendedShutdownPolicy::FailWaiters)
+                .build(),
+        );
+
+        let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &[ResourceId::new::<Counter>()]);
+        let rx = acquire_counter_on_thread(&dispatcher);
+        dispatcher.end_game_loop();
+
+        let result = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The waiter wasn't failed");
+        assert_eq!(result, Err(DispatchError::Terminated));
+        drop(scope);
+    }
+
+    #[test]
+    fn waiters_wait_for_holder_when_terminating_by_default() {
+        let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter(0)).build());
+
+        let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &[ResourceId::new::<Counter>()]);
+        let rx = acquire_counter_on_thread(&dispatcher);
+        dispatcher.end_game_loop();
+
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+        drop(scope);
+
+        let result = rx
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The waiter never got the resource");
+        assert_eq!(result, Ok(()));
+    }
+}
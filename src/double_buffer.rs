@@ -0,0 +1,131 @@
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use shred::ResourceId;
+
+// The copy of a double buffered resource that systems write, see
+// DispatcherBuilder::insert_double_buffered. Declare it as WriteBack<R>
+pub struct BackBuffer<R> {
+    resource: R,
+}
+
+impl<R> Deref for BackBuffer<R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.resource
+    }
+}
+
+impl<R> DerefMut for BackBuffer<R> {
+    fn deref_mut(&mut self) -> &mut R {
+        &mut self.resource
+    }
+}
+
+// SystemData for writing a double buffered resource. Changes show up in ReadFront at the end of
+// the frame
+pub type WriteBack<'a, R> = shred::WriteExpect<'a, BackBuffer<R>>;
+
+// The copy of a double buffered resource that systems read. It has no lock, readers only take a
+// reference to the current front, which is replaced as a whole at the end of each frame
+pub(super) struct FrontBuffer<R> {
+    // The number of times the front has been replaced, and the front itself
+    front: Mutex<(u64, Arc<R>)>,
+}
+
+impl<R> FrontBuffer<R> {
+    fn get(&self) -> (u64, Arc<R>) {
+        let front = self.front.lock().unwrap();
+        (front.0, front.1.clone())
+    }
+}
+
+// The id of the resource a system declares when it fetches ReadFront<R>
+pub(super) fn front_buffer_resource_id<R: shred::Resource>() -> ResourceId {
+    ResourceId::new::<FrontBuffer<R>>()
+}
+
+pub(super) fn back_buffer_resource_id<R: shred::Resource>() -> ResourceId {
+    ResourceId::new::<BackBuffer<R>>()
+}
+
+pub(super) fn insert_buffers<R: Clone + shred::Resource>(world: &mut shred::World, resource: R) {
+    world.insert(FrontBuffer {
+        front: Mutex::new((0, Arc::new(resource.clone()))),
+    });
+    world.insert(BackBuffer { resource });
+}
+
+// Copies the back buffer to the front. The back buffer must be locked
+fn publish<R: Clone + shred::Resource>(world: &shred::World) {
+    let back = world.fetch::<BackBuffer<R>>();
+    let front = world.fetch::<FrontBuffer<R>>();
+    let published = Arc::new(back.resource.clone());
+    let mut front = front.front.lock().unwrap();
+    *front = (front.0 + 1, published);
+}
+
+type PublishFn = fn(&shred::World);
+
+// A resource inserted with DispatcherBuilder::insert_double_buffered
+#[derive(Clone)]
+pub(super) struct DoubleBufferedResource {
+    pub(super) back_resource_id: ResourceId,
+    pub(super) publish: PublishFn,
+}
+
+impl DoubleBufferedResource {
+    pub(super) fn new<R: Clone + shred::Resource>() -> Self {
+        DoubleBufferedResource {
+            back_resource_id: back_buffer_resource_id::<R>(),
+            publish: publish::<R>,
+        }
+    }
+}
+
+// SystemData for reading a double buffered resource. It never waits for the writer: it sees the
+// resource as it was at the end of the last frame, and keeps seeing that same copy for as long as
+// it's held, even if the front is replaced in the meantime
+pub struct ReadFront<R> {
+    version: u64,
+    front: Arc<R>,
+}
+
+impl<R> ReadFront<R> {
+    // How many times the front had been replaced when this was fetched
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl<R> Deref for ReadFront<R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.front
+    }
+}
+
+impl<'a, R: Clone + shred::Resource> shred::SystemData<'a> for ReadFront<R> {
+    fn setup(_world: &mut shred::World) {}
+
+    fn fetch(world: &'a shred::World) -> Self {
+        let (version, front) = world
+            .try_fetch::<FrontBuffer<R>>()
+            .expect("The resource for a ReadFront was not inserted with insert_double_buffered.")
+            .get();
+
+        ReadFront { version, front }
+    }
+
+    fn reads() -> Vec<ResourceId> {
+        vec![front_buffer_resource_id::<R>()]
+    }
+
+    fn writes() -> Vec<ResourceId> {
+        vec![]
+    }
+}
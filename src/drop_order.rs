@@ -0,0 +1,68 @@
+use shred::ResourceId;
+
+type DropFn = fn(&mut shred::World, ResourceId);
+
+fn drop_resource<R: shred::Resource>(world: &mut shred::World, resource_id: ResourceId) {
+    world.remove_by_id::<R>(resource_id);
+}
+
+// Every resource inserted with the DispatcherBuilder, in the order they were inserted, along with
+// how to drop it. The world can only remove a resource by its type, so this is captured at insert
+// time
+pub(super) struct InsertedResources {
+    resources: Vec<(ResourceId, DropFn)>,
+}
+
+impl InsertedResources {
+    pub(super) fn new() -> Self {
+        InsertedResources { resources: vec![] }
+    }
+
+    pub(super) fn push<R: shred::Resource>(&mut self, resource_id: ResourceId) {
+        self.resources.push((resource_id, drop_resource::<R>));
+    }
+
+    // The given resources first, in that order, then the rest in reverse of the order they were
+    // inserted
+    pub(super) fn into_drop_order(mut self, first: &[ResourceId]) -> ResourceDropOrder {
+        let mut drop_order = Vec::with_capacity(self.resources.len());
+        for resource_id in first {
+            let index = self
+                .resources
+                .iter()
+                .position(|(inserted_id, _)| inserted_id == resource_id)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Resource {:?} was given to with_drop_order but wasn't inserted with the \
+                         DispatcherBuilder, or was given more than once",
+                        resource_id
+                    )
+                });
+            drop_order.push(self.resources.remove(index));
+        }
+
+        drop_order.extend(self.resources.into_iter().rev());
+        ResourceDropOrder {
+            resources: drop_order,
+        }
+    }
+}
+
+// Kept in the world so that drop_world can still find it after the dispatcher is gone
+pub(super) struct ResourceDropOrder {
+    resources: Vec<(ResourceId, DropFn)>,
+}
+
+// Drops a world returned by the dispatcher (i.e. from Dispatcher::enter_game_loop), dropping
+// resources in the order set with DispatcherBuilder::with_drop_order. Just letting the world go
+// out of scope drops its resources in no particular order, which is a problem when one resource's
+// Drop needs another to still be around (i.e. GPU buffers that must be freed before the device).
+// Resources the builder didn't insert (i.e. defaults created by a system's setup) are dropped
+// last, in no particular order
+pub fn drop_world(mut world: shred::World) {
+    if let Some(drop_order) = world.remove::<ResourceDropOrder>() {
+        for (resource_id, drop_fn) in drop_order.resources {
+            drop_fn(&mut world, resource_id);
+        }
+    }
+}
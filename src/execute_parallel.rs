@@ -1,18 +1,45 @@
+use crate::runtime::DefaultRuntime;
+use crate::runtime::Runtime;
+
 type ChildFuture<ErrorT> = dyn futures::future::Future<Item = (), Error = ErrorT> + Send;
 
+// Dropping this cancels the spawned child future it was returned for
+type CancelHandle = futures::sync::oneshot::Sender<()>;
+
+// Spawns a child future. If the returned handle is dropped before the child completes, the child
+// is dropped too, which releases anything it holds (like resource locks). This is how dropping a
+// composite future cancels the children it spawned
+fn spawn_cancellable<F>(future: F) -> CancelHandle
+where
+    F: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+{
+    use futures::Future;
+    let (cancel_tx, cancel_rx) = futures::sync::oneshot::channel();
+    DefaultRuntime::spawn(Box::new(future.select2(cancel_rx).then(|_| Ok(()))));
+    cancel_tx
+}
+
 // Given a list of futures, executes all futures in parallel. The result (whether success or failure)
 // is ignored. This task should always succeed.
 //
 // This is different from tokio's join. It is actually prone to cause deadlocks for the way this
 // crate tries to use it since the futures don't get dropped when they complete. (So any locks they
 // were holding don't get released when the future is done.)
+//
+// If this is dropped before every future completes, the futures that are still running are
+// dropped as well.
 pub struct ExecuteParallel<ErrorT: Send + 'static> {
     state: ExecuteParallelState<ErrorT>,
+    force_sequential: bool,
+    // Only held so that the children are dropped with us
+    _cancel_handles: Vec<CancelHandle>,
 }
 
 enum ExecuteParallelState<ErrorT: Send + 'static> {
     NotStarted(Vec<Box<ChildFuture<ErrorT>>>),
-    Started(Vec<tokio::sync::oneshot::Receiver<Result<(), ErrorT>>>),
+    Started(Vec<futures::sync::oneshot::Receiver<Result<(), ErrorT>>>),
+    // With force_sequential, the futures that haven't completed yet. The front one is running
+    StartedSequential(std::collections::VecDeque<Box<ChildFuture<ErrorT>>>),
     Finished,
 }
 
@@ -20,8 +47,18 @@ impl<ErrorT: Send + 'static> ExecuteParallel<ErrorT> {
     pub fn new(futures: Vec<Box<ChildFuture<ErrorT>>>) -> Self {
         ExecuteParallel {
             state: ExecuteParallelState::NotStarted(futures),
+            force_sequential: false,
+            _cancel_handles: vec![],
         }
     }
+
+    // For debugging: if true, run the futures one at a time in the order they were given instead
+    // of in parallel. If a bug goes away with this set, it likely depends on the order or the
+    // timing of the futures. Like in parallel, each future's result is ignored
+    pub fn with_force_sequential(mut self, force_sequential: bool) -> Self {
+        self.force_sequential = force_sequential;
+        self
+    }
 }
 
 impl<ErrorT: Send + 'static> futures::future::Future for ExecuteParallel<ErrorT> {
@@ -31,13 +68,17 @@ impl<ErrorT: Send + 'static> futures::future::Future for ExecuteParallel<ErrorT>
     fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
         loop {
             match &mut self.state {
+                ExecuteParallelState::NotStarted(futures) if self.force_sequential => {
+                    let futures = std::mem::take(futures);
+                    self.state = ExecuteParallelState::StartedSequential(futures.into());
+                }
                 ExecuteParallelState::NotStarted(futures) => {
-                    let futures = std::mem::replace(futures, Vec::new());
+                    let futures = std::mem::take(futures);
                     let mut receivers = Vec::with_capacity(futures.len());
 
                     // For each future, create a oneshot that will be triggered when that future completes
                     for future in futures {
-                        let (tx, rx) = tokio::sync::oneshot::channel();
+                        let (tx, rx) = futures::sync::oneshot::channel();
 
                         let future = future.then(|result| {
                             // Ignore the result, we don't care if the "owner" future was dropped (this
@@ -46,7 +87,7 @@ impl<ErrorT: Send + 'static> futures::future::Future for ExecuteParallel<ErrorT>
                             Ok(())
                         });
 
-                        tokio::spawn(future);
+                        self._cancel_handles.push(spawn_cancellable(future));
                         receivers.push(rx);
                     }
 
@@ -69,14 +110,219 @@ impl<ErrorT: Send + 'static> futures::future::Future for ExecuteParallel<ErrorT>
                                     return Ok(futures::Async::NotReady)
                                 }
                                 Ok(_) => {
-                                    rx_list.pop().unwrap();
+                                    rx_list.pop();
                                 }
                             },
                         }
                     }
                 }
+                ExecuteParallelState::StartedSequential(futures) => loop {
+                    match futures.front_mut() {
+                        None => {
+                            self.state = ExecuteParallelState::Finished;
+                            return Ok(futures::Async::Ready(()));
+                        }
+                        Some(future) => match future.poll() {
+                            Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                            _ => {
+                                // Drop it now so that anything it holds (like resource locks) is
+                                // released before the next one starts
+                                futures.pop_front();
+                            }
+                        },
+                    }
+                },
                 ExecuteParallelState::Finished => unreachable!(),
             }
         }
     }
 }
+
+type CollectChildFuture<O, ErrorT> = dyn futures::future::Future<Item = O, Error = ErrorT> + Send;
+
+impl<ErrorT: Send + 'static> ExecuteParallel<ErrorT> {
+    // Like new(), but gathers each future's output. See CollectParallel
+    pub fn collect<O: Send + 'static>(
+        futures: Vec<Box<CollectChildFuture<O, ErrorT>>>,
+    ) -> CollectParallel<O, ErrorT> {
+        CollectParallel::new(futures)
+    }
+}
+
+// Given a list of futures, executes all futures in parallel and gathers their outputs in the same
+// order the futures were given (regardless of the order they complete in). Every future is allowed
+// to complete. If any of them fail, the error of the first failing future (by position) is
+// returned. Like ExecuteParallel, dropping this drops any futures that are still running.
+pub struct CollectParallel<O: Send + 'static, ErrorT: Send + 'static> {
+    state: CollectParallelState<O, ErrorT>,
+    // Only held so that the children are dropped with us
+    _cancel_handles: Vec<CancelHandle>,
+}
+
+enum CollectParallelState<O: Send + 'static, ErrorT: Send + 'static> {
+    NotStarted(Vec<Box<CollectChildFuture<O, ErrorT>>>),
+    Started(Vec<CollectParallelSlot<O, ErrorT>>),
+    Finished,
+}
+
+// Either still waiting on the future at this position, or holding its result
+enum CollectParallelSlot<O, ErrorT> {
+    Pending(futures::sync::oneshot::Receiver<Result<O, ErrorT>>),
+    Complete(Result<O, ErrorT>),
+}
+
+impl<O: Send + 'static, ErrorT: Send + 'static> CollectParallel<O, ErrorT> {
+    pub fn new(futures: Vec<Box<CollectChildFuture<O, ErrorT>>>) -> Self {
+        CollectParallel {
+            state: CollectParallelState::NotStarted(futures),
+            _cancel_handles: vec![],
+        }
+    }
+}
+
+impl<O: Send + 'static, ErrorT: Send + 'static> futures::future::Future
+    for CollectParallel<O, ErrorT>
+{
+    type Item = Vec<O>;
+    type Error = ErrorT;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        loop {
+            match &mut self.state {
+                CollectParallelState::NotStarted(futures) => {
+                    let futures = std::mem::take(futures);
+                    let mut slots = Vec::with_capacity(futures.len());
+
+                    // For each future, create a oneshot that will receive its result
+                    for future in futures {
+                        let (tx, rx) = futures::sync::oneshot::channel();
+
+                        let future = future.then(|result| {
+                            // Ignore the send result, we don't care if the "owner" future was
+                            // dropped (this could be considered a cancellation)
+                            let _ = tx.send(result);
+                            Ok(())
+                        });
+
+                        self._cancel_handles.push(spawn_cancellable(future));
+                        slots.push(CollectParallelSlot::Pending(rx));
+                    }
+
+                    self.state = CollectParallelState::Started(slots)
+                }
+                CollectParallelState::Started(slots) => {
+                    // Check every slot that is still pending, results are stored by position
+                    let mut all_complete = true;
+                    for slot in slots.iter_mut() {
+                        if let CollectParallelSlot::Pending(rx) = slot {
+                            match rx.poll() {
+                                Err(_) => {
+                                    panic!("A task has been dropped without first sending a result")
+                                }
+                                Ok(futures::Async::NotReady) => all_complete = false,
+                                Ok(futures::Async::Ready(result)) => {
+                                    *slot = CollectParallelSlot::Complete(result)
+                                }
+                            }
+                        }
+                    }
+
+                    if !all_complete {
+                        return Ok(futures::Async::NotReady);
+                    }
+
+                    let slots = std::mem::take(slots);
+                    self.state = CollectParallelState::Finished;
+
+                    let mut outputs = Vec::with_capacity(slots.len());
+                    for slot in slots {
+                        match slot {
+                            CollectParallelSlot::Complete(Ok(output)) => outputs.push(output),
+                            CollectParallelSlot::Complete(Err(e)) => return Err(e),
+                            CollectParallelSlot::Pending(_) => unreachable!(),
+                        }
+                    }
+
+                    return Ok(futures::Async::Ready(outputs));
+                }
+                CollectParallelState::Finished => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dispatcher;
+    use crate::DispatcherBuilder;
+    use futures::Future;
+    use shred::ResourceId;
+    use std::sync::Arc;
+
+    struct First(u32);
+    struct Second(u32);
+
+    struct FirstSystem;
+
+    impl<'a> shred::System<'a> for FirstSystem {
+        type SystemData = shred::WriteExpect<'a, First>;
+
+        fn run(&mut self, mut first: Self::SystemData) {
+            first.0 += 1;
+        }
+    }
+
+    struct SecondSystem;
+
+    impl<'a> shred::System<'a> for SecondSystem {
+        type SystemData = shred::WriteExpect<'a, Second>;
+
+        fn run(&mut self, mut second: Self::SystemData) {
+            second.0 += 1;
+        }
+    }
+
+    #[test]
+    fn dropping_partway_releases_every_lock() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let dispatcher = Arc::new(
+                DispatcherBuilder::new()
+                    .insert(First(0))
+                    .insert(Second(0))
+                    .build(),
+            );
+            let resource_ids = [ResourceId::new::<First>(), ResourceId::new::<Second>()];
+
+            // FirstSystem waits on this, so it's still running when the ExecuteParallel is dropped
+            let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &resource_ids[..1]);
+
+            let runtime_dispatcher = dispatcher.clone();
+            DefaultRuntime::run(Box::new(futures::future::lazy(move || {
+                let dispatcher = runtime_dispatcher;
+                let mut parallel = ExecuteParallel::new(vec![
+                    Dispatcher::create_future(&dispatcher, FirstSystem),
+                    Dispatcher::create_future(&dispatcher, SecondSystem),
+                ]);
+                assert!(parallel.poll().unwrap().is_not_ready());
+
+                // Give the children time to queue on the lock, then hand it over as it's dropped
+                DefaultRuntime::delay(std::time::Duration::from_millis(50)).map(move |_| {
+                    drop(scope);
+                    drop(parallel);
+                })
+            })));
+
+            drop(Dispatcher::acquire_blocking(
+                &dispatcher,
+                &[],
+                &resource_ids,
+            ));
+            tx.send(()).unwrap();
+        });
+
+        rx.recv_timeout(std::time::Duration::from_secs(10))
+            .expect("A lock was lost when the ExecuteParallel was dropped");
+    }
+}
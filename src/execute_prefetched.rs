@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use shred::ResourceId;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+
+type RunFn = dyn FnOnce() + Send;
+type AcquireFuture = dyn futures::future::Future<Item = Box<RunFn>, Error = ()> + Send;
+
+// A system waiting for its turn in an ExecutePrefetched. Created with
+// Dispatcher::create_prefetchable
+pub struct PrefetchableSystem {
+    resources: Vec<ResourceId>,
+    acquire: Box<AcquireFuture>,
+    // Set once the resources are acquired. Holds the guards, and runs the system when called
+    run: Option<Box<RunFn>>,
+}
+
+impl PrefetchableSystem {
+    pub(super) fn new(resources: Vec<ResourceId>, acquire: Box<AcquireFuture>) -> Self {
+        PrefetchableSystem {
+            resources,
+            acquire,
+            run: None,
+        }
+    }
+
+    fn conflicts_with(&self, other: &PrefetchableSystem) -> bool {
+        self.resources
+            .iter()
+            .any(|resource_id| other.resources.contains(resource_id))
+    }
+
+    fn poll_acquire(&mut self) -> Result<(), ()> {
+        if self.run.is_none() {
+            if let futures::Async::Ready(run) = self.acquire.poll()? {
+                self.run = Some(run);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Runs systems in sequence like ExecuteSequential, but starts acquiring resources for up to depth
+// systems past the one whose turn it is, so that by the time a system's turn comes its resources
+// are often already held. Prefetched resources are held until the system runs, which keeps other
+// tasks off them for longer, so keep depth small (depth 0 is plain sequential execution).
+//
+// Holding resources while waiting is exactly what the rest of the dispatcher avoids, so prefetching
+// is limited to keep it deadlock free:
+// - A system is only prefetched if it shares no resources with any system ahead of it in the
+//   sequence. What it holds is never needed by anything it's waiting for.
+// - Only one ExecutePrefetched per dispatcher (and its scoped dispatchers) prefetches at a time.
+//   Others run plain sequentially until it finishes, so two sequences can't each hold what the
+//   other's current system needs.
+// Everything else that holds resources only does so while running, never while waiting, so the
+// systems ahead of a prefetched one always get their resources eventually. This no longer holds if
+// something else keeps resources while waiting on others (i.e. a Transaction that's held across
+// an acquisition).
+//
+// Only systems within one sequence are prefetched. The next frame's systems can't be, since the
+// end of every frame waits for all resources to run maintenance.
+pub struct ExecutePrefetched<L: AsyncResourceLock = DefaultResourceLock> {
+    dispatcher: Arc<Dispatcher<L>>,
+    depth: usize,
+    systems: VecDeque<PrefetchableSystem>,
+    // Whether we hold the dispatcher's prefetch slot
+    prefetching: bool,
+}
+
+impl<L: AsyncResourceLock> ExecutePrefetched<L> {
+    pub fn new(
+        dispatcher: &Arc<Dispatcher<L>>,
+        depth: usize,
+        systems: Vec<PrefetchableSystem>,
+    ) -> Self {
+        ExecutePrefetched {
+            dispatcher: dispatcher.clone(),
+            depth,
+            systems: systems.into(),
+            prefetching: false,
+        }
+    }
+
+    // Whether the system at index can start acquiring now
+    fn can_prefetch(&mut self, index: usize) -> bool {
+        if self
+            .systems
+            .range(..index)
+            .any(|ahead| ahead.conflicts_with(&self.systems[index]))
+        {
+            return false;
+        }
+
+        if !self.prefetching {
+            self.prefetching = self.dispatcher.try_claim_prefetch_slot();
+        }
+
+        self.prefetching
+    }
+
+    fn release_prefetch_slot(&mut self) {
+        if self.prefetching {
+            self.prefetching = false;
+            self.dispatcher.release_prefetch_slot();
+        }
+    }
+}
+
+impl<L: AsyncResourceLock> futures::future::Future for ExecutePrefetched<L> {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        loop {
+            if self.systems.is_empty() {
+                self.release_prefetch_slot();
+                return Ok(futures::Async::Ready(()));
+            }
+
+            self.systems[0].poll_acquire()?;
+            let window = self.systems.len().min(self.depth + 1);
+            for index in 1..window {
+                if self.systems[index].run.is_none() && self.can_prefetch(index) {
+                    self.systems[index].poll_acquire()?;
+                }
+            }
+
+            match self.systems[0].run.take() {
+                Some(run) => {
+                    self.systems.pop_front();
+                    run();
+                }
+                None => return Ok(futures::Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<L: AsyncResourceLock> Drop for ExecutePrefetched<L> {
+    fn drop(&mut self) {
+        // Give up any prefetched resources before letting another sequence prefetch
+        self.systems.clear();
+        self.release_prefetch_slot();
+    }
+}
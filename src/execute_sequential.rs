@@ -1,39 +1,278 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::deferred_poll;
+use crate::deferred_poll::DeferredChild;
+use crate::deferred_poll::DepthGuard;
+
 type ChildFuture<ErrorT> = dyn futures::future::Future<Item = (), Error = ErrorT> + Send;
+type CollectChildFuture<O, ErrorT> = dyn futures::future::Future<Item = O, Error = ErrorT> + Send;
 
 // Executes all given futures in sequence. The result of one is not passed to the other. If any task
 // results in an error, we stop executing the futures and return that error
+//
+// ExecuteSequentials can be nested as deeply as needed. Past a certain depth, children are polled
+// by the outermost ExecuteSequential instead of by their parent, so the stack doesn't grow with the
+// nesting (see deferred_poll)
 pub struct ExecuteSequential<ErrorT> {
-    futures: Vec<Box<ChildFuture<ErrorT>>>,
-    next_future_index: usize,
+    futures: VecDeque<Box<ChildFuture<ErrorT>>>,
+    // Set while the front future is being polled by the outermost ExecuteSequential
+    deferred: Option<Arc<DeferredChild<ErrorT>>>,
 }
 
 impl<ErrorT> ExecuteSequential<ErrorT> {
     pub fn new(futures: Vec<Box<ChildFuture<ErrorT>>>) -> Self {
         ExecuteSequential {
-            futures,
-            next_future_index: 0,
+            futures: futures.into(),
+            deferred: None,
         }
     }
 }
 
-impl<ErrorT> futures::future::Future for ExecuteSequential<ErrorT> {
-    type Item = ();
-    type Error = ErrorT;
-
-    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+impl<ErrorT: 'static> ExecuteSequential<ErrorT> {
+    fn poll_futures(&mut self, depth: &DepthGuard) -> futures::Poll<(), ErrorT> {
         loop {
-            if self.next_future_index >= self.futures.len() {
-                return Ok(futures::Async::Ready(()));
+            if let Some(deferred) = &self.deferred {
+                match DeferredChild::poll(deferred) {
+                    None => return Ok(futures::Async::NotReady),
+                    Some(result) => {
+                        self.deferred = None;
+                        result?;
+                        continue;
+                    }
+                }
             }
 
-            let result = self.futures[self.next_future_index].poll();
-            match result {
+            let future = match self.futures.front_mut() {
+                Some(future) => future,
+                None => return Ok(futures::Async::Ready(())),
+            };
+
+            if depth.is_too_deep() {
+                let future = self.futures.pop_front().unwrap();
+                self.deferred = Some(DeferredChild::new(future));
+                continue;
+            }
+
+            match future.poll() {
                 Err(e) => return Err(e),
                 Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
                 Ok(futures::Async::Ready(_)) => {
-                    self.next_future_index += 1;
+                    self.futures.pop_front();
                 }
             }
         }
     }
 }
+
+impl<ErrorT: 'static> futures::future::Future for ExecuteSequential<ErrorT> {
+    type Item = ();
+    type Error = ErrorT;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let depth = deferred_poll::enter();
+        loop {
+            let result = self.poll_futures(&depth);
+            if !depth.is_outermost() || !matches!(result, Ok(futures::Async::NotReady)) {
+                return result;
+            }
+
+            // Poll whatever was too deep to poll inline. If any of it finished, go around again so
+            // that its parent moves on to its next future
+            if !deferred_poll::poll_deferred() {
+                return result;
+            }
+        }
+    }
+}
+
+impl<ErrorT> ExecuteSequential<ErrorT> {
+    // Like new(), but gathers each future's output. See CollectSequential
+    pub fn collect<O>(
+        futures: Vec<Box<CollectChildFuture<O, ErrorT>>>,
+    ) -> CollectSequential<O, ErrorT> {
+        CollectSequential::new(futures)
+    }
+}
+
+// Executes all given futures in sequence and gathers their outputs in the order they were given.
+// With Dispatcher::create_future_with_result this hands the systems back, so that systems that keep
+// state between frames can be reused instead of rebuilt. Systems of different types can be
+// collected together as Box<dyn Any + Send> and downcast afterwards. Like ExecuteSequential, the
+// first error stops execution and is returned, and the outputs gathered so far are dropped
+pub struct CollectSequential<O, ErrorT> {
+    futures: VecDeque<Box<CollectChildFuture<O, ErrorT>>>,
+    outputs: Vec<O>,
+}
+
+impl<O, ErrorT> CollectSequential<O, ErrorT> {
+    pub fn new(futures: Vec<Box<CollectChildFuture<O, ErrorT>>>) -> Self {
+        CollectSequential {
+            outputs: Vec::with_capacity(futures.len()),
+            futures: futures.into(),
+        }
+    }
+}
+
+impl<O, ErrorT> futures::future::Future for CollectSequential<O, ErrorT> {
+    type Item = Vec<O>;
+    type Error = ErrorT;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        while let Some(future) = self.futures.front_mut() {
+            let output = futures::try_ready!(future.poll());
+            self.outputs.push(output);
+            self.futures.pop_front();
+        }
+
+        Ok(futures::Async::Ready(std::mem::take(&mut self.outputs)))
+    }
+}
+
+// Whether a SteppableSequential has anything left to run after a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    MoreSteps,
+    Finished,
+}
+
+// Same as ExecuteSequential, but each future is only run when step() is called. This is for
+// debugging, so that the world can be inspected (i.e. with Dispatcher::read_resource) between
+// stages
+pub struct SteppableSequential<ErrorT> {
+    futures: VecDeque<Box<ChildFuture<ErrorT>>>,
+}
+
+impl<ErrorT> SteppableSequential<ErrorT> {
+    pub fn new(futures: Vec<Box<ChildFuture<ErrorT>>>) -> Self {
+        SteppableSequential {
+            futures: futures.into(),
+        }
+    }
+
+    pub fn remaining_steps(&self) -> usize {
+        self.futures.len()
+    }
+
+    // Returns a future that runs the next stage. If there's nothing left to run, it completes
+    // immediately with StepResult::Finished
+    pub fn step(&mut self) -> ExecuteStep<ErrorT> {
+        let future = self.futures.pop_front();
+        let result = if self.futures.is_empty() {
+            StepResult::Finished
+        } else {
+            StepResult::MoreSteps
+        };
+
+        ExecuteStep { future, result }
+    }
+}
+
+// Runs a single stage of a SteppableSequential
+pub struct ExecuteStep<ErrorT> {
+    future: Option<Box<ChildFuture<ErrorT>>>,
+    result: StepResult,
+}
+
+impl<ErrorT> futures::future::Future for ExecuteStep<ErrorT> {
+    type Item = StepResult;
+    type Error = ErrorT;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        if let Some(future) = &mut self.future {
+            futures::try_ready!(future.poll());
+            self.future = None;
+        }
+
+        Ok(futures::Async::Ready(self.result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dispatcher;
+    use crate::DispatcherBuilder;
+    use futures::Future;
+    use shred::ResourceId;
+
+    struct First(u32);
+    struct Second(u32);
+
+    struct FirstSystem;
+
+    impl<'a> shred::System<'a> for FirstSystem {
+        type SystemData = shred::WriteExpect<'a, First>;
+
+        fn run(&mut self, mut first: Self::SystemData) {
+            first.0 += 1;
+        }
+    }
+
+    struct SecondSystem;
+
+    impl<'a> shred::System<'a> for SecondSystem {
+        type SystemData = shred::WriteExpect<'a, Second>;
+
+        fn run(&mut self, mut second: Self::SystemData) {
+            second.0 += 1;
+        }
+    }
+
+    #[test]
+    fn dropping_partway_releases_every_lock() {
+        let dispatcher = Arc::new(
+            DispatcherBuilder::new()
+                .insert(First(0))
+                .insert(Second(0))
+                .build(),
+        );
+        let resource_ids = [ResourceId::new::<First>(), ResourceId::new::<Second>()];
+
+        // SecondSystem runs first, then FirstSystem waits on this
+        let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &resource_ids[..1]);
+        let sequential = ExecuteSequential::new(vec![
+            Dispatcher::create_future(&dispatcher, SecondSystem),
+            Dispatcher::create_future(&dispatcher, FirstSystem),
+        ]);
+
+        // Polls the sequential once and hands it back unfinished
+        let sequential = match sequential.select2(futures::future::ok::<(), ()>(())).wait() {
+            Ok(futures::future::Either::B((_, sequential))) => sequential,
+            _ => panic!("The ExecuteSequential finished while its lock was held"),
+        };
+
+        // The lock is handed to FirstSystem as it's dropped
+        drop(scope);
+        drop(sequential);
+
+        let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &resource_ids);
+        assert_eq!(dispatcher.world().fetch::<Second>().0, 1);
+        assert_eq!(dispatcher.world().fetch::<First>().0, 0);
+        drop(scope);
+    }
+
+    #[test]
+    fn deeply_nested_stages_run() {
+        let dispatcher = Arc::new(
+            DispatcherBuilder::new()
+                .insert(First(0))
+                .insert(Second(0))
+                .build(),
+        );
+
+        // Each stage runs FirstSystem and then the stage nested inside it
+        let mut stage =
+            ExecuteSequential::new(vec![Dispatcher::create_future(&dispatcher, SecondSystem)]);
+        for _ in 0..10_000 {
+            stage = ExecuteSequential::new(vec![
+                Dispatcher::create_future(&dispatcher, FirstSystem),
+                Box::new(stage),
+            ]);
+        }
+
+        stage.wait().unwrap();
+        assert_eq!(dispatcher.world().fetch::<First>().0, 10_000);
+        assert_eq!(dispatcher.world().fetch::<Second>().0, 1);
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+struct ExpediteQueueState {
+    task_ids: VecDeque<u64>,
+    parked_tasks: Vec<futures::task::Task>,
+}
+
+// Acquisitions that have been moved to the front of the line. While anything is in this queue, only
+// the task at the front of it may try to acquire resources, everything else waits until it's done.
+#[derive(Debug)]
+pub(super) struct ExpediteQueue {
+    // Lets acquisitions skip taking the mutex when nothing is expedited, which is almost always
+    len: AtomicUsize,
+    state: Mutex<ExpediteQueueState>,
+}
+
+impl ExpediteQueue {
+    pub(super) fn new() -> Self {
+        ExpediteQueue {
+            len: AtomicUsize::new(0),
+            state: Mutex::new(ExpediteQueueState {
+                task_ids: VecDeque::new(),
+                parked_tasks: vec![],
+            }),
+        }
+    }
+
+    // Expedited tasks are served in the order they were expedited
+    pub(super) fn push(&self, task_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if !state.task_ids.contains(&task_id) {
+            state.task_ids.push_back(task_id);
+            self.len.store(state.task_ids.len(), Ordering::Release);
+        }
+    }
+
+    // Returns true if the given task may try to acquire now. If not, the current task is parked
+    // and will be notified when the expedited task is done
+    pub(super) fn poll_turn(&self, task_id: u64) -> bool {
+        if self.len.load(Ordering::Acquire) == 0 {
+            return true;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match state.task_ids.front() {
+            None => true,
+            Some(front) if *front == task_id => true,
+            Some(_) => {
+                state.parked_tasks.push(futures::task::current());
+                false
+            }
+        }
+    }
+
+    // Called when an acquisition finishes or is dropped
+    pub(super) fn remove(&self, task_id: u64) {
+        if self.len.load(Ordering::Acquire) == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(index) = state.task_ids.iter().position(|id| *id == task_id) {
+            state.task_ids.remove(index);
+            self.len.store(state.task_ids.len(), Ordering::Release);
+            for task in state.parked_tasks.drain(..) {
+                task.notify();
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+// splitmix64's increment. Each decision advances the state by this much and mixes the result
+const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// One in this many dispatch lock grants is given up
+const YIELD_ODDS: u64 = 4;
+
+// Decides when an acquisition that won the dispatch lock should give it up and go to the back of
+// the line, so that tests see grants in a different order than the lock would normally give them.
+// The decisions only depend on the seed and on how many were made before, so a single threaded
+// runtime sees the same interleaving every time it runs with the same seed.
+pub(super) struct FaultInjector {
+    seed: u64,
+    state: AtomicU64,
+}
+
+impl FaultInjector {
+    pub(super) fn new(seed: u64) -> Self {
+        FaultInjector {
+            seed,
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    pub(super) fn should_yield(&self) -> bool {
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        z.is_multiple_of(YIELD_ODDS)
+    }
+
+    // Start over from the seed, so that a reset dispatcher repeats the same interleaving
+    pub(super) fn reset(&self) {
+        self.state.store(self.seed, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+struct FrameCounterState {
+    frame: u64,
+    // Tasks waiting for a frame, keyed by the frame they are waiting for
+    waiting_tasks: BTreeMap<u64, Vec<futures::task::Task>>,
+}
+
+// Counts the frames completed by the game loop and wakes anything waiting for a particular frame
+pub(super) struct FrameCounter {
+    state: Mutex<FrameCounterState>,
+}
+
+impl FrameCounter {
+    pub(super) fn new() -> Self {
+        FrameCounter {
+            state: Mutex::new(FrameCounterState {
+                frame: 0,
+                waiting_tasks: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub(super) fn frame(&self) -> u64 {
+        self.state.lock().unwrap().frame
+    }
+
+    // Called at the end of every frame
+    pub(super) fn advance(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.frame += 1;
+
+        // Everything after the current frame stays, everything up to it is woken
+        let frame = state.frame;
+        let later = state.waiting_tasks.split_off(&(frame + 1));
+        let reached = std::mem::replace(&mut state.waiting_tasks, later);
+        for task in reached.into_values().flatten() {
+            task.notify();
+        }
+    }
+
+    // Anything still waiting keeps waiting, but for frames counted from zero again
+    pub(super) fn reset(&self) {
+        self.state.lock().unwrap().frame = 0;
+    }
+}
+
+// Completes once the game loop has completed the given number of frames
+pub struct AtFrame {
+    frame_counter: Arc<FrameCounter>,
+    frame: u64,
+}
+
+impl AtFrame {
+    pub(super) fn new(frame_counter: Arc<FrameCounter>, frame: u64) -> Self {
+        AtFrame {
+            frame_counter,
+            frame,
+        }
+    }
+}
+
+impl futures::future::Future for AtFrame {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let mut state = self.frame_counter.state.lock().unwrap();
+        if state.frame >= self.frame {
+            Ok(futures::Async::Ready(()))
+        } else {
+            state
+                .waiting_tasks
+                .entry(self.frame)
+                .or_default()
+                .push(futures::task::current());
+            Ok(futures::Async::NotReady)
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// How long a single system took to run
+#[derive(Debug, Clone)]
+pub struct SystemTiming {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+// How long a frame took, and how long each system that ran during it took
+#[derive(Debug, Clone)]
+pub struct FrameTiming {
+    pub frame_index: u64,
+    pub duration: Duration,
+    pub system_timings: Vec<SystemTiming>,
+}
+
+// Frame durations over a run of Dispatcher::run_frames. All zero if no frames ran
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    pub frame_count: usize,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+impl FrameStats {
+    pub(super) fn record_frame(&mut self, duration: Duration) {
+        if self.frame_count == 0 || duration < self.min {
+            self.min = duration;
+        }
+
+        self.max = self.max.max(duration);
+        self.frame_count += 1;
+        self.total += duration;
+        self.mean = self.total / self.frame_count as u32;
+    }
+}
+
+struct FrameHistoryState {
+    frames: VecDeque<FrameTiming>,
+    next_frame_index: u64,
+    current_system_timings: Vec<SystemTiming>,
+}
+
+// Keeps the timings of the last N frames. Older frames are dropped as new ones are recorded so this
+// never grows past its capacity
+pub(super) struct FrameHistory {
+    capacity: usize,
+    state: Mutex<FrameHistoryState>,
+}
+
+impl FrameHistory {
+    pub(super) fn new(capacity: usize) -> Self {
+        FrameHistory {
+            capacity,
+            state: Mutex::new(FrameHistoryState {
+                frames: VecDeque::with_capacity(capacity),
+                next_frame_index: 0,
+                current_system_timings: vec![],
+            }),
+        }
+    }
+
+    // Systems are attributed to whichever frame is in progress when they complete
+    pub(super) fn record_system(&self, name: &'static str, duration: Duration) {
+        self.state
+            .lock()
+            .unwrap()
+            .current_system_timings
+            .push(SystemTiming { name, duration });
+    }
+
+    pub(super) fn end_frame(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let frame_index = state.next_frame_index;
+        state.next_frame_index += 1;
+
+        let system_timings = std::mem::take(&mut state.current_system_timings);
+        if self.capacity == 0 {
+            return;
+        }
+
+        if state.frames.len() >= self.capacity {
+            state.frames.pop_front();
+        }
+
+        state.frames.push_back(FrameTiming {
+            frame_index,
+            duration,
+            system_timings,
+        });
+    }
+
+    pub(super) fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.frames.clear();
+        state.next_frame_index = 0;
+        state.current_system_timings.clear();
+    }
+
+    // Returns the recorded frames, oldest first
+    pub(super) fn frames(&self) -> Vec<FrameTiming> {
+        self.state.lock().unwrap().frames.iter().cloned().collect()
+    }
+}
+
+// Writes frames in the folded stack format that flamegraph tools (inferno, flamegraph.pl) take, one
+// "frame;<system> <microseconds>" line per system, summed over every frame. Time in a frame that
+// wasn't spent in a system is reported on a "frame" line of its own. Systems that run in parallel
+// can add up to more than the frame took, in which case there is none
+pub(super) fn write_folded<W: std::io::Write>(
+    frames: &[FrameTiming],
+    mut writer: W,
+) -> std::io::Result<()> {
+    let mut outside_systems = 0;
+    let mut systems = BTreeMap::new();
+    for frame in frames {
+        let mut in_systems = Duration::default();
+        for system_timing in &frame.system_timings {
+            *systems.entry(system_timing.name).or_insert(0) += system_timing.duration.as_micros();
+            in_systems += system_timing.duration;
+        }
+
+        outside_systems += frame.duration.saturating_sub(in_systems).as_micros();
+    }
+
+    if outside_systems > 0 {
+        writeln!(writer, "frame {}", outside_systems)?;
+    }
+
+    for (name, micros) in systems {
+        writeln!(writer, "frame;{} {}", name, micros)?;
+    }
+
+    Ok(())
+}
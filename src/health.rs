@@ -0,0 +1,68 @@
+use hashbrown::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use shred::ResourceId;
+
+// A resource whose lock has been held by the same task for longer than the healthcheck's
+// threshold
+#[derive(Debug, Clone)]
+pub struct StuckResource {
+    pub resource_name: String,
+    pub task_id: u64,
+    pub held_for: Duration,
+}
+
+// The result of Dispatcher::healthcheck
+#[derive(Debug, Clone, Default)]
+pub struct HealthReport {
+    // How many resource locks were checked
+    pub checked: usize,
+    // How many of those were held at the time of the check
+    pub held: usize,
+    // Held locks that have been held for too long, longest first
+    pub stuck: Vec<StuckResource>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.stuck.is_empty()
+    }
+}
+
+// Which task (and which system) holds each resource's lock, and since when. Only kept when the
+// dispatcher was built with DispatcherBuilder::with_lock_holder_tracking
+pub(super) struct LockHolders {
+    holders: Mutex<HashMap<ResourceId, (u64, &'static str, Instant)>>,
+}
+
+impl LockHolders {
+    pub(super) fn new() -> Self {
+        LockHolders {
+            holders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(super) fn acquired(&self, task_id: u64, system: &'static str, resources: &[ResourceId]) {
+        let now = Instant::now();
+        let mut holders = self.holders.lock().unwrap();
+        for resource_id in resources {
+            holders.insert(resource_id.clone(), (task_id, system, now));
+        }
+    }
+
+    pub(super) fn released(&self, task_id: u64, resources: &[ResourceId]) {
+        let mut holders = self.holders.lock().unwrap();
+        for resource_id in resources {
+            // Only forget our own entry, in case someone else has been recorded since
+            if holders.get(resource_id).map(|(holder, _, _)| *holder) == Some(task_id) {
+                holders.remove(resource_id);
+            }
+        }
+    }
+
+    pub(super) fn holder(&self, resource_id: &ResourceId) -> Option<(u64, &'static str, Instant)> {
+        self.holders.lock().unwrap().get(resource_id).cloned()
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
+struct InFlightState {
+    count: usize,
+    waiting_tasks: Vec<futures::task::Task>,
+}
+
+// Tracks tasks spawned through Dispatcher::spawn so that shutdown can wait for them to finish
+pub(super) struct InFlightTasks {
+    state: Mutex<InFlightState>,
+}
+
+impl InFlightTasks {
+    pub(super) fn new() -> Self {
+        InFlightTasks {
+            state: Mutex::new(InFlightState {
+                count: 0,
+                waiting_tasks: vec![],
+            }),
+        }
+    }
+
+    // Registers a task. It stays in flight until the returned guard is dropped
+    pub(super) fn begin(self: &Arc<Self>) -> InFlightGuard {
+        self.state.lock().unwrap().count += 1;
+        InFlightGuard {
+            in_flight: self.clone(),
+        }
+    }
+
+    pub(super) fn count(&self) -> usize {
+        self.state.lock().unwrap().count
+    }
+}
+
+// Held by a tracked task until it completes (or is dropped)
+pub(super) struct InFlightGuard {
+    in_flight: Arc<InFlightTasks>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut state = self.in_flight.state.lock().unwrap();
+        state.count -= 1;
+        if state.count == 0 {
+            for task in state.waiting_tasks.drain(..) {
+                task.notify();
+            }
+        }
+    }
+}
+
+// Completes once there are no tasks in flight
+pub struct WaitForInFlight {
+    in_flight: Arc<InFlightTasks>,
+}
+
+impl WaitForInFlight {
+    pub(super) fn new(in_flight: Arc<InFlightTasks>) -> Self {
+        WaitForInFlight { in_flight }
+    }
+}
+
+impl futures::future::Future for WaitForInFlight {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let mut state = self.in_flight.state.lock().unwrap();
+        if state.count == 0 {
+            Ok(futures::Async::Ready(()))
+        } else {
+            state.waiting_tasks.push(futures::task::current());
+            Ok(futures::Async::NotReady)
+        }
+    }
+}
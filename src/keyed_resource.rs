@@ -0,0 +1,115 @@
+use std::ops::Deref;
+use std::ops::DerefMut;
+
+use shred::ResourceId;
+
+// The id of one instance of a resource that was inserted with DispatcherBuilder::insert_keyed
+pub fn keyed_resource_id<R: shred::Resource>(key: u64) -> ResourceId {
+    ResourceId::new_with_dynamic_id::<R>(key)
+}
+
+// Declares access to a single keyed resource. Since the key isn't known from the type, a system
+// using KeyedRead or KeyedWrite must return this from System::accessor, i.e.
+// AccessorCow::Owned(KeyedAccessor::write::<Camera>(self.key))
+pub struct KeyedAccessor {
+    resource_id: ResourceId,
+    write: bool,
+}
+
+impl KeyedAccessor {
+    pub fn read<R: shred::Resource>(key: u64) -> Self {
+        KeyedAccessor {
+            resource_id: keyed_resource_id::<R>(key),
+            write: false,
+        }
+    }
+
+    pub fn write<R: shred::Resource>(key: u64) -> Self {
+        KeyedAccessor {
+            resource_id: keyed_resource_id::<R>(key),
+            write: true,
+        }
+    }
+}
+
+impl shred::Accessor for KeyedAccessor {
+    fn try_new() -> Option<Self> {
+        None
+    }
+
+    fn reads(&self) -> Vec<ResourceId> {
+        if self.write {
+            vec![]
+        } else {
+            vec![self.resource_id.clone()]
+        }
+    }
+
+    fn writes(&self) -> Vec<ResourceId> {
+        if self.write {
+            vec![self.resource_id.clone()]
+        } else {
+            vec![]
+        }
+    }
+}
+
+// SystemData for reading one keyed resource
+pub struct KeyedRead<'a, R: shred::Resource> {
+    resource: shred::Fetch<'a, R>,
+}
+
+impl<'a, R: shred::Resource> Deref for KeyedRead<'a, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.resource
+    }
+}
+
+impl<'a, R: shred::Resource> shred::DynamicSystemData<'a> for KeyedRead<'a, R> {
+    type Accessor = KeyedAccessor;
+
+    fn setup(_accessor: &KeyedAccessor, _world: &mut shred::World) {}
+
+    fn fetch(accessor: &KeyedAccessor, world: &'a shred::World) -> Self {
+        KeyedRead {
+            resource: world
+                .try_fetch_by_id(accessor.resource_id.clone())
+                .expect("A keyed resource does not exist for a certain key."),
+        }
+    }
+}
+
+// SystemData for writing one keyed resource
+pub struct KeyedWrite<'a, R: shred::Resource> {
+    resource: shred::FetchMut<'a, R>,
+}
+
+impl<'a, R: shred::Resource> Deref for KeyedWrite<'a, R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.resource
+    }
+}
+
+impl<'a, R: shred::Resource> DerefMut for KeyedWrite<'a, R> {
+    fn deref_mut(&mut self) -> &mut R {
+        &mut self.resource
+    }
+}
+
+impl<'a, R: shred::Resource> shred::DynamicSystemData<'a> for KeyedWrite<'a, R> {
+    type Accessor = KeyedAccessor;
+
+    fn setup(_accessor: &KeyedAccessor, _world: &mut shred::World) {}
+
+    fn fetch(accessor: &KeyedAccessor, world: &'a shred::World) -> Self {
+        KeyedWrite {
+            resource: world
+                .try_fetch_mut_by_id(accessor.resource_id.clone())
+                .expect("A keyed resource does not exist for a certain key."),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use hashbrown::HashMap;
+use hashbrown::HashSet;
+use std::cell::OnceCell;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use shred::ResourceId;
+
+use super::AsyncResourceLock;
+
+type TryLockFn = dyn Fn(&[ResourceId]) -> Result<Option<Box<dyn Send>>, ResourceId> + Send + Sync;
+
+// Takes locks for Lazy in the middle of a system's run (and for WorldView). The dispatcher inserts
+// this into the world when it's built, since SystemData only gets to see the world
+pub(super) struct LazyLocker {
+    try_lock: Box<TryLockFn>,
+}
+
+impl LazyLocker {
+    pub(super) fn new<L: AsyncResourceLock>(
+        dispatch_lock: L,
+        resource_locks: HashMap<ResourceId, L>,
+        lazy_resource_locks: Arc<Mutex<HashMap<ResourceId, L>>>,
+        seqlock_resources: HashSet<ResourceId>,
+    ) -> Self {
+        // Same rule as any other acquisition: hold the dispatch lock while taking the locks, and
+        // take all of them or none
+        let try_lock = move |resource_ids: &[ResourceId]| {
+            let _dispatch_guard = match dispatch_lock.try_lock() {
+                Some(guard) => guard,
+                None => return Ok(None),
+            };
+
+            let mut guards = Vec::with_capacity(resource_ids.len());
+            for resource_id in resource_ids {
+                if seqlock_resources.contains(resource_id) {
+                    continue;
+                }
+
+                let lock = resource_locks
+                    .get(resource_id)
+                    .cloned()
+                    .or_else(|| {
+                        lazy_resource_locks
+                            .lock()
+                            .unwrap()
+                            .get(resource_id)
+                            .cloned()
+                    })
+                    .ok_or_else(|| resource_id.clone())?;
+                match lock.try_lock() {
+                    Some(guard) => guards.push(guard),
+                    None => return Ok(None),
+                }
+            }
+
+            Ok(Some(Box::new(guards) as Box<dyn Send>))
+        };
+
+        LazyLocker {
+            try_lock: Box::new(try_lock),
+        }
+    }
+
+    // Takes the locks if they're all free right now, without queueing on any of them. Err is a
+    // resource that has no lock
+    pub(super) fn try_lock(
+        &self,
+        resource_ids: &[ResourceId],
+    ) -> Result<Option<Box<dyn Send>>, ResourceId> {
+        (self.try_lock)(resource_ids)
+    }
+}
+
+// SystemData for resources that a system declares but rarely touches, i.e.
+// Lazy<'a, Write<'a, Log>>. Nothing is acquired for it before the system runs, so other systems
+// that use the same resources aren't held up by this one. The locks are taken the first time get
+// succeeds, and held until the system's data is dropped.
+//
+// Since the system is already holding its other locks by then, a lazy acquisition never waits
+// on a lock: get tries once to take all of them, through the dispatch lock like any other
+// acquisition, and returns None if any of them is held. Nothing is queued on a lock that can't be
+// taken, so a system can keep trying on later runs. A system must not declare the same resource
+// both normally and lazily.
+//
+// The resources must be inserted with the DispatcherBuilder (they aren't defaulted). Lazy locks
+// aren't reported to on_acquire, the recorder, audit or lock holder tracking, and are left out of
+// schedules and conflict checks.
+pub struct Lazy<'a, A: shred::SystemData<'a>> {
+    world: &'a shred::World,
+    locker: shred::Fetch<'a, LazyLocker>,
+    // Fields drop in order, so the borrow is given back before the locks
+    acquired: OnceCell<(A, Box<dyn Send>)>,
+}
+
+impl<'a, A: shred::SystemData<'a>> Lazy<'a, A> {
+    fn resource_ids() -> Vec<ResourceId> {
+        let mut resource_ids = A::reads();
+        resource_ids.extend(A::writes());
+        resource_ids
+    }
+
+    // Takes the locks if they're all free right now
+    fn try_acquire(&self) -> Option<&A> {
+        if self.acquired.get().is_none() {
+            let guards = self
+                .locker
+                .try_lock(&Self::resource_ids())
+                .expect("The resource for a Lazy does not exist.")?;
+            let _ = self.acquired.set((A::fetch(self.world), guards));
+        }
+
+        self.acquired.get().map(|(access, _)| access)
+    }
+
+    // Whether the locks have been taken yet
+    pub fn is_acquired(&self) -> bool {
+        self.acquired.get().is_some()
+    }
+
+    // Returns the data if it's already acquired or its locks are free right now
+    pub fn get(&self) -> Option<&A> {
+        self.try_acquire()
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut A> {
+        self.try_acquire()?;
+        self.acquired.get_mut().map(|(access, _)| access)
+    }
+}
+
+impl<'a, A: shred::SystemData<'a>> shred::SystemData<'a> for Lazy<'a, A> {
+    fn setup(_world: &mut shred::World) {}
+
+    fn fetch(world: &'a shred::World) -> Self {
+        Lazy {
+            world,
+            locker: world
+                .try_fetch::<LazyLocker>()
+                .expect("Lazy can only be used in systems run by a Dispatcher."),
+            acquired: OnceCell::new(),
+        }
+    }
+
+    // Nothing is declared, so nothing is acquired up front
+    fn reads() -> Vec<ResourceId> {
+        vec![]
+    }
+
+    fn writes() -> Vec<ResourceId> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dispatcher;
+    use crate::DispatcherBuilder;
+
+    struct Counter(u32);
+
+    // Remembers whether the lazy write could be taken
+    struct LazySystem {
+        acquired: bool,
+    }
+
+    impl<'a> shred::System<'a> for LazySystem {
+        type SystemData = Lazy<'a, shred::WriteExpect<'a, Counter>>;
+
+        fn run(&mut self, mut counter: Self::SystemData) {
+            self.acquired = match counter.get_mut() {
+                Some(counter) => {
+                    counter.0 += 1;
+                    true
+                }
+                None => false,
+            };
+        }
+    }
+
+    #[test]
+    fn get_gives_up_while_held() {
+        let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter(0)).build());
+        let counter_id = ResourceId::new::<Counter>();
+
+        let scope =
+            Dispatcher::acquire_blocking(&dispatcher, &[], std::slice::from_ref(&counter_id));
+        let system = Dispatcher::run_system(&dispatcher, LazySystem { acquired: true });
+        assert!(!system.acquired);
+        drop(scope);
+
+        // Giving up didn't leave anything queued on the lock
+        let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &[counter_id]);
+        drop(scope);
+
+        let system = Dispatcher::run_system(&dispatcher, LazySystem { acquired: false });
+        assert!(system.acquired);
+    }
+}
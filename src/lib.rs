@@ -1,15 +1,175 @@
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "metrics")]
+mod access_counts;
 mod acquire_resources;
+mod acquisition_order;
+mod acquisition_recorder;
+#[cfg(feature = "audit")]
+mod audit;
+mod available_resources;
+mod budgeted_stage;
+mod coalesce;
+mod contention;
+mod cross_dispatcher;
+mod deferred_poll;
+mod dispatch_gate;
+mod dispatch_lock_histogram;
 mod dispatcher;
+mod double_buffer;
+mod drop_order;
 mod execute_parallel;
+mod execute_prefetched;
 mod execute_sequential;
+mod expedite;
+#[cfg(feature = "fault-injection")]
+mod fault_injection;
+mod frame_counter;
+mod frame_history;
+mod health;
+mod in_flight;
+mod keyed_resource;
+mod lazy_resource;
+mod log_config;
+mod loop_handle;
+mod manual_loop;
+#[cfg(feature = "metrics")]
+mod phase;
+mod planned_system;
+mod queued_bytes;
+mod replicate;
 mod required_resources;
+mod resource_bundle;
+mod resource_dependencies;
+mod resource_lock;
+mod resource_policy;
+mod resource_scope;
+mod resource_waiters;
+mod resumable_system;
+mod runtime;
+mod schedule;
+mod schedule_explanation;
+mod scoped_dispatcher;
+mod seed;
+mod seqlock;
+mod snapshot;
+mod streaming_system;
+mod system_set;
+mod transaction;
+mod typed_dispatcher_builder;
+mod weak_dispatcher;
+mod world_view;
+mod write_versions;
 
 pub use acquire_resources::AcquireResources;
+pub use acquire_resources::AcquireStatus;
+pub use acquire_resources::AcquireStatusHandle;
+pub use acquire_resources::CriticalSection;
+pub use acquire_resources::DispatchError;
+pub use acquire_resources::ExternalWaker;
+pub use acquisition_order::AcquisitionOrder;
+pub use acquisition_recorder::AcquisitionEvent;
+pub use acquisition_recorder::AcquisitionEventKind;
+pub use acquisition_recorder::AcquisitionRecorder;
+pub use acquisition_recorder::AcquisitionReplay;
+#[cfg(feature = "audit")]
+pub use audit::AuditRecord;
+pub use available_resources::AvailableResources;
+pub use budgeted_stage::BudgetedStage;
+pub use budgeted_stage::ExecuteBudgeted;
+pub use contention::BlockingSystem;
+pub use contention::FrameContention;
+pub use contention::ResourceContention;
+pub use cross_dispatcher::CrossAcquireResources;
+pub use cross_dispatcher::CrossAcquiredResourcesLockGuards;
+pub use cross_dispatcher::CrossDispatcher;
+pub use cross_dispatcher::CrossDispatcherRequest;
+pub use dispatch_lock_histogram::DispatchLockWaitHistogram;
+pub use dispatcher::ContendedShutdownPolicy;
 pub use dispatcher::Dispatcher;
 pub use dispatcher::DispatcherBuilder;
+pub use dispatcher::DuplicateResource;
+pub use dispatcher::DynamicSystemFn;
+pub use dispatcher::LockState;
+pub use dispatcher::LoopStatus;
+pub use dispatcher::ShutdownPolicy;
+pub use double_buffer::BackBuffer;
+pub use double_buffer::ReadFront;
+pub use double_buffer::WriteBack;
+pub use drop_order::drop_world;
+pub use execute_parallel::CollectParallel;
 pub use execute_parallel::ExecuteParallel;
+pub use execute_prefetched::ExecutePrefetched;
+pub use execute_prefetched::PrefetchableSystem;
+pub use execute_sequential::CollectSequential;
 pub use execute_sequential::ExecuteSequential;
+pub use execute_sequential::ExecuteStep;
+pub use execute_sequential::StepResult;
+pub use execute_sequential::SteppableSequential;
+pub use frame_counter::AtFrame;
+pub use frame_history::FrameStats;
+pub use frame_history::FrameTiming;
+pub use frame_history::SystemTiming;
+pub use health::HealthReport;
+pub use health::StuckResource;
+pub use in_flight::WaitForInFlight;
+pub use keyed_resource::keyed_resource_id;
+pub use keyed_resource::KeyedAccessor;
+pub use keyed_resource::KeyedRead;
+pub use keyed_resource::KeyedWrite;
+pub use lazy_resource::Lazy;
+pub use log_config::AcquireTransition;
+pub use log_config::LogConfig;
+pub use loop_handle::LoopHandle;
+pub use manual_loop::FrameResult;
+pub use manual_loop::ManualLoop;
+#[cfg(feature = "metrics")]
+pub use phase::PhaseMetrics;
+pub use planned_system::ExtraAccess;
+pub use planned_system::ExtraResources;
+pub use planned_system::PlannedSystem;
+pub use planned_system::PlannedSystemFuture;
+pub use replicate::Replicate;
 pub use required_resources::RequiredResources;
+pub use required_resources::ResourceIdList;
+pub use resource_bundle::ResourceBundle;
+pub use resource_dependencies::DependsOn;
+pub use resource_dependencies::ResourceDeps;
+pub use resource_lock::AsyncResourceLock;
+pub use resource_lock::DefaultResourceLock;
+pub use resource_lock::SemaphoreLock;
+pub use resource_lock::SemaphoreLockGuard;
+pub use resource_policy::ResourceLockPolicy;
+pub use resource_scope::ResourceScope;
+pub use resumable_system::ResumableSystem;
+pub use resumable_system::SystemProgress;
+pub use resumable_system::YieldToken;
+#[cfg(feature = "async-std-runtime")]
+pub use runtime::AsyncStdRuntime;
+pub use runtime::DefaultRuntime;
+pub use runtime::Runtime;
+pub use runtime::RuntimeFuture;
+#[cfg(feature = "tokio-runtime")]
+pub use runtime::TokioRuntime;
+pub use schedule::Schedule;
+pub use schedule::ScheduleHint;
+pub use schedule::SystemId;
+pub use schedule_explanation::ExplainedSystem;
+pub use schedule_explanation::LevelExplanation;
+pub use schedule_explanation::ScheduleExplanation;
+pub use schedule_explanation::SystemConflict;
+pub use scoped_dispatcher::ScopedDispatcher;
+pub use seqlock::SeqLock;
+pub use snapshot::snapshot_resource_id;
+pub use snapshot::Snapshot;
+pub use streaming_system::StreamSender;
+pub use streaming_system::StreamingSystem;
+pub use streaming_system::SystemStream;
+pub use system_set::SystemSet;
+pub use transaction::Transaction;
+pub use typed_dispatcher_builder::MissingResource;
+pub use typed_dispatcher_builder::TypedDispatcherBuilder;
+pub use weak_dispatcher::WeakDispatcher;
+pub use world_view::WorldView;
+pub use write_versions::ReacquireGuard;
@@ -0,0 +1,115 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+use super::AcquireStatus;
+
+// Which AcquireStatus an acquisition moved to, without the resource it's about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AcquireTransition {
+    WaitForDispatch,
+    WaitForResource,
+    TimedOut,
+    Terminated,
+    Finished,
+}
+
+const TRANSITION_COUNT: usize = 5;
+
+impl AcquireTransition {
+    fn of(status: &AcquireStatus) -> Self {
+        match status {
+            AcquireStatus::WaitForDispatch => AcquireTransition::WaitForDispatch,
+            AcquireStatus::WaitForResource(_) => AcquireTransition::WaitForResource,
+            AcquireStatus::TimedOut(_) => AcquireTransition::TimedOut,
+            AcquireStatus::Terminated => AcquireTransition::Terminated,
+            AcquireStatus::Finished => AcquireTransition::Finished,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TransitionLog {
+    level: Option<log::Level>,
+    sample_every: u64,
+}
+
+// Which acquisition state transitions are logged, at what level and how often. Passed to
+// DispatcherBuilder::with_log_config. Nothing is logged unless it's turned on here. This is on top
+// of the trace logs, which stay as they are and can be filtered out with the logger as usual
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    transitions: [TransitionLog; TRANSITION_COUNT],
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig::new()
+    }
+}
+
+impl LogConfig {
+    pub fn new() -> Self {
+        LogConfig {
+            transitions: [TransitionLog {
+                level: None,
+                sample_every: 1,
+            }; TRANSITION_COUNT],
+        }
+    }
+
+    // Log every acquisition that moves to transition at level
+    pub fn with_level(mut self, transition: AcquireTransition, level: log::Level) -> Self {
+        self.transitions[transition as usize].level = Some(level);
+        self
+    }
+
+    // Only log one in every n of the transitions to transition, for ones that happen too often to
+    // log them all (i.e. WaitForDispatch). The first one is always logged
+    pub fn with_sampling(mut self, transition: AcquireTransition, n: u64) -> Self {
+        assert!(n > 0, "Can't log one in every 0 transitions");
+        self.transitions[transition as usize].sample_every = n;
+        self
+    }
+}
+
+// Logs transitions as set up by a LogConfig, counting them for sampling
+pub(super) struct TransitionLogger {
+    config: LogConfig,
+    counts: [AtomicU64; TRANSITION_COUNT],
+}
+
+impl TransitionLogger {
+    pub(super) fn new(config: LogConfig) -> Self {
+        TransitionLogger {
+            config,
+            counts: Default::default(),
+        }
+    }
+
+    pub(super) fn log(&self, task_id: u64, system_name: &'static str, status: &AcquireStatus) {
+        let transition = AcquireTransition::of(status);
+        let transition_log = self.config.transitions[transition as usize];
+        let level = match transition_log.level {
+            Some(level) if log_enabled!(level) => level,
+            _ => return,
+        };
+
+        let count = self.counts[transition as usize].fetch_add(1, Ordering::Relaxed);
+        if !count.is_multiple_of(transition_log.sample_every) {
+            return;
+        }
+
+        if transition_log.sample_every > 1 {
+            log!(
+                level,
+                "<{}> {:?} for {} (1 in {})",
+                task_id,
+                status,
+                system_name,
+                transition_log.sample_every
+            );
+        } else {
+            log!(level, "<{}> {:?} for {}", task_id, status, system_name);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::thread::JoinHandle;
+
+// A game loop running on its own thread, returned by Dispatcher::enter_game_loop_detached.
+// Dropping the handle leaves the loop running until something calls end_game_loop, and the world
+// is dropped with the thread
+pub struct LoopHandle {
+    stop: Option<futures::sync::oneshot::Sender<()>>,
+    thread: JoinHandle<shred::World>,
+}
+
+impl LoopHandle {
+    pub(super) fn new(
+        stop: futures::sync::oneshot::Sender<()>,
+        thread: JoinHandle<shred::World>,
+    ) -> Self {
+        LoopHandle {
+            stop: Some(stop),
+            thread,
+        }
+    }
+
+    // Ends the loop the same way end_game_loop does: the current frame finishes and then the loop
+    // waits for tasks spawned through the dispatcher. Doesn't wait for any of that, use join
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            // The loop may have already ended on its own
+            let _ = stop.send(());
+        }
+    }
+
+    // Whether the loop has ended and the world is ready to be taken with join
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    // Blocks until the loop ends and returns the world. This doesn't stop the loop, call stop
+    // first unless it ends on its own. If the loop panicked, the panic is resumed here
+    pub fn join(self) -> shred::World {
+        match self.thread.join() {
+            Ok(world) => world,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use super::WaitForInFlight;
+
+type FrameFuture = dyn futures::future::Future<Item = Duration, Error = ()> + Send;
+type WakeCallbackFn = dyn Fn() + Send + Sync;
+
+// What ManualLoop::poll_once got done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameResult {
+    // The current frame is waiting on something. See ManualLoop::is_woken
+    Pending,
+
+    // A frame finished and took this long. The next poll_once starts the next one
+    FrameFinished(Duration),
+
+    // end_game_loop was called (or a frame failed) and everything spawned through the dispatcher
+    // has finished. Take the world back with ManualLoop::into_world
+    Ended,
+}
+
+struct Waker {
+    woken: AtomicBool,
+    on_wake: Option<Box<WakeCallbackFn>>,
+}
+
+impl futures::executor::Notify for Waker {
+    fn notify(&self, _id: usize) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(on_wake) = &self.on_wake {
+            (on_wake)();
+        }
+    }
+}
+
+enum ManualLoopState {
+    // Between frames
+    Idle,
+    Frame(futures::executor::Spawn<Box<FrameFuture>>),
+    WaitForInFlight(futures::executor::Spawn<WaitForInFlight>),
+    Ended,
+}
+
+// A game loop that's driven by calling poll_once from some other loop (i.e. a winit event loop)
+// instead of by a runtime. Returned by Dispatcher::into_manual_loop.
+//
+// Each poll_once makes as much progress on the current frame as it can without blocking. Frames
+// that are waiting on something are woken the same way as on a runtime, which sets is_woken and
+// calls the callback given to with_wake_callback, so the outer loop knows to poll again. Anything
+// that spawns (ExecuteParallel, Dispatcher::spawn) or uses timers (DefaultRuntime::delay) still
+// needs a runtime, so frames driven this way should stick to ExecuteSequential and plain systems
+pub struct ManualLoop<F, L: AsyncResourceLock = DefaultResourceLock> {
+    dispatcher: Arc<Dispatcher<L>>,
+    f: F,
+    state: ManualLoopState,
+    waker: Arc<Waker>,
+}
+
+impl<F, FutureT, L> ManualLoop<F, L>
+where
+    L: AsyncResourceLock,
+    F: Fn(Arc<Dispatcher<L>>) -> FutureT + Copy + Send + 'static,
+    FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+{
+    pub(super) fn new(dispatcher: Arc<Dispatcher<L>>, f: F) -> Self {
+        dispatcher.set_loop_running(true);
+        ManualLoop {
+            dispatcher,
+            f,
+            state: ManualLoopState::Idle,
+            waker: Arc::new(Waker {
+                // The first frame hasn't started yet, so it's ready to be polled
+                woken: AtomicBool::new(true),
+                on_wake: None,
+            }),
+        }
+    }
+
+    // Call f whenever the current frame is woken, i.e. to post an event that gets the outer loop
+    // to call poll_once. f may be called from any thread
+    pub fn with_wake_callback<W>(mut self, f: W) -> Self
+    where
+        W: Fn() + Send + Sync + 'static,
+    {
+        self.waker = Arc::new(Waker {
+            woken: AtomicBool::new(self.waker.woken.load(Ordering::Acquire)),
+            on_wake: Some(Box::new(f)),
+        });
+        self
+    }
+
+    pub fn dispatcher(&self) -> &Arc<Dispatcher<L>> {
+        &self.dispatcher
+    }
+
+    // Whether the frame has been woken since the last poll_once, meaning that polling again will
+    // make progress
+    pub fn is_woken(&self) -> bool {
+        self.waker.woken.load(Ordering::Acquire)
+    }
+
+    // Makes progress on the current frame, starting one if there isn't one in progress. Returns as
+    // soon as the frame finishes or can't make any more progress
+    pub fn poll_once(&mut self) -> FrameResult {
+        self.waker.woken.store(false, Ordering::Release);
+        loop {
+            match &mut self.state {
+                ManualLoopState::Idle => {
+                    self.state = if self.dispatcher.should_terminate() {
+                        let wait_for_in_flight = self.dispatcher.wait_for_in_flight();
+                        ManualLoopState::WaitForInFlight(futures::executor::spawn(
+                            wait_for_in_flight,
+                        ))
+                    } else {
+                        let frame = Dispatcher::frame(&self.dispatcher, self.f);
+                        ManualLoopState::Frame(futures::executor::spawn(Box::new(frame)))
+                    };
+                }
+                ManualLoopState::Frame(frame) => {
+                    match frame.poll_future_notify(&self.waker, 0) {
+                        Ok(futures::Async::NotReady) => return FrameResult::Pending,
+                        Ok(futures::Async::Ready(frame_duration)) => {
+                            self.state = ManualLoopState::Idle;
+                            return FrameResult::FrameFinished(frame_duration);
+                        }
+                        // Same as the game loop, a failed frame ends it
+                        Err(()) => {
+                            self.dispatcher.end_game_loop();
+                            self.state = ManualLoopState::Idle;
+                        }
+                    }
+                }
+                ManualLoopState::WaitForInFlight(wait_for_in_flight) => {
+                    match wait_for_in_flight.poll_future_notify(&self.waker, 0) {
+                        Ok(futures::Async::NotReady) => return FrameResult::Pending,
+                        _ => {
+                            self.dispatcher.set_loop_running(false);
+                            self.state = ManualLoopState::Ended;
+                        }
+                    }
+                }
+                ManualLoopState::Ended => return FrameResult::Ended,
+            }
+        }
+    }
+
+    // Returns the world once poll_once has returned FrameResult::Ended. Panics if it hasn't
+    pub fn into_world(self) -> shred::World {
+        assert!(
+            matches!(self.state, ManualLoopState::Ended),
+            "ManualLoop::into_world was called before the loop ended"
+        );
+
+        Dispatcher::into_world(self.dispatcher)
+    }
+}
@@ -0,0 +1,75 @@
+use hashbrown::HashMap;
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::time::Duration;
+
+thread_local! {
+    // The phase given to Dispatcher::phase on this thread, if we're inside one
+    static CURRENT_PHASE: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+// The phase that acquisitions created on this thread right now belong to
+pub(super) fn current_phase() -> Option<&'static str> {
+    CURRENT_PHASE.with(|phase| phase.get())
+}
+
+// Tags everything f creates with the phase, putting back whatever phase was set before (phases
+// can be nested, the innermost one wins)
+pub(super) fn with_phase<F, RetT>(phase: &'static str, f: F) -> RetT
+where
+    F: FnOnce() -> RetT,
+{
+    struct RestorePhase(Option<&'static str>);
+
+    impl Drop for RestorePhase {
+        fn drop(&mut self) {
+            CURRENT_PHASE.with(|phase| phase.set(self.0));
+        }
+    }
+
+    let _restore = RestorePhase(CURRENT_PHASE.with(|current| current.replace(Some(phase))));
+    f()
+}
+
+// Acquisition metrics for every acquisition created within one phase, see Dispatcher::phase
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhaseMetrics {
+    // How many acquisitions finished
+    pub acquisitions: u64,
+    // How many times an acquisition had to wait for a resource that something else was holding
+    pub resource_waits: u64,
+    // The time from each acquisition's first poll until it had its resources, summed
+    pub total_wait: Duration,
+}
+
+pub(super) struct PhaseMetricsTracker {
+    phases: Mutex<HashMap<&'static str, PhaseMetrics>>,
+}
+
+impl PhaseMetricsTracker {
+    pub(super) fn new() -> Self {
+        PhaseMetricsTracker {
+            phases: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(super) fn resource_wait(&self, phase: &'static str) {
+        self.phases
+            .lock()
+            .unwrap()
+            .entry(phase)
+            .or_default()
+            .resource_waits += 1;
+    }
+
+    pub(super) fn acquired(&self, phase: &'static str, wait: Duration) {
+        let mut phases = self.phases.lock().unwrap();
+        let metrics = phases.entry(phase).or_default();
+        metrics.acquisitions += 1;
+        metrics.total_wait += wait;
+    }
+
+    pub(super) fn metrics(&self) -> HashMap<&'static str, PhaseMetrics> {
+        self.phases.lock().unwrap().clone()
+    }
+}
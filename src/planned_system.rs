@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use shred::ResourceId;
+
+use super::acquire_resources::try_take_locks;
+use super::acquire_resources::AcquiredResourcesLockGuards;
+use super::acquire_resources::TryTakeLocksResult;
+use super::AcquireResources;
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use super::RequiredResources;
+use crate::resource_lock::release_when_available;
+
+// Additional resources a PlannedSystem needs for a particular run
+#[derive(Debug, Clone, Default)]
+pub struct ExtraResources {
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+}
+
+impl ExtraResources {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn read<R: shred::Resource>(mut self) -> Self {
+        self.reads.push(ResourceId::new::<R>());
+        self
+    }
+
+    pub fn write<R: shred::Resource>(mut self) -> Self {
+        self.writes.push(ResourceId::new::<R>());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reads.is_empty() && self.writes.is_empty()
+    }
+
+    fn contains(&self, resource_id: &ResourceId) -> bool {
+        self.reads.contains(resource_id) || self.writes.contains(resource_id)
+    }
+
+    // Drops anything that is already held by the system's base resources. The locks are exclusive
+    // so it doesn't matter if the base holds it for reading or writing
+    fn without(mut self, base_reads: &[ResourceId], base_writes: &[ResourceId]) -> Self {
+        let held = |resource_id: &ResourceId| {
+            base_reads.contains(resource_id) || base_writes.contains(resource_id)
+        };
+        self.reads.retain(|resource_id| !held(resource_id));
+        self.writes.retain(|resource_id| !held(resource_id));
+        self
+    }
+}
+
+// Gives a PlannedSystem access to the extra resources it asked for (and nothing else)
+pub struct ExtraAccess<'a> {
+    world: &'a shred::World,
+    extras: &'a ExtraResources,
+}
+
+impl<'a> ExtraAccess<'a> {
+    // Returns None if R wasn't requested in the plan
+    pub fn read<R: shred::Resource>(&self) -> Option<shred::Fetch<'a, R>> {
+        if self.extras.contains(&ResourceId::new::<R>()) {
+            self.world.try_fetch::<R>()
+        } else {
+            None
+        }
+    }
+
+    // Returns None if R wasn't requested as a write in the plan
+    pub fn write<R: shred::Resource>(&self) -> Option<shred::FetchMut<'a, R>> {
+        if self.extras.writes.contains(&ResourceId::new::<R>()) {
+            self.world.try_fetch_mut::<R>()
+        } else {
+            None
+        }
+    }
+}
+
+// A system that only sometimes needs some of its resources. The SystemData declares the resources
+// that are always needed. Once those are held, plan() decides what else is needed, and then
+// run_with_extras() is called once the extras are held too. System::run is only called through the
+// default run_with_extras.
+pub trait PlannedSystem: for<'b> shred::System<'b> + Send + 'static {
+    fn plan(&self, data: <Self as shred::System<'_>>::SystemData) -> ExtraResources;
+
+    fn run_with_extras<'a>(
+        &mut self,
+        data: <Self as shred::System<'a>>::SystemData,
+        _extras: ExtraAccess<'a>,
+    ) {
+        self.run(data);
+    }
+}
+
+enum PlannedSystemState<T, L: AsyncResourceLock> {
+    // Acquiring the resources in the system's SystemData
+    AcquireBase(Box<AcquireResources<T, L>>),
+
+    // Holding the base resources and waiting for our turn to try to take the extras
+    AcquireExtras(Box<AcquiredResourcesLockGuards<T, L>>, ExtraResources, L),
+
+    // We couldn't get an extra resource, so everything was released. Once this lock is available we
+    // start over, since the plan might be different by then
+    WaitForExtra(L),
+
+    // The system has run
+    Finished,
+}
+
+// Acquires a PlannedSystem's base resources, plans, acquires the extras, and runs it. If the extras
+// can't all be taken, every lock is released before waiting so that a task is never waiting while
+// holding something (which is what keeps the dispatcher deadlock-free)
+pub struct PlannedSystemFuture<T, L: AsyncResourceLock = DefaultResourceLock> {
+    dispatcher: Arc<Dispatcher<L>>,
+    system: Option<T>,
+    state: PlannedSystemState<T, L>,
+}
+
+impl<T, L: AsyncResourceLock> Drop for PlannedSystemFuture<T, L> {
+    fn drop(&mut self) {
+        // We may be queued on this lock, so it needs to be handed back once it's released
+        match std::mem::replace(&mut self.state, PlannedSystemState::Finished) {
+            PlannedSystemState::AcquireExtras(_, _, lock)
+            | PlannedSystemState::WaitForExtra(lock) => release_when_available(lock),
+            _ => {}
+        }
+    }
+}
+
+impl<T: PlannedSystem, L: AsyncResourceLock> PlannedSystemFuture<T, L> {
+    pub fn new(dispatcher: Arc<Dispatcher<L>>, system: T) -> Self {
+        let required_resources = RequiredResources::from_system(&system);
+        let acquire = AcquireResources::new(dispatcher.clone(), required_resources);
+        PlannedSystemFuture {
+            dispatcher,
+            system: Some(system),
+            state: PlannedSystemState::AcquireBase(Box::new(acquire)),
+        }
+    }
+
+    fn plan(&self) -> ExtraResources {
+        use shred::Accessor;
+        use shred::DynamicSystemData;
+
+        let system = self.system.as_ref().unwrap();
+        let world = self.dispatcher.world();
+        let data = <T as shred::System>::SystemData::fetch(&system.accessor(), &world);
+        let extras = system.plan(data);
+
+        let accessor = system.accessor();
+        extras.without(&accessor.reads(), &accessor.writes())
+    }
+
+    fn run(&mut self, extras: &ExtraResources) {
+        use shred::DynamicSystemData;
+
+        let start = std::time::Instant::now();
+        let system = self.system.as_mut().unwrap();
+        let world = self.dispatcher.world();
+        let data = <T as shred::System>::SystemData::fetch(&system.accessor(), &world);
+        system.run_with_extras(
+            data,
+            ExtraAccess {
+                world: &world,
+                extras,
+            },
+        );
+
+        self.dispatcher
+            .record_system_timing(std::any::type_name::<T>(), start.elapsed());
+    }
+}
+
+impl<T: PlannedSystem, L: AsyncResourceLock> futures::future::Future for PlannedSystemFuture<T, L> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        loop {
+            match &mut self.state {
+                PlannedSystemState::AcquireBase(acquire) => {
+                    let base_guards = Box::new(futures::try_ready!(acquire.poll().map_err(|_| ())));
+                    let extras = self.plan();
+                    let dispatch_lock = self.dispatcher.dispatch_lock().clone();
+                    self.state =
+                        PlannedSystemState::AcquireExtras(base_guards, extras, dispatch_lock);
+                }
+                PlannedSystemState::AcquireExtras(_, extras, dispatch_lock) => {
+                    let extra_guards = if extras.is_empty() {
+                        vec![]
+                    } else {
+                        // The second round follows the same rules as the first, only one task may
+                        // be trying to take locks at a time
+                        let _dispatch_guard = match dispatch_lock.poll_lock() {
+                            futures::Async::Ready(guard) => guard,
+                            futures::Async::NotReady => return Ok(futures::Async::NotReady),
+                        };
+
+                        let _gate_guard = match self.dispatcher.poll_dispatch_gate(false) {
+                            futures::Async::Ready(guard) => guard,
+                            futures::Async::NotReady => return Ok(futures::Async::NotReady),
+                        };
+
+                        let mut extra_guards = vec![];
+                        for required in &[&extras.reads, &extras.writes] {
+                            match try_take_locks(&self.dispatcher, required) {
+                                TryTakeLocksResult::Success(guards) => extra_guards.extend(guards),
+                                TryTakeLocksResult::Failure(resource_id, lock) => {
+                                    trace!(
+                                        "Failed to acquire extra {:?}, releasing everything",
+                                        resource_id
+                                    );
+                                    self.state = PlannedSystemState::WaitForExtra(lock);
+                                    return Ok(futures::Async::NotReady);
+                                }
+                            }
+                        }
+
+                        extra_guards
+                    };
+
+                    let state = std::mem::replace(&mut self.state, PlannedSystemState::Finished);
+                    if let PlannedSystemState::AcquireExtras(base_guards, extras, _) = state {
+                        self.run(&extras);
+                        drop(extra_guards);
+                        drop(base_guards);
+                    }
+
+                    return Ok(futures::Async::Ready(self.system.take().unwrap()));
+                }
+                PlannedSystemState::WaitForExtra(lock) => {
+                    // If we don't poll the lock after waiting for it, we will get stuck
+                    match lock.poll_lock() {
+                        futures::Async::Ready(_) => {}
+                        futures::Async::NotReady => return Ok(futures::Async::NotReady),
+                    }
+
+                    let system = self.system.as_ref().unwrap();
+                    let required_resources = RequiredResources::from_system(system);
+                    self.state = PlannedSystemState::AcquireBase(Box::new(AcquireResources::new(
+                        self.dispatcher.clone(),
+                        required_resources,
+                    )));
+                }
+
+                // This state is here to catch if we try to poll in a completed state
+                PlannedSystemState::Finished => unreachable!(),
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+// Estimates how much memory is held by futures created with Dispatcher::create_future that are
+// still waiting for their resources, and optionally caps it
+pub(super) struct QueuedBytes {
+    budget: Option<usize>,
+    current: AtomicUsize,
+}
+
+impl QueuedBytes {
+    pub(super) fn new(budget: Option<usize>) -> Self {
+        QueuedBytes {
+            budget,
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    // Counts the bytes as queued until the returned reservation is dropped. Returns None if that
+    // would go over the budget
+    pub(super) fn try_reserve(self: &Arc<Self>, bytes: usize) -> Option<QueuedBytesReservation> {
+        let budget = self.budget.unwrap_or(usize::MAX);
+        self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                current.checked_add(bytes).filter(|total| *total <= budget)
+            })
+            .ok()?;
+
+        Some(QueuedBytesReservation {
+            queued_bytes: self.clone(),
+            bytes,
+        })
+    }
+
+    pub(super) fn current(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+
+    pub(super) fn budget(&self) -> Option<usize> {
+        self.budget
+    }
+}
+
+pub(super) struct QueuedBytesReservation {
+    queued_bytes: Arc<QueuedBytes>,
+    bytes: usize,
+}
+
+impl Drop for QueuedBytesReservation {
+    fn drop(&mut self) {
+        self.queued_bytes
+            .current
+            .fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
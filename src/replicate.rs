@@ -0,0 +1,181 @@
+use hashbrown::HashMap;
+use std::any::Any;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::sync::Weak;
+
+use shred::ResourceId;
+
+use crate::write_versions::WriteVersions;
+
+// A resource whose state can be copied to a standby dispatcher with Dispatcher::replicate_to.
+// Snapshot is whatever is needed to bring another copy of the resource up to date. It could be a
+// clone of the resource, or something serialized for sending to another process
+pub trait Replicate: shred::Resource {
+    type Snapshot: Send + 'static;
+
+    fn snapshot(&self) -> Self::Snapshot;
+
+    fn apply(&mut self, snapshot: Self::Snapshot);
+}
+
+type SnapshotFn = fn(&shred::World) -> Box<dyn Any + Send>;
+type ApplyFn = fn(&shred::World, Box<dyn Any + Send>);
+
+fn snapshot<R: Replicate>(world: &shred::World) -> Box<dyn Any + Send> {
+    let resource = world
+        .try_fetch::<R>()
+        .expect("A replicated resource does not exist.");
+    Box::new(resource.snapshot())
+}
+
+fn apply<R: Replicate>(world: &shred::World, snapshot: Box<dyn Any + Send>) {
+    let snapshot = snapshot.downcast::<R::Snapshot>().unwrap();
+    world
+        .try_fetch_mut::<R>()
+        .expect("A resource replicated to a standby does not exist in the standby.")
+        .apply(*snapshot);
+}
+
+// A resource inserted with DispatcherBuilder::insert_replicated
+pub(super) struct ReplicatedResource {
+    resource_id: ResourceId,
+    snapshot: SnapshotFn,
+    apply: ApplyFn,
+}
+
+impl ReplicatedResource {
+    pub(super) fn new<R: Replicate>() -> Self {
+        ReplicatedResource {
+            resource_id: ResourceId::new::<R>(),
+            snapshot: snapshot::<R>,
+            apply: apply::<R>,
+        }
+    }
+}
+
+// A standby, by its world, and the write versions of the resources last applied to it
+type SentVersions = (Weak<RwLock<shred::World>>, HashMap<ResourceId, u64>);
+
+pub(super) struct Replication {
+    resources: Vec<ReplicatedResource>,
+    sent: Mutex<Vec<SentVersions>>,
+}
+
+impl Replication {
+    pub(super) fn new(resources: Vec<ReplicatedResource>) -> Self {
+        Replication {
+            resources,
+            sent: Mutex::new(vec![]),
+        }
+    }
+
+    pub(super) fn resource_ids(&self) -> Vec<ResourceId> {
+        self.resources
+            .iter()
+            .map(|resource| resource.resource_id.clone())
+            .collect()
+    }
+
+    // Snapshots every replicated resource that was written since it was last applied to the
+    // standby. Must be called while the resources are read locked so that the versions match what
+    // is snapshotted. Without write versions there's no way to tell, so everything is snapshotted
+    pub(super) fn snapshot_changed(
+        this: &Arc<Self>,
+        world: &shred::World,
+        write_versions: Option<&WriteVersions>,
+        standby: &Arc<RwLock<shred::World>>,
+    ) -> ReplicationBatch {
+        let versions = write_versions.map(|write_versions| {
+            let resource_ids = this.resource_ids();
+            let versions = write_versions.versions(&resource_ids);
+            resource_ids
+                .into_iter()
+                .zip(versions)
+                .collect::<HashMap<_, _>>()
+        });
+
+        let sent = this.sent.lock().unwrap();
+        let sent = sent
+            .iter()
+            .find(|(world, _)| Weak::ptr_eq(world, &Arc::downgrade(standby)))
+            .map(|(_, sent)| sent);
+
+        let snapshots = this
+            .resources
+            .iter()
+            .filter(|resource| match (&versions, sent) {
+                (Some(versions), Some(sent)) => {
+                    sent.get(&resource.resource_id) != versions.get(&resource.resource_id)
+                }
+                _ => true,
+            })
+            .map(|resource| {
+                (
+                    resource.resource_id.clone(),
+                    (resource.snapshot)(world),
+                    resource.apply,
+                )
+            })
+            .collect();
+
+        ReplicationBatch {
+            snapshots,
+            versions,
+            replication: this.clone(),
+            standby: Arc::downgrade(standby),
+        }
+    }
+
+    fn applied(&self, standby: Weak<RwLock<shred::World>>, versions: HashMap<ResourceId, u64>) {
+        let mut sent = self.sent.lock().unwrap();
+
+        // Forget standbys that no longer exist
+        sent.retain(|(world, _)| world.strong_count() > 0);
+        match sent
+            .iter_mut()
+            .find(|(world, _)| Weak::ptr_eq(world, &standby))
+        {
+            Some((_, sent)) => sent.extend(versions),
+            None => sent.push((standby, versions)),
+        }
+    }
+}
+
+// Snapshots taken by Replication::snapshot_changed, waiting for the standby's locks
+pub(super) struct ReplicationBatch {
+    snapshots: Vec<(ResourceId, Box<dyn Any + Send>, ApplyFn)>,
+    versions: Option<HashMap<ResourceId, u64>>,
+    replication: Arc<Replication>,
+    standby: Weak<RwLock<shred::World>>,
+}
+
+impl ReplicationBatch {
+    pub(super) fn resource_ids(&self) -> Vec<ResourceId> {
+        self.snapshots
+            .iter()
+            .map(|(resource_id, _, _)| resource_id.clone())
+            .collect()
+    }
+
+    // Applies the snapshots to the standby's world, which must be write locked for all of them.
+    // Returns how many resources were replicated
+    pub(super) fn apply(self, world: &shred::World) -> usize {
+        let count = self.snapshots.len();
+        let mut applied = HashMap::new();
+        for (resource_id, snapshot, apply) in self.snapshots {
+            apply(world, snapshot);
+            if let Some(versions) = &self.versions {
+                applied.insert(resource_id.clone(), versions[&resource_id]);
+            }
+        }
+
+        // Only now that the standby has them are they considered sent
+        if self.versions.is_some() {
+            self.replication.applied(self.standby, applied);
+        }
+
+        count
+    }
+}
@@ -1,21 +1,37 @@
 use shred::ResourceId;
+use smallvec::SmallVec;
 use std::marker::PhantomData;
 
+// Most systems only touch a handful of resources, so keep that many inline to avoid allocating for
+// every acquisition
+pub type ResourceIdList = SmallVec<[ResourceId; 8]>;
+
 // This is a helper that determines the reads/writes required for a system. I would have preferred
 // not to need this structure at all, but many of the shred types require lifetimes that just
 // don't play nicely with tasks. This gets rid of those lifetimes.
 #[derive(Debug)]
 pub struct RequiredResources<T> {
-    pub(super) reads: Vec<ResourceId>,
-    pub(super) writes: Vec<ResourceId>,
+    pub(super) reads: ResourceIdList,
+    pub(super) writes: ResourceIdList,
     phantom_data: PhantomData<T>,
 }
 
 impl<T> RequiredResources<T> {
     pub fn new(reads: Vec<ResourceId>, writes: Vec<ResourceId>) -> Self {
         RequiredResources {
-            reads,
-            writes,
+            reads: ResourceIdList::from_vec(reads),
+            writes: ResourceIdList::from_vec(writes),
+            phantom_data: PhantomData,
+        }
+    }
+
+    // Build from a fixed set of resources without allocating (as long as there are no more than
+    // the inline capacity of ResourceIdList). Useful for hot systems with a statically-known set
+    // of resources, where from_system would allocate every time
+    pub fn from_slices(reads: &[ResourceId], writes: &[ResourceId]) -> Self {
+        RequiredResources {
+            reads: reads.iter().cloned().collect(),
+            writes: writes.iter().cloned().collect(),
             phantom_data: PhantomData,
         }
     }
@@ -30,4 +46,27 @@ impl<T> RequiredResources<T> {
 
         RequiredResources::new(reads, writes)
     }
+
+    // Adds other's resources to ours, so that a group of systems can acquire everything they need
+    // at once and run without anything else interleaving. A resource that either side writes is
+    // written, and each resource is only listed once. Chain it to merge more than two, i.e.
+    // a.merge(&b).merge(&c)
+    pub fn merge<U>(mut self, other: &RequiredResources<U>) -> Self {
+        for resource_id in &other.writes {
+            if !self.writes.contains(resource_id) {
+                self.writes.push(resource_id.clone());
+            }
+        }
+
+        for resource_id in &other.reads {
+            if !self.reads.contains(resource_id) {
+                self.reads.push(resource_id.clone());
+            }
+        }
+
+        let writes = &self.writes;
+        self.reads
+            .retain(|resource_id| !writes.contains(resource_id));
+        self
+    }
 }
@@ -0,0 +1,12 @@
+use super::AsyncResourceLock;
+use super::DispatcherBuilder;
+
+// A group of related resources that are inserted together, i.e. everything a physics feature
+// needs. Implement insert_into by calling insert (or any of the other insert functions) on the
+// builder for each resource, and register it with DispatcherBuilder::insert_bundle
+pub trait ResourceBundle {
+    fn insert_into<L: AsyncResourceLock>(
+        self,
+        builder: DispatcherBuilder<L>,
+    ) -> DispatcherBuilder<L>;
+}
@@ -0,0 +1,95 @@
+use shred::ResourceId;
+
+use super::AsyncResourceLock;
+use super::DispatcherBuilder;
+
+// Declares the resources that a resource can't be used without. When a resource is inserted with
+// DispatcherBuilder::insert_with_dependencies, each of its dependencies that hasn't been inserted
+// yet is inserted with its Default value, along with its own dependencies. A resource with no
+// dependencies uses type Deps = ()
+pub trait DependsOn {
+    // A tuple of resource types, i.e. (PhysicsWorld, Gravity)
+    type Deps: ResourceDeps;
+}
+
+// Implemented for tuples of up to 8 resources that are Default and DependsOn
+pub trait ResourceDeps {
+    // The resources being inserted are on the stack, so that a dependency cycle can be reported
+    fn insert_defaults<L: AsyncResourceLock>(
+        builder: DispatcherBuilder<L>,
+        stack: &mut Vec<(ResourceId, &'static str)>,
+    ) -> DispatcherBuilder<L>;
+}
+
+impl ResourceDeps for () {
+    fn insert_defaults<L: AsyncResourceLock>(
+        builder: DispatcherBuilder<L>,
+        _stack: &mut Vec<(ResourceId, &'static str)>,
+    ) -> DispatcherBuilder<L> {
+        builder
+    }
+}
+
+macro_rules! impl_resource_deps {
+    ($($dep:ident),*) => {
+        impl<$($dep),*> ResourceDeps for ($($dep,)*)
+        where
+            $($dep: shred::Resource + Default + DependsOn),*
+        {
+            fn insert_defaults<L: AsyncResourceLock>(
+                mut builder: DispatcherBuilder<L>,
+                stack: &mut Vec<(ResourceId, &'static str)>,
+            ) -> DispatcherBuilder<L> {
+                $(builder = insert_default::<$dep, L>(builder, stack);)*
+                builder
+            }
+        }
+    };
+}
+
+impl_resource_deps!(A);
+impl_resource_deps!(A, B);
+impl_resource_deps!(A, B, C);
+impl_resource_deps!(A, B, C, D);
+impl_resource_deps!(A, B, C, D, E);
+impl_resource_deps!(A, B, C, D, E, F);
+impl_resource_deps!(A, B, C, D, E, F, G);
+impl_resource_deps!(A, B, C, D, E, F, G, H);
+
+// Inserts R's dependencies and then R, unless R was already inserted. Panics if R is already being
+// inserted further up the stack, since then the dependencies are circular
+fn insert_default<R, L>(
+    builder: DispatcherBuilder<L>,
+    stack: &mut Vec<(ResourceId, &'static str)>,
+) -> DispatcherBuilder<L>
+where
+    R: shred::Resource + Default + DependsOn,
+    L: AsyncResourceLock,
+{
+    let resource_id = ResourceId::new::<R>();
+    check_not_in_stack(&resource_id, std::any::type_name::<R>(), stack);
+    if builder.is_inserted(&resource_id) {
+        return builder;
+    }
+
+    stack.push((resource_id, std::any::type_name::<R>()));
+    let builder = R::Deps::insert_defaults(builder, stack);
+    stack.pop();
+    builder.insert(R::default())
+}
+
+fn check_not_in_stack(
+    resource_id: &ResourceId,
+    resource_name: &'static str,
+    stack: &[(ResourceId, &'static str)],
+) {
+    if let Some(position) = stack.iter().position(|(id, _)| id == resource_id) {
+        let cycle: Vec<&str> = stack[position..]
+            .iter()
+            .map(|(_, name)| *name)
+            .chain(std::iter::once(resource_name))
+            .collect();
+
+        panic!("Resource dependencies are circular: {}", cycle.join(" -> "));
+    }
+}
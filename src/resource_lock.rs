@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
+// The lock used by the dispatcher for the dispatch lock and for each resource. The dispatcher only
+// ever needs to try to take a lock without blocking, and to be woken up when a lock it failed to
+// take might be available. By default this is tokio's lock, but anything that can provide that
+// behavior can be plugged in (for example a lock that provides fairness)
+pub trait AsyncResourceLock: Clone + Send + Sync + 'static {
+    // Releases the lock when dropped
+    type Guard: Send + 'static;
+
+    // Create a new, unlocked lock
+    fn new() -> Self;
+
+    // Try to take the lock. If it can't be taken, the current task must be notified when it should
+    // try again
+    fn poll_lock(&mut self) -> futures::Async<Self::Guard>;
+
+    // Try to take the lock without waiting for it, and without needing to be inside a task. This
+    // is for checks made while a system is running. Locks that can't give up without being queued
+    // fall back to a probe, which stays queued until the lock is handed to it (see probe_lock), so
+    // locks that can should override this
+    fn try_lock(&self) -> Option<Self::Guard> {
+        probe_lock(self)
+    }
+}
+
+// The lock the dispatcher uses unless told otherwise
+pub type DefaultResourceLock = SemaphoreLock;
+
+// A lock built on tokio's semaphore. It behaves like tokio's lock, but can also be tried without
+// being queued
+pub struct SemaphoreLock {
+    semaphore: Arc<tokio_sync::semaphore::Semaphore>,
+    permit: tokio_sync::semaphore::Permit,
+}
+
+pub struct SemaphoreLockGuard {
+    semaphore: Arc<tokio_sync::semaphore::Semaphore>,
+    permit: tokio_sync::semaphore::Permit,
+}
+
+impl Drop for SemaphoreLockGuard {
+    fn drop(&mut self) {
+        self.permit.release(&self.semaphore);
+    }
+}
+
+impl Clone for SemaphoreLock {
+    // Each handle waits in the queue on its own
+    fn clone(&self) -> Self {
+        SemaphoreLock {
+            semaphore: self.semaphore.clone(),
+            permit: tokio_sync::semaphore::Permit::new(),
+        }
+    }
+}
+
+impl Drop for SemaphoreLock {
+    // Leaves the queue if this handle is waiting, and passes the lock on if it was just handed
+    // over to this handle
+    fn drop(&mut self) {
+        self.permit.release(&self.semaphore);
+    }
+}
+
+impl AsyncResourceLock for SemaphoreLock {
+    type Guard = SemaphoreLockGuard;
+
+    fn new() -> Self {
+        SemaphoreLock {
+            semaphore: Arc::new(tokio_sync::semaphore::Semaphore::new(1)),
+            permit: tokio_sync::semaphore::Permit::new(),
+        }
+    }
+
+    fn poll_lock(&mut self) -> futures::Async<Self::Guard> {
+        match self.permit.poll_acquire(&self.semaphore) {
+            Ok(futures::Async::Ready(())) => {}
+            Ok(futures::Async::NotReady) => return futures::Async::NotReady,
+            Err(_) => unreachable!("The semaphore is never closed"),
+        }
+
+        futures::Async::Ready(SemaphoreLockGuard {
+            semaphore: self.semaphore.clone(),
+            permit: std::mem::replace(&mut self.permit, tokio_sync::semaphore::Permit::new()),
+        })
+    }
+
+    fn try_lock(&self) -> Option<Self::Guard> {
+        let mut permit = tokio_sync::semaphore::Permit::new();
+        permit.try_acquire(&self.semaphore).ok()?;
+        Some(SemaphoreLockGuard {
+            semaphore: self.semaphore.clone(),
+            permit,
+        })
+    }
+}
+
+impl AsyncResourceLock for tokio_sync::lock::Lock<()> {
+    type Guard = tokio_sync::lock::LockGuard<()>;
+
+    fn new() -> Self {
+        tokio_sync::lock::Lock::new(())
+    }
+
+    fn poll_lock(&mut self) -> futures::Async<Self::Guard> {
+        tokio_sync::lock::Lock::poll_lock(self)
+    }
+}
+
+// A lock that can't be taken still queues whoever polled it, and is handed to them when it's
+// released. Since nothing is waiting on a probe, the lock would be lost for good at that point, so
+// the probe stays alive after giving up, takes the lock when it's handed over and releases it
+// right away.
+struct Probe<L: AsyncResourceLock> {
+    this: std::sync::Weak<Probe<L>>,
+    spawn: Mutex<Option<futures::executor::Spawn<ProbeFuture<L>>>>,
+}
+
+struct ProbeFuture<L: AsyncResourceLock> {
+    lock: L,
+}
+
+impl<L: AsyncResourceLock> futures::future::Future for ProbeFuture<L> {
+    type Item = L::Guard;
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        Ok(self.lock.poll_lock())
+    }
+}
+
+impl<L: AsyncResourceLock> Probe<L> {
+    // Returns the guard if the lock was taken. Otherwise the probe will be notified when the lock
+    // is handed to it
+    fn poll(this: &Arc<Self>) -> Option<L::Guard> {
+        let mut spawn = this.spawn.lock().unwrap();
+        let guard = match spawn.as_mut()?.poll_future_notify(this, 0) {
+            Ok(futures::Async::Ready(guard)) => guard,
+            _ => return None,
+        };
+
+        // Done with the lock, this also drops the probe's place in the lock's queue
+        *spawn = None;
+        Some(guard)
+    }
+}
+
+impl<L: AsyncResourceLock> futures::executor::Notify for Probe<L> {
+    fn notify(&self, _id: usize) {
+        if let Some(this) = self.this.upgrade() {
+            drop(Probe::poll(&this));
+        }
+    }
+}
+
+// Tries to take the lock once without needing to be inside a task. This is for diagnostics that
+// want to know if a lock is currently held, nothing will be woken up if it isn't available.
+pub(super) fn probe_lock<L: AsyncResourceLock>(lock: &L) -> Option<L::Guard> {
+    probe_owned_lock(lock.clone())
+}
+
+// Gives up on a lock that a task may be waiting on (i.e. because the task is being dropped). This
+// must be the same handle the task polled, since that's the one that's queued. It's taken and
+// released as soon as it's handed over, so it isn't lost to a waiter that no longer exists
+pub(super) fn release_when_available<L: AsyncResourceLock>(lock: L) {
+    drop(probe_owned_lock(lock));
+}
+
+fn probe_owned_lock<L: AsyncResourceLock>(lock: L) -> Option<L::Guard> {
+    let probe = Arc::new_cyclic(|this| Probe {
+        this: this.clone(),
+        spawn: Mutex::new(Some(futures::executor::spawn(ProbeFuture { lock }))),
+    });
+
+    Probe::poll(&probe)
+}
@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+
+// Decides who gets a contended resource next. The default, Fifo, leaves the decision to the lock
+// itself. The other policies let tasks that are already waiting on a resource with one kind of
+// access hold off new tasks that want the other kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResourceLockPolicy {
+    // Grant in the order the lock decides, no preference for readers or writers
+    #[default]
+    Fifo,
+
+    // New writers wait while any reader is waiting on the resource. Good for read-mostly data like
+    // configuration, but writers can wait longer
+    ReaderPreferred,
+
+    // New readers wait while any writer is waiting on the resource. Good for write-heavy data like
+    // event queues since writers can't be starved by a stream of readers
+    WriterPreferred,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ResourceAccess {
+    Read,
+    Write,
+}
+
+struct ResourcePolicyWaiters {
+    pending_reads: usize,
+    pending_writes: usize,
+    parked_tasks: Vec<futures::task::Task>,
+}
+
+// Per-resource bookkeeping for resources that have a policy other than Fifo. A task is "pending" on
+// a resource while it is waiting for that resource's lock. Tasks that yield to pending tasks are
+// parked here and woken whenever the set of pending tasks changes.
+pub(super) struct ResourcePolicyState {
+    policy: ResourceLockPolicy,
+    waiters: Mutex<ResourcePolicyWaiters>,
+}
+
+impl ResourcePolicyState {
+    pub(super) fn new(policy: ResourceLockPolicy) -> Self {
+        ResourcePolicyState {
+            policy,
+            waiters: Mutex::new(ResourcePolicyWaiters {
+                pending_reads: 0,
+                pending_writes: 0,
+                parked_tasks: vec![],
+            }),
+        }
+    }
+
+    pub(super) fn policy(&self) -> ResourceLockPolicy {
+        self.policy
+    }
+
+    // Returns true if a task wanting the given access should let pending tasks go first. In that
+    // case, the current task is parked and will be notified when the pending tasks change. The
+    // caller's own pending access (if any) is not counted against it.
+    pub(super) fn try_yield(
+        &self,
+        access: ResourceAccess,
+        own_pending: Option<ResourceAccess>,
+    ) -> bool {
+        let mut waiters = self.waiters.lock().unwrap();
+        let own_reads = (own_pending == Some(ResourceAccess::Read)) as usize;
+        let own_writes = (own_pending == Some(ResourceAccess::Write)) as usize;
+
+        let should_yield = match (self.policy, access) {
+            (ResourceLockPolicy::ReaderPreferred, ResourceAccess::Write) => {
+                waiters.pending_reads > own_reads
+            }
+            (ResourceLockPolicy::WriterPreferred, ResourceAccess::Read) => {
+                waiters.pending_writes > own_writes
+            }
+            _ => false,
+        };
+
+        if should_yield {
+            waiters.parked_tasks.push(futures::task::current());
+        }
+
+        should_yield
+    }
+
+    pub(super) fn add_pending(&self, access: ResourceAccess) {
+        let mut waiters = self.waiters.lock().unwrap();
+        match access {
+            ResourceAccess::Read => waiters.pending_reads += 1,
+            ResourceAccess::Write => waiters.pending_writes += 1,
+        }
+    }
+
+    pub(super) fn remove_pending(&self, access: ResourceAccess) {
+        let mut waiters = self.waiters.lock().unwrap();
+        match access {
+            ResourceAccess::Read => waiters.pending_reads -= 1,
+            ResourceAccess::Write => waiters.pending_writes -= 1,
+        }
+
+        for task in waiters.parked_tasks.drain(..) {
+            task.notify();
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+use std::sync::RwLockReadGuard;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use crate::acquire_resources::AcquiredResourcesLockGuards;
+
+// Holds resources acquired with Dispatcher::acquire_blocking. The resources are released when this
+// is dropped. It also keeps the dispatcher alive, so drop it before the game loop ends.
+pub struct ResourceScope<L: AsyncResourceLock = DefaultResourceLock> {
+    // Declared first so the locks are released before the dispatcher reference is
+    _guards: AcquiredResourcesLockGuards<(), L>,
+    dispatcher: Arc<Dispatcher<L>>,
+}
+
+impl<L: AsyncResourceLock> ResourceScope<L> {
+    pub(super) fn new(
+        dispatcher: Arc<Dispatcher<L>>,
+        guards: AcquiredResourcesLockGuards<(), L>,
+    ) -> Self {
+        ResourceScope {
+            _guards: guards,
+            dispatcher,
+        }
+    }
+
+    // Returns the world. Only the resources that were passed to acquire_blocking may be fetched
+    // from it
+    pub fn world(&self) -> RwLockReadGuard<'_, shred::World> {
+        self.dispatcher.world()
+    }
+}
@@ -0,0 +1,35 @@
+use hashbrown::HashMap;
+use std::sync::Mutex;
+
+// Tasks that are parked waiting on a resource lock, by task id. A parked task is normally only woken
+// when the lock is released, so end_game_loop wakes these to give up instead of waiting on a holder
+// that may never finish (see ContendedShutdownPolicy)
+pub(super) struct ResourceWaiters {
+    tasks: Mutex<HashMap<u64, futures::task::Task>>,
+}
+
+impl ResourceWaiters {
+    pub(super) fn new() -> Self {
+        ResourceWaiters {
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Must be called from within the waiting task
+    pub(super) fn park(&self, task_id: u64) {
+        self.tasks
+            .lock()
+            .unwrap()
+            .insert(task_id, futures::task::current());
+    }
+
+    pub(super) fn unpark(&self, task_id: u64) {
+        self.tasks.lock().unwrap().remove(&task_id);
+    }
+
+    pub(super) fn wake_all(&self) {
+        for (_, task) in self.tasks.lock().unwrap().drain() {
+            task.notify();
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use super::AcquireResources;
+use super::AsyncResourceLock;
+use super::Dispatcher;
+use super::RequiredResources;
+
+// What a ResumableSystem's run_resumable got done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemProgress {
+    // The system's work is done
+    Finished,
+
+    // The system stopped partway through because YieldToken::check said to. It will be run again
+    // once it has re-acquired its resources
+    Yielded,
+}
+
+// Given to a ResumableSystem so that it can tell when it has had the resources for long enough
+pub struct YieldToken<'a> {
+    deadline: Instant,
+    should_terminate: &'a dyn Fn() -> bool,
+}
+
+impl<'a> YieldToken<'a> {
+    // True once the system has run for longer than its time slice, meaning it should save its
+    // progress and return SystemProgress::Yielded. Always false once the dispatcher is
+    // terminating, since a system that let go of its resources then would never get them back
+    pub fn check(&self) -> bool {
+        Instant::now() >= self.deadline && !(self.should_terminate)()
+    }
+}
+
+// A long running system that can stop partway through to let other systems have its resources,
+// and carry on later. Use with Dispatcher::create_resumable_future. System::run isn't called for
+// these.
+//
+// run_resumable should call YieldToken::check every so often (i.e. once per item of work). When
+// it returns true the system must keep whatever it needs to pick up where it left off in its own
+// fields and return SystemProgress::Yielded. Its resources are released, and it's run again once
+// it has acquired them again, so it must not assume anything about them is the same as when it
+// stopped (see Dispatcher::reacquire_guard). Ignoring check is allowed, it just holds the
+// resources for the whole run like any other system
+pub trait ResumableSystem: for<'b> shred::System<'b> + Send + 'static {
+    fn run_resumable(
+        &mut self,
+        data: <Self as shred::System<'_>>::SystemData,
+        token: &YieldToken,
+    ) -> SystemProgress;
+}
+
+fn run_resumable_system<T, L>(
+    dispatcher: &Dispatcher<L>,
+    system: &mut T,
+    slice: Duration,
+) -> SystemProgress
+where
+    T: ResumableSystem,
+    L: AsyncResourceLock,
+{
+    use shred::DynamicSystemData;
+
+    let start = Instant::now();
+    let world = dispatcher.world();
+    let data = <T as shred::System>::SystemData::fetch(&system.accessor(), &world);
+    let token = YieldToken {
+        deadline: start + slice,
+        should_terminate: &|| dispatcher.should_terminate(),
+    };
+
+    let progress = system.run_resumable(data, &token);
+    drop(world);
+
+    dispatcher.record_system_timing(std::any::type_name::<T>(), start.elapsed());
+    progress
+}
+
+// Resolves on the second poll, after the current task has been woken. Polling the other tasks
+// waiting to run in between gives them a chance to take the resources we just released
+struct YieldNow {
+    yielded: bool,
+}
+
+impl futures::Future for YieldNow {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<(), ()> {
+        if self.yielded {
+            return Ok(futures::Async::Ready(()));
+        }
+
+        self.yielded = true;
+        futures::task::current().notify();
+        Ok(futures::Async::NotReady)
+    }
+}
+
+// Acquires the system's resources and runs it, and keeps doing so until it finishes
+pub(super) fn create_resumable_future<T, L>(
+    dispatcher: Arc<Dispatcher<L>>,
+    system: T,
+    slice: Duration,
+) -> impl futures::Future<Item = T, Error = ()>
+where
+    T: ResumableSystem,
+    L: AsyncResourceLock,
+{
+    use futures::future::Loop;
+    use futures::Future;
+
+    futures::future::loop_fn(system, move |mut system| {
+        let dispatcher = dispatcher.clone();
+        let required_resources = RequiredResources::from_system(&system);
+        let acquire = AcquireResources::<T, L>::new(dispatcher.clone(), required_resources);
+        acquire.map_err(|_| ()).and_then(move |guards| {
+            let progress = run_resumable_system(&dispatcher, &mut system, slice);
+            drop(guards);
+
+            match progress {
+                SystemProgress::Finished => {
+                    futures::future::Either::A(futures::future::ok(Loop::Break(system)))
+                }
+                SystemProgress::Yielded => futures::future::Either::B(
+                    YieldNow { yielded: false }.map(move |_| Loop::Continue(system)),
+                ),
+            }
+        })
+    })
+}
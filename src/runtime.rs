@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+pub type RuntimeFuture = Box<dyn futures::future::Future<Item = (), Error = ()> + Send>;
+
+// The parts of an async runtime the dispatcher needs. The runtime that's used is picked with
+// features: tokio by default (tokio-runtime), or async-std (async-std-runtime) for projects that
+// don't otherwise use tokio. If both are enabled, tokio is used.
+pub trait Runtime {
+    // Run the future to completion, blocking the current thread until it and everything it
+    // spawned has finished
+    fn run(future: RuntimeFuture);
+
+    // Run the future in the background. Must be called from inside run
+    fn spawn(future: RuntimeFuture);
+
+    // Returns a future that completes after the given duration
+    fn delay(duration: Duration) -> RuntimeFuture;
+
+    // Returns a future that calls f somewhere it's ok to block for a long time, so that it doesn't
+    // hold up other tasks
+    fn blocking<F, RetT>(f: F) -> BlockingFuture<RetT>
+    where
+        F: FnOnce() -> RetT + Send + 'static,
+        RetT: Send + 'static;
+}
+
+pub type BlockingFuture<RetT> = Box<dyn futures::future::Future<Item = RetT, Error = ()> + Send>;
+
+#[cfg(feature = "tokio-runtime")]
+pub struct TokioRuntime;
+
+#[cfg(feature = "tokio-runtime")]
+impl Runtime for TokioRuntime {
+    fn run(future: RuntimeFuture) {
+        tokio::run(future);
+    }
+
+    fn spawn(future: RuntimeFuture) {
+        tokio::spawn(future);
+    }
+
+    fn delay(duration: Duration) -> RuntimeFuture {
+        use futures::Future;
+        let deadline = std::time::Instant::now() + duration;
+        Box::new(tokio::timer::Delay::new(deadline).map_err(|_| ()))
+    }
+
+    fn blocking<F, RetT>(f: F) -> BlockingFuture<RetT>
+    where
+        F: FnOnce() -> RetT + Send + 'static,
+        RetT: Send + 'static,
+    {
+        // blocking hands our worker thread's other tasks to another thread while f runs. It's
+        // NotReady (without calling f) if too many threads are already blocking
+        let mut f = Some(f);
+        Box::new(futures::future::poll_fn(move || {
+            match tokio_threadpool::blocking(|| (f.take().unwrap())()) {
+                Ok(result) => Ok(result),
+                // Not on a threadpool (i.e. a current_thread runtime), so there's nowhere else to
+                // run it
+                Err(_) => Ok(futures::Async::Ready((f.take().unwrap())())),
+            }
+        }))
+    }
+}
+
+#[cfg(feature = "async-std-runtime")]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "async-std-runtime")]
+impl Runtime for AsyncStdRuntime {
+    fn run(future: RuntimeFuture) {
+        use futures03::compat::Future01CompatExt;
+
+        // Unlike tokio::run, block_on doesn't wait for spawned tasks. The dispatcher's game loop
+        // waits for tasks spawned through Dispatcher::spawn itself
+        let _ = async_std::task::block_on(future.compat());
+    }
+
+    fn spawn(future: RuntimeFuture) {
+        use futures03::compat::Future01CompatExt;
+        async_std::task::spawn(async move {
+            let _ = future.compat().await;
+        });
+    }
+
+    fn delay(duration: Duration) -> RuntimeFuture {
+        let delay = async move {
+            async_std::task::sleep(duration).await;
+            Ok::<(), ()>(())
+        };
+
+        Box::new(futures03::compat::Compat::new(Box::pin(delay)))
+    }
+
+    fn blocking<F, RetT>(f: F) -> BlockingFuture<RetT>
+    where
+        F: FnOnce() -> RetT + Send + 'static,
+        RetT: Send + 'static,
+    {
+        let blocking = async move { Ok::<RetT, ()>(async_std::task::spawn_blocking(f).await) };
+        Box::new(futures03::compat::Compat::new(Box::pin(blocking)))
+    }
+}
+
+#[cfg(feature = "tokio-runtime")]
+pub type DefaultRuntime = TokioRuntime;
+
+#[cfg(all(feature = "async-std-runtime", not(feature = "tokio-runtime")))]
+pub type DefaultRuntime = AsyncStdRuntime;
+
+#[cfg(not(any(feature = "tokio-runtime", feature = "async-std-runtime")))]
+compile_error!("Either the tokio-runtime or the async-std-runtime feature must be enabled");
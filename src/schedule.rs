@@ -0,0 +1,450 @@
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use shred::ResourceId;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use super::ExecuteParallel;
+use super::ExecuteSequential;
+use super::ExplainedSystem;
+use super::LevelExplanation;
+use super::ScheduleExplanation;
+use super::SystemConflict;
+use crate::schedule_explanation::conflicting_resources;
+
+type ChildFuture = dyn futures::future::Future<Item = (), Error = ()> + Send;
+type CreateFutureFn<L> = dyn Fn(&Arc<Dispatcher<L>>) -> Box<ChildFuture> + Send + Sync;
+
+// Identifies a system that was added to a Schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SystemId(usize);
+
+impl SystemId {
+    pub(super) fn index(self) -> usize {
+        self.0
+    }
+}
+
+struct ScheduledSystem<L: AsyncResourceLock> {
+    after: Vec<SystemId>,
+    create_future: Box<CreateFutureFn<L>>,
+    // Only used to explain the schedule
+    name: &'static str,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+}
+
+impl<L: AsyncResourceLock> ScheduledSystem<L> {
+    fn resources(&self) -> (&[ResourceId], &[ResourceId]) {
+        (&self.reads, &self.writes)
+    }
+}
+
+// How a Schedule orders the systems within a level. Systems in a level are started in this order,
+// which is roughly the order they get their resources and run in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScheduleHint {
+    // The order they were added in
+    #[default]
+    InsertionOrder,
+
+    // Each system is followed by whichever system left in the level shares the most read
+    // resources with it, so that data is more likely to still be in cache when the next system
+    // reads it. Ties keep the order they were added in
+    CacheLocality,
+}
+
+// Holds a scheduled system while its future is queued or running, and puts it back in its slot
+// when dropped. The future can fail (i.e. a resource timeout, the queued bytes budget or the
+// dispatcher terminating) or be dropped partway through, and the system has to be back in the
+// schedule for the next frame either way
+struct ScheduledSystemGuard<T> {
+    slot: Arc<Mutex<Option<T>>>,
+    system: Option<T>,
+}
+
+impl<T> Drop for ScheduledSystemGuard<T> {
+    fn drop(&mut self) {
+        if let Some(system) = self.system.take() {
+            *self.slot.lock().unwrap() = Some(system);
+        }
+    }
+}
+
+impl<'a, T: shred::System<'a>> shred::System<'a> for ScheduledSystemGuard<T> {
+    type SystemData = T::SystemData;
+
+    fn run(&mut self, data: Self::SystemData) {
+        self.system.as_mut().unwrap().run(data)
+    }
+
+    fn running_time(&self) -> shred::RunningTime {
+        self.system.as_ref().unwrap().running_time()
+    }
+
+    fn accessor<'b>(&'b self) -> shred::AccessorCow<'a, 'b, Self> {
+        match self.system.as_ref().unwrap().accessor() {
+            shred::AccessorCow::Ref(accessor) => shred::AccessorCow::Ref(accessor),
+            shred::AccessorCow::Owned(accessor) => shred::AccessorCow::Owned(accessor),
+        }
+    }
+
+    fn setup(&mut self, world: &mut shred::World) {
+        self.system.as_mut().unwrap().setup(world)
+    }
+}
+
+// A set of systems with explicit ordering constraints. Systems are grouped into levels, where a
+// system's level is one past the latest level of anything it must run after. Each level runs as an
+// ExecuteParallel and the levels run in sequence. Systems in the same level that touch the same
+// resources are still kept safe by the resource locks, so only the ordering needs to be declared.
+//
+// Systems can be inserted and removed while the schedule is running (i.e. from a system, or
+// another thread, with the schedule in an Arc). The systems that run in a frame are decided when
+// that frame's future is created, so changes take effect the next time create_future is called and
+// anything already running finishes as it was scheduled.
+pub struct Schedule<L: AsyncResourceLock = DefaultResourceLock> {
+    // Removed systems leave a None behind so that SystemIds stay valid
+    systems: Mutex<Vec<Option<ScheduledSystem<L>>>>,
+    hint: ScheduleHint,
+}
+
+impl<L: AsyncResourceLock> Default for Schedule<L> {
+    fn default() -> Self {
+        Schedule::new()
+    }
+}
+
+impl<L: AsyncResourceLock> Schedule<L> {
+    pub fn new() -> Self {
+        Schedule {
+            systems: Mutex::new(vec![]),
+            hint: ScheduleHint::default(),
+        }
+    }
+
+    // Changes how the systems within each level are ordered, see ScheduleHint
+    pub fn with_hint(mut self, hint: ScheduleHint) -> Self {
+        self.hint = hint;
+        self
+    }
+
+    // Add a system that must run after all the given systems have completed. The system is kept
+    // by the schedule and reused every time the schedule runs
+    pub fn add<T>(&mut self, system: T, after: &[SystemId]) -> SystemId
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        self.insert(system, after)
+    }
+
+    // Same as add, but can be called while the schedule is running. The system will run starting
+    // with the next frame
+    pub fn insert<T>(&self, system: T, after: &[SystemId]) -> SystemId
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use shred::Accessor;
+        let accessor = system.accessor();
+        let reads = accessor.reads();
+        let writes = accessor.writes();
+
+        // The system is moved into the future while it runs and put back when the future is done
+        // with it, however that happens (see ScheduledSystemGuard). It isn't taken until the future
+        // is first polled so that the next frame's future can be created before this frame's
+        // completes
+        let slot = Arc::new(Mutex::new(Some(system)));
+        let create_future = move |dispatcher: &Arc<Dispatcher<L>>| -> Box<ChildFuture> {
+            use futures::Future;
+            let dispatcher = dispatcher.clone();
+            let slot = slot.clone();
+            Box::new(futures::future::lazy(move || {
+                let system = match slot.lock().unwrap().take() {
+                    Some(system) => system,
+                    None => {
+                        // The last frame's future for this schedule is still running it
+                        error!(
+                            "Skipped {} since it was started while it was already running",
+                            std::any::type_name::<T>()
+                        );
+                        return futures::future::Either::A(futures::future::ok(()));
+                    }
+                };
+
+                let guard = ScheduledSystemGuard {
+                    slot,
+                    system: Some(system),
+                };
+                futures::future::Either::B(
+                    Dispatcher::create_future_with_result(&dispatcher, guard).map(|_guard| ()),
+                )
+            }))
+        };
+
+        let mut systems = self.systems.lock().unwrap();
+
+        // Since ids are only handed out by insert(), everything in after was inserted before this
+        // system, so there can't be a cycle
+        for system_id in after {
+            assert!(
+                system_id.0 < systems.len(),
+                "A SystemId from a different schedule was used"
+            );
+        }
+
+        systems.push(Some(ScheduledSystem {
+            after: after.to_vec(),
+            create_future: Box::new(create_future),
+            name: std::any::type_name::<T>(),
+            reads,
+            writes,
+        }));
+
+        SystemId(systems.len() - 1)
+    }
+
+    // Removes a system, starting with the next frame. If it is running right now it will still
+    // finish. Anything that had to run after it no longer waits for it. Returns false if the system
+    // was already removed
+    pub fn remove(&self, system_id: SystemId) -> bool {
+        let mut systems = self.systems.lock().unwrap();
+        systems
+            .get_mut(system_id.0)
+            .expect("A SystemId from a different schedule was used")
+            .take()
+            .is_some()
+    }
+
+    // Returns the systems in each level, in the order the levels will run
+    pub fn levels(&self) -> Vec<Vec<SystemId>> {
+        self.compute_levels(&self.systems.lock().unwrap())
+    }
+
+    // Describes each level, why each system is in the level it's in, and which systems in the same
+    // level will end up waiting on each other for resources. This is for answering "why isn't my
+    // system running in parallel?"
+    pub fn explain(&self) -> ScheduleExplanation {
+        let systems = self.systems.lock().unwrap();
+        let system = |system_id: &SystemId| systems[system_id.0].as_ref().unwrap();
+
+        let levels = self
+            .compute_levels(&systems)
+            .into_iter()
+            .map(|level| {
+                let explained_systems = level
+                    .iter()
+                    .map(|system_id| ExplainedSystem {
+                        id: *system_id,
+                        name: system(system_id).name,
+                        after: system(system_id)
+                            .after
+                            .iter()
+                            .filter(|after_id| systems[after_id.0].is_some())
+                            .map(|after_id| SystemConflict {
+                                first: *after_id,
+                                second: *system_id,
+                                resources: conflicting_resources(
+                                    system(after_id).resources(),
+                                    system(system_id).resources(),
+                                ),
+                            })
+                            .collect(),
+                    })
+                    .collect();
+
+                let mut lock_conflicts = vec![];
+                for (index, first) in level.iter().enumerate() {
+                    for second in &level[index + 1..] {
+                        let resources = conflicting_resources(
+                            system(first).resources(),
+                            system(second).resources(),
+                        );
+
+                        if !resources.is_empty() {
+                            lock_conflicts.push(SystemConflict {
+                                first: *first,
+                                second: *second,
+                                resources,
+                            });
+                        }
+                    }
+                }
+
+                LevelExplanation {
+                    systems: explained_systems,
+                    lock_conflicts,
+                }
+            })
+            .collect();
+
+        ScheduleExplanation { levels }
+    }
+
+    // Levels are recomputed from the constraints every time since removing a system can move the
+    // systems after it to an earlier level. Constraints always point at an earlier index, so a
+    // single pass in index order sees every dependency before the systems that depend on it
+    fn compute_levels(&self, systems: &[Option<ScheduledSystem<L>>]) -> Vec<Vec<SystemId>> {
+        let mut system_levels: Vec<Option<usize>> = Vec::with_capacity(systems.len());
+        let mut levels: Vec<Vec<SystemId>> = vec![];
+        for (index, system) in systems.iter().enumerate() {
+            let level = system.as_ref().map(|system| {
+                system
+                    .after
+                    .iter()
+                    .filter_map(|system_id| system_levels[system_id.0])
+                    .map(|level| level + 1)
+                    .max()
+                    .unwrap_or(0)
+            });
+
+            system_levels.push(level);
+            if let Some(level) = level {
+                if levels.len() <= level {
+                    levels.resize(level + 1, vec![]);
+                }
+                levels[level].push(SystemId(index));
+            }
+        }
+
+        match self.hint {
+            ScheduleHint::InsertionOrder => levels,
+            ScheduleHint::CacheLocality => levels
+                .into_iter()
+                .map(|level| Self::order_for_cache_locality(systems, level))
+                .collect(),
+        }
+    }
+
+    // Greedily picks the next system by how many reads it shares with the one before it. This
+    // doesn't find the best order overall, but levels are small and it's recomputed every frame
+    fn order_for_cache_locality(
+        systems: &[Option<ScheduledSystem<L>>],
+        mut remaining: Vec<SystemId>,
+    ) -> Vec<SystemId> {
+        let shared_reads = |first: SystemId, second: SystemId| {
+            let second_reads = &systems[second.0].as_ref().unwrap().reads;
+            systems[first.0]
+                .as_ref()
+                .unwrap()
+                .reads
+                .iter()
+                .filter(|resource_id| second_reads.contains(resource_id))
+                .count()
+        };
+
+        let mut ordered = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let next = match ordered.last() {
+                Some(previous) => remaining
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(index, system_id)| {
+                        (shared_reads(*previous, **system_id), Reverse(*index))
+                    })
+                    .map(|(index, _)| index)
+                    .unwrap(),
+                None => 0,
+            };
+
+            ordered.push(remaining.remove(next));
+        }
+
+        ordered
+    }
+
+    // Returns a future that runs every system in the schedule once. Changes made to the schedule
+    // after this is called don't affect the returned future
+    pub fn create_future(&self, dispatcher: &Arc<Dispatcher<L>>) -> ExecuteSequential<()> {
+        let systems = self.systems.lock().unwrap();
+        let levels = self
+            .compute_levels(&systems)
+            .into_iter()
+            .map(|level| {
+                let futures = level
+                    .into_iter()
+                    .map(|system_id| {
+                        let system = systems[system_id.0].as_ref().unwrap();
+                        (system.create_future)(dispatcher)
+                    })
+                    .collect();
+                Box::new(ExecuteParallel::new(futures)) as Box<ChildFuture>
+            })
+            .collect();
+
+        ExecuteSequential::new(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DispatcherBuilder;
+    use futures::Future;
+
+    struct Counter(u32);
+
+    struct IncrementSystem;
+
+    impl<'a> shred::System<'a> for IncrementSystem {
+        type SystemData = shred::WriteExpect<'a, Counter>;
+
+        fn run(&mut self, mut counter: Self::SystemData) {
+            counter.0 += 1;
+        }
+    }
+
+    fn create_system_future(
+        schedule: &Schedule,
+        system_id: SystemId,
+        dispatcher: &Arc<Dispatcher>,
+    ) -> Box<ChildFuture> {
+        let systems = schedule.systems.lock().unwrap();
+        (systems[system_id.index()].as_ref().unwrap().create_future)(dispatcher)
+    }
+
+    #[test]
+    fn system_is_kept_when_its_future_is_dropped() {
+        let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter(0)).build());
+        let mut schedule = Schedule::new();
+        let system_id = schedule.add(IncrementSystem, &[]);
+
+        // Poll the future once while the counter is held, so the system is waiting for it, and
+        // then drop it
+        let held = Dispatcher::acquire_blocking(&dispatcher, &[], &[ResourceId::new::<Counter>()]);
+        let waiting = create_system_future(&schedule, system_id, &dispatcher)
+            .select2(futures::future::ok::<(), ()>(()))
+            .wait();
+        assert!(matches!(waiting, Ok(futures::future::Either::B(_))));
+        drop(waiting);
+        drop(held);
+
+        create_system_future(&schedule, system_id, &dispatcher)
+            .wait()
+            .unwrap();
+        assert_eq!(
+            dispatcher.read_resource::<Counter, _, _>(|counter| counter.0),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn system_is_kept_when_its_future_fails() {
+        let dispatcher = Arc::new(
+            DispatcherBuilder::new()
+                .insert(Counter(0))
+                .with_queued_bytes_budget(0)
+                .build(),
+        );
+        let mut schedule = Schedule::new();
+        let system_id = schedule.add(IncrementSystem, &[]);
+
+        // If the first failure lost the system, the second future would skip it and succeed
+        for _ in 0..2 {
+            let result = create_system_future(&schedule, system_id, &dispatcher).wait();
+            assert!(result.is_err());
+        }
+    }
+}
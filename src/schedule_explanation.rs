@@ -0,0 +1,117 @@
+use shred::ResourceId;
+
+use super::SystemId;
+
+// What a Schedule will do when it runs, see Schedule::explain
+#[derive(Debug, Clone)]
+pub struct ScheduleExplanation {
+    // In the order the levels run
+    pub levels: Vec<LevelExplanation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LevelExplanation {
+    pub systems: Vec<ExplainedSystem>,
+
+    // Pairs of systems in this level that are started together but can't actually run at the same
+    // time, since one of them writes a resource the other uses. Whichever gets the resource first
+    // runs first
+    pub lock_conflicts: Vec<SystemConflict>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExplainedSystem {
+    pub id: SystemId,
+    pub name: &'static str,
+
+    // The systems this one was declared to run after. This is why it isn't in an earlier level
+    pub after: Vec<SystemConflict>,
+}
+
+// Two systems, and the resources they conflict on (one of them writes it). For a declared
+// ordering, the resources are empty if the systems don't actually share anything
+#[derive(Debug, Clone)]
+pub struct SystemConflict {
+    pub first: SystemId,
+    pub second: SystemId,
+    pub resources: Vec<ResourceId>,
+}
+
+// The resources that two systems can't use at the same time
+pub(super) fn conflicting_resources(
+    (first_reads, first_writes): (&[ResourceId], &[ResourceId]),
+    (second_reads, second_writes): (&[ResourceId], &[ResourceId]),
+) -> Vec<ResourceId> {
+    let mut resources: Vec<ResourceId> = first_writes
+        .iter()
+        .filter(|resource_id| {
+            second_reads.contains(resource_id) || second_writes.contains(resource_id)
+        })
+        .chain(
+            second_writes
+                .iter()
+                .filter(|resource_id| first_reads.contains(resource_id)),
+        )
+        .cloned()
+        .collect();
+
+    resources.sort();
+    resources.dedup();
+    resources
+}
+
+// The size of the largest subset of candidates where no two conflict, given a matrix of which
+// systems conflict with which
+pub(super) fn largest_independent_set(conflicts: &[Vec<bool>], candidates: Vec<usize>) -> usize {
+    let (first, rest) = match candidates.split_first() {
+        Some((first, rest)) => (*first, rest),
+        None => return 0,
+    };
+
+    // A system that conflicts with none of the others can always be included. The matrix says
+    // every system that writes anything conflicts with itself, so only look at the others
+    if rest.iter().all(|other| !conflicts[first][*other]) {
+        return 1 + largest_independent_set(conflicts, rest.to_vec());
+    }
+
+    let compatible = rest
+        .iter()
+        .cloned()
+        .filter(|other| !conflicts[first][*other])
+        .collect();
+    let with_first = 1 + largest_independent_set(conflicts, compatible);
+    let without_first = largest_independent_set(conflicts, rest.to_vec());
+    with_first.max(without_first)
+}
+
+impl std::fmt::Display for ScheduleExplanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, level) in self.levels.iter().enumerate() {
+            writeln!(f, "Level {}", index)?;
+            for system in &level.systems {
+                writeln!(f, "  {:?} {}", system.id, system.name)?;
+                for ordering in &system.after {
+                    if ordering.resources.is_empty() {
+                        writeln!(f, "    after {:?} (no shared resources)", ordering.first)?;
+                    } else {
+                        writeln!(
+                            f,
+                            "    after {:?} for {:?}",
+                            ordering.first, ordering.resources
+                        )?;
+                    }
+                }
+            }
+
+            for conflict in &level.lock_conflicts {
+                writeln!(
+                    f,
+                    "  {:?} and {:?} take turns for {:?}",
+                    conflict.first, conflict.second, conflict.resources
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
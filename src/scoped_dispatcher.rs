@@ -0,0 +1,75 @@
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use super::FrameStats;
+
+// A child of another dispatcher, from Dispatcher::scoped. It shares the parent's world and
+// resource locks, so a system running in the child and one running in the parent never hold the
+// same resource at once. Scheduling is separate: the child has its own dispatch lock, task ids and
+// frame count, so a nested loop over a few systems (i.e. a rollback region) doesn't contend for the
+// parent's dispatch lock on every acquisition.
+//
+// Using two dispatch locks over the same resource locks can't deadlock. An acquisition takes all
+// of its locks (in the same sorted order in both dispatchers by default) or releases all of them
+// before it waits, so nothing waits while holding a resource. Two acquisitions trying at the same
+// time may make each other fail, which only costs a retry.
+//
+// Derefs to the child's Arc<Dispatcher>, so futures are created with Dispatcher::create_future as
+// usual. The child keeps the parent alive, so drop it (and anything holding the child) before the
+// parent's loop ends.
+pub struct ScopedDispatcher<L: AsyncResourceLock = DefaultResourceLock> {
+    dispatcher: Arc<Dispatcher<L>>,
+}
+
+impl<L: AsyncResourceLock> ScopedDispatcher<L> {
+    pub(super) fn new(dispatcher: Arc<Dispatcher<L>>) -> Self {
+        ScopedDispatcher { dispatcher }
+    }
+
+    // Returns a future that runs the child's loop, calling f for each frame like enter_game_loop,
+    // until the child's end_game_loop is called. The child can't block a thread with its own
+    // runtime since it's running inside the parent's, so this is spawned or waited on from the
+    // parent instead. Resolves to the timings of the frames that ran.
+    pub fn create_loop_future<F, FutureT>(
+        &self,
+        f: F,
+    ) -> Box<impl futures::Future<Item = FrameStats, Error = ()>>
+    where
+        F: Fn(Arc<Dispatcher<L>>) -> FutureT + Send + Sync + Copy + 'static,
+        FutureT: futures::future::Future<Item = (), Error = ()> + Send + 'static,
+    {
+        use futures::Future;
+
+        let dispatcher = self.dispatcher.clone();
+        let frame_stats = Arc::new(Mutex::new(FrameStats::default()));
+        let frame_stats_clone = frame_stats.clone();
+        let start = futures::future::lazy(move || {
+            dispatcher.set_loop_running(true);
+            let loop_future = Dispatcher::frame_loop(
+                &dispatcher,
+                None,
+                frame_stats_clone,
+                futures::future::empty(),
+                f,
+            );
+            loop_future.then(move |result| {
+                dispatcher.set_loop_running(false);
+                result
+            })
+        });
+
+        Box::new(start.map(move |_| frame_stats.lock().unwrap().clone()))
+    }
+}
+
+impl<L: AsyncResourceLock> Deref for ScopedDispatcher<L> {
+    type Target = Arc<Dispatcher<L>>;
+
+    fn deref(&self) -> &Arc<Dispatcher<L>> {
+        &self.dispatcher
+    }
+}
@@ -0,0 +1,16 @@
+// splitmix64's increment and finalizer, the same mixing the fault injector uses
+const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Derives a system's seed for a frame. Each input goes through the mixer before the next is added
+// so that i.e. (frame 1, system 2) and (frame 2, system 1) don't collide
+pub(super) fn system_seed(base_seed: u64, frame: u64, system_index: u64) -> u64 {
+    let z = mix(base_seed.wrapping_add(GAMMA));
+    let z = mix(z ^ frame.wrapping_add(GAMMA));
+    mix(z ^ system_index.wrapping_add(GAMMA))
+}
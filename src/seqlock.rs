@@ -0,0 +1,71 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+// A small Copy resource that can be read without taking its lock. Insert with
+// DispatcherBuilder::insert_seqlock and declare it in a system as shred::ReadExpect<SeqLock<T>>
+// (never a write, since it has no lock to protect a mutable fetch). Reads retry if a write happened
+// while they were copying the value out, so they never block, but they can spin if it's written
+// constantly. Writes are serialized with each other. A system that reads the value twice may see
+// two different values, so copy it out once if that matters.
+pub struct SeqLock<T: Copy> {
+    // Odd while a write is in progress
+    sequence: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+// The value is only ever copied in and out, and a copy that raced with a write is thrown away
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub fn new(value: T) -> Self {
+        SeqLock {
+            sequence: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    // Returns a copy of the value
+    pub fn get(&self) -> T {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before & 1 != 0 {
+                std::hint::spin_loop();
+                continue;
+            }
+
+            let value = unsafe { std::ptr::read_volatile(self.value.get()) };
+            std::sync::atomic::fence(Ordering::Acquire);
+
+            if self.sequence.load(Ordering::Relaxed) == before {
+                return value;
+            }
+        }
+    }
+
+    pub fn set(&self, value: T) {
+        // Take the write side by moving the sequence from even to odd
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence & 1 != 0 {
+                std::hint::spin_loop();
+                sequence = self.sequence.load(Ordering::Relaxed);
+                continue;
+            }
+
+            match self.sequence.compare_exchange_weak(
+                sequence,
+                sequence + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => sequence = current,
+            }
+        }
+
+        std::sync::atomic::fence(Ordering::Release);
+        unsafe { std::ptr::write_volatile(self.value.get(), value) };
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+}
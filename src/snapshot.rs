@@ -0,0 +1,77 @@
+use hashbrown::HashMap;
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use shred::ResourceId;
+
+// Stands in for R in a system's declared reads when it only wants a copy of R
+struct SnapshotOf<R>(PhantomData<fn() -> R>);
+
+// The id a system declares when it fetches Snapshot<R>
+pub fn snapshot_resource_id<R: shred::Resource>() -> ResourceId {
+    ResourceId::new::<SnapshotOf<R>>()
+}
+
+// Which resource each snapshot id copies from. Snapshot::setup registers itself here, since the
+// dispatcher can't work out the source from the id
+#[derive(Default)]
+pub(super) struct SnapshotSources {
+    sources: HashMap<ResourceId, ResourceId>,
+}
+
+impl SnapshotSources {
+    pub(super) fn source(&self, snapshot_id: &ResourceId) -> Option<&ResourceId> {
+        self.sources.get(snapshot_id)
+    }
+}
+
+// SystemData for a private copy of a resource. The resource is read locked just long enough to
+// clone it when the system's data is fetched, and then released before the system runs, so a
+// system that needs a consistent view of a large resource doesn't hold up its writers for the
+// whole run. Only systems queued with Dispatcher::create_future (or create_future_with_result)
+// release it early, anything else holds it for the run like a Read.
+pub struct Snapshot<R: Clone + shred::Resource> {
+    resource: R,
+}
+
+impl<R: Clone + shred::Resource> Snapshot<R> {
+    pub fn into_inner(self) -> R {
+        self.resource
+    }
+}
+
+impl<R: Clone + shred::Resource> Deref for Snapshot<R> {
+    type Target = R;
+
+    fn deref(&self) -> &R {
+        &self.resource
+    }
+}
+
+impl<'a, R: Clone + shred::Resource> shred::SystemData<'a> for Snapshot<R> {
+    fn setup(world: &mut shred::World) {
+        world
+            .entry::<SnapshotSources>()
+            .or_insert_with(SnapshotSources::default)
+            .sources
+            .insert(snapshot_resource_id::<R>(), ResourceId::new::<R>());
+    }
+
+    fn fetch(world: &'a shred::World) -> Self {
+        Snapshot {
+            resource: world
+                .try_fetch::<R>()
+                .expect("The resource for a Snapshot does not exist.")
+                .deref()
+                .clone(),
+        }
+    }
+
+    fn reads() -> Vec<ResourceId> {
+        vec![snapshot_resource_id::<R>()]
+    }
+
+    fn writes() -> Vec<ResourceId> {
+        vec![]
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use super::AcquireResources;
+use super::AsyncResourceLock;
+use super::Dispatcher;
+use super::RequiredResources;
+
+type ChildFuture = dyn futures::future::Future<Item = (), Error = ()> + Send;
+
+// Given to a StreamingSystem to send results to whoever is consuming the stream
+pub struct StreamSender<O> {
+    sender: Option<futures::sync::mpsc::Sender<O>>,
+}
+
+impl<O> StreamSender<O> {
+    // Sends a result, blocking while the stream's buffer is full. Returns false if the stream was
+    // dropped, in which case nobody will see any more results and the system may as well stop
+    pub fn send(&mut self, output: O) -> bool {
+        use futures::Future;
+        use futures::Sink;
+
+        let sender = match self.sender.take() {
+            Some(sender) => sender,
+            None => return false,
+        };
+
+        match sender.send(output).wait() {
+            Ok(sender) => {
+                self.sender = Some(sender);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+// A system that produces results while it runs rather than only when it's done. Use with
+// Dispatcher::create_stream. System::run isn't called for these.
+pub trait StreamingSystem: for<'b> shred::System<'b> + Send + 'static {
+    type Output: Send + 'static;
+
+    fn run_streaming(
+        &mut self,
+        data: <Self as shred::System<'_>>::SystemData,
+        sender: StreamSender<Self::Output>,
+    );
+}
+
+enum SystemStreamState<O> {
+    NotStarted(Box<ChildFuture>, futures::sync::mpsc::Receiver<O>),
+    Started(futures::sync::mpsc::Receiver<O>),
+}
+
+// The results of a StreamingSystem. The system is spawned (through Dispatcher::spawn) the first
+// time this is polled. It holds its resources for as long as it's running, and it runs until it
+// returns, so it can block other tasks that need the same resources for a long time. Since it
+// blocks when the buffer is full, a slow consumer makes this worse (and on a single threaded
+// runtime, a system that fills the buffer will never be unblocked). If the stream is dropped, the
+// system's sends start returning false.
+pub struct SystemStream<O, L: AsyncResourceLock> {
+    dispatcher: Arc<Dispatcher<L>>,
+    state: Option<SystemStreamState<O>>,
+}
+
+impl<O: Send + 'static, L: AsyncResourceLock> SystemStream<O, L> {
+    pub(super) fn new<T>(dispatcher: Arc<Dispatcher<L>>, system: T, buffer: usize) -> Self
+    where
+        T: StreamingSystem<Output = O>,
+    {
+        use futures::Future;
+
+        let (sender, receiver) = futures::sync::mpsc::channel(buffer);
+        let required_resources = RequiredResources::from_system(&system);
+        let acquire = AcquireResources::<T, L>::new(dispatcher.clone(), required_resources);
+
+        let dispatcher_clone = dispatcher.clone();
+        let run = acquire.map_err(|_| ()).and_then(move |guards| {
+            run_streaming_system(
+                &dispatcher_clone,
+                system,
+                StreamSender {
+                    sender: Some(sender),
+                },
+            );
+
+            drop(guards);
+            Ok(())
+        });
+
+        SystemStream {
+            dispatcher,
+            state: Some(SystemStreamState::NotStarted(Box::new(run), receiver)),
+        }
+    }
+}
+
+fn run_streaming_system<T, L>(
+    dispatcher: &Dispatcher<L>,
+    mut system: T,
+    sender: StreamSender<T::Output>,
+) where
+    T: StreamingSystem,
+    L: AsyncResourceLock,
+{
+    use shred::DynamicSystemData;
+
+    let start = std::time::Instant::now();
+    let world = dispatcher.world();
+    let data = <T as shred::System>::SystemData::fetch(&system.accessor(), &world);
+    system.run_streaming(data, sender);
+    drop(world);
+
+    dispatcher.record_system_timing(std::any::type_name::<T>(), start.elapsed());
+}
+
+impl<O: Send + 'static, L: AsyncResourceLock> futures::stream::Stream for SystemStream<O, L> {
+    type Item = O;
+    type Error = ();
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Self::Error> {
+        self.state = match self.state.take() {
+            Some(SystemStreamState::NotStarted(run, receiver)) => {
+                // The system has to run on its own task so that results can be consumed while it's
+                // still running
+                self.dispatcher.spawn(run);
+                Some(SystemStreamState::Started(receiver))
+            }
+            state => state,
+        };
+
+        match &mut self.state {
+            Some(SystemStreamState::Started(receiver)) => receiver.poll(),
+            _ => unreachable!(),
+        }
+    }
+}
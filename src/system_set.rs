@@ -0,0 +1,44 @@
+use shred::ResourceId;
+
+// The systems given to DispatcherBuilder::setup_systems. Implemented for tuples of up to 8 mutable
+// references to systems, i.e. (&mut physics, &mut render)
+pub trait SystemSet {
+    // Runs each system's setup and adds every resource the systems declare to resources
+    fn setup(self, world: &mut shred::World, resources: &mut Vec<ResourceId>);
+}
+
+fn setup_system<T>(system: &mut T, world: &mut shred::World, resources: &mut Vec<ResourceId>)
+where
+    T: for<'b> shred::System<'b>,
+{
+    use shred::Accessor;
+
+    shred::RunNow::setup(system, world);
+    let accessor = system.accessor();
+    resources.extend(accessor.reads());
+    resources.extend(accessor.writes());
+}
+
+macro_rules! impl_system_set {
+    ($($system:ident),*) => {
+        impl<'s, $($system),*> SystemSet for ($(&'s mut $system,)*)
+        where
+            $($system: for<'b> shred::System<'b>),*
+        {
+            #[allow(non_snake_case)]
+            fn setup(self, world: &mut shred::World, resources: &mut Vec<ResourceId>) {
+                let ($($system,)*) = self;
+                $(setup_system($system, world, resources);)*
+            }
+        }
+    };
+}
+
+impl_system_set!(A);
+impl_system_set!(A, B);
+impl_system_set!(A, B, C);
+impl_system_set!(A, B, C, D);
+impl_system_set!(A, B, C, D, E);
+impl_system_set!(A, B, C, D, E, F);
+impl_system_set!(A, B, C, D, E, F, G);
+impl_system_set!(A, B, C, D, E, F, G, H);
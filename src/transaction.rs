@@ -0,0 +1,101 @@
+use hashbrown::HashMap;
+use std::any::Any;
+use std::sync::Arc;
+
+use shred::ResourceId;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use crate::acquire_resources::AcquiredResourcesLockGuards;
+
+// Puts a staged copy back into the world
+type WriteBackFn = fn(&shred::World, Box<dyn Any + Send>);
+
+fn write_back<R: shred::Resource>(world: &shred::World, value: Box<dyn Any + Send>) {
+    let value = value.downcast::<R>().unwrap();
+    *world.fetch_mut::<R>() = *value;
+}
+
+// Changes to several resources that are applied all at once or not at all. Returned by
+// Dispatcher::transaction once its resources have been acquired, and holds them until it's
+// committed or dropped.
+//
+// get and get_mut hand out a copy of the resource, cloned the first time it's asked for. Changes
+// are made to the copies, and commit writes the copies of the written resources back into the
+// world. Dropping the transaction without committing discards them, so a system that fails
+// halfway through leaves the world as it was. Nothing else can use the resources while the
+// transaction holds them, so no system ever sees some of the changes without the others.
+pub struct Transaction<L: AsyncResourceLock = DefaultResourceLock> {
+    dispatcher: Arc<Dispatcher<L>>,
+    writes: Vec<ResourceId>,
+    staged: HashMap<ResourceId, (Box<dyn Any + Send>, WriteBackFn)>,
+    _guards: AcquiredResourcesLockGuards<(), L>,
+}
+
+impl<L: AsyncResourceLock> Transaction<L> {
+    pub(super) fn new(
+        dispatcher: Arc<Dispatcher<L>>,
+        writes: &[ResourceId],
+        guards: AcquiredResourcesLockGuards<(), L>,
+    ) -> Self {
+        Transaction {
+            dispatcher,
+            writes: writes.to_vec(),
+            staged: HashMap::new(),
+            _guards: guards,
+        }
+    }
+
+    fn stage<R>(&mut self) -> &mut (Box<dyn Any + Send>, WriteBackFn)
+    where
+        R: shred::Resource + Clone,
+    {
+        let dispatcher = &self.dispatcher;
+        self.staged
+            .entry(ResourceId::new::<R>())
+            .or_insert_with(|| {
+                let world = dispatcher.world();
+                let resource = world
+                    .try_fetch::<R>()
+                    .expect("A resource used in a Transaction does not exist.");
+                (
+                    Box::new(R::clone(&resource)) as Box<dyn Any + Send>,
+                    write_back::<R> as WriteBackFn,
+                )
+            })
+    }
+
+    // The transaction's copy of R, which must be one of its reads or writes
+    pub fn get<R>(&mut self) -> &R
+    where
+        R: shred::Resource + Clone,
+    {
+        self.stage::<R>().0.downcast_ref::<R>().unwrap()
+    }
+
+    // The transaction's copy of R, which must be one of its writes. Changes are only made to the
+    // world by commit
+    pub fn get_mut<R>(&mut self) -> &mut R
+    where
+        R: shred::Resource + Clone,
+    {
+        assert!(
+            self.writes.contains(&ResourceId::new::<R>()),
+            "Transaction::get_mut was called for a resource that the transaction doesn't write"
+        );
+
+        self.stage::<R>().0.downcast_mut::<R>().unwrap()
+    }
+
+    // Writes every copy that was taken with get_mut back into the world, and then releases the
+    // resources
+    pub fn commit(mut self) {
+        let world = self.dispatcher.world();
+        for (resource_id, (value, write_back)) in self.staged.drain() {
+            if self.writes.contains(&resource_id) {
+                write_back(&world, value);
+            }
+        }
+    }
+}
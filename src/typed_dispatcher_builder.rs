@@ -0,0 +1,137 @@
+use shred::ResourceId;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+use super::DispatcherBuilder;
+use super::DuplicateResource;
+use super::ResourceLockPolicy;
+
+// A resource that a registered system needs but that was never inserted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingResource {
+    pub system_name: &'static str,
+    pub resource_id: ResourceId,
+}
+
+struct RegisteredSystem {
+    name: &'static str,
+    resources: Vec<ResourceId>,
+}
+
+// Wraps a DispatcherBuilder and also takes the systems that will run on the dispatcher. build()
+// checks that every resource those systems use was inserted, so that a missing resource is
+// reported up front rather than as a panic when a system first tries to acquire it.
+pub struct TypedDispatcherBuilder<L: AsyncResourceLock = DefaultResourceLock> {
+    builder: DispatcherBuilder<L>,
+    systems: Vec<RegisteredSystem>,
+}
+
+impl Default for TypedDispatcherBuilder {
+    fn default() -> Self {
+        TypedDispatcherBuilder::new()
+    }
+}
+
+impl TypedDispatcherBuilder {
+    pub fn new() -> Self {
+        TypedDispatcherBuilder::from_builder(DispatcherBuilder::new())
+    }
+}
+
+impl<L: AsyncResourceLock> TypedDispatcherBuilder<L> {
+    // Use this to start from a builder that has already been configured (i.e. with a custom lock
+    // type or a frame history)
+    pub fn from_builder(builder: DispatcherBuilder<L>) -> Self {
+        TypedDispatcherBuilder {
+            builder,
+            systems: vec![],
+        }
+    }
+
+    pub fn insert<R>(mut self, r: R) -> Self
+    where
+        R: shred::Resource,
+    {
+        self.builder = self.builder.insert(r);
+        self
+    }
+
+    pub fn try_insert<R>(mut self, r: R) -> Result<Self, DuplicateResource>
+    where
+        R: shred::Resource,
+    {
+        self.builder = self.builder.try_insert(r)?;
+        Ok(self)
+    }
+
+    pub fn insert_with_policy<R>(mut self, r: R, policy: ResourceLockPolicy) -> Self
+    where
+        R: shred::Resource,
+    {
+        self.builder = self.builder.insert_with_policy(r, policy);
+        self
+    }
+
+    pub fn insert_with_timeout<R>(mut self, r: R, timeout: std::time::Duration) -> Self
+    where
+        R: shred::Resource,
+    {
+        self.builder = self.builder.insert_with_timeout(r, timeout);
+        self
+    }
+
+    pub fn insert_seqlock<R>(mut self, r: R) -> Self
+    where
+        R: Copy + Send + 'static,
+    {
+        self.builder = self.builder.insert_seqlock(r);
+        self
+    }
+
+    // Declare that the given system will run on the dispatcher. The system's setup is run, so
+    // resources it can default (shred's Read<T>) are inserted if they are missing
+    pub fn register_system<T>(mut self, system: &T) -> Self
+    where
+        T: for<'b> shred::System<'b> + Send + 'static,
+    {
+        use shred::Accessor;
+        use shred::DynamicSystemData;
+
+        let accessor = system.accessor();
+        <T as shred::System>::SystemData::setup(&accessor, self.builder.world_mut());
+
+        self.systems.push(RegisteredSystem {
+            name: std::any::type_name::<T>(),
+            resources: accessor
+                .reads()
+                .into_iter()
+                .chain(accessor.writes())
+                .collect(),
+        });
+
+        self
+    }
+
+    // Create the dispatcher, or return every resource that a registered system needs but that
+    // doesn't exist
+    pub fn build(mut self) -> Result<Dispatcher<L>, Vec<MissingResource>> {
+        let mut missing_resources = vec![];
+        for system in &self.systems {
+            for resource_id in &system.resources {
+                if !self.builder.register_existing_resource(resource_id) {
+                    missing_resources.push(MissingResource {
+                        system_name: system.name,
+                        resource_id: resource_id.clone(),
+                    });
+                }
+            }
+        }
+
+        if missing_resources.is_empty() {
+            Ok(self.builder.build())
+        } else {
+            Err(missing_resources)
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+use std::sync::Weak;
+
+use super::AsyncResourceLock;
+use super::DefaultResourceLock;
+use super::Dispatcher;
+
+// A reference to the dispatcher that doesn't keep it alive, from Dispatcher::weak_handle. Systems
+// that are kept around between frames should hold this rather than an Arc<Dispatcher>, since the
+// game loop can't take the world back while anything else holds an Arc.
+pub struct WeakDispatcher<L: AsyncResourceLock = DefaultResourceLock> {
+    dispatcher: Weak<Dispatcher<L>>,
+}
+
+// Not derived since that would require L: Clone
+impl<L: AsyncResourceLock> Clone for WeakDispatcher<L> {
+    fn clone(&self) -> Self {
+        WeakDispatcher {
+            dispatcher: self.dispatcher.clone(),
+        }
+    }
+}
+
+impl<L: AsyncResourceLock> WeakDispatcher<L> {
+    pub(super) fn new(dispatcher: &Arc<Dispatcher<L>>) -> Self {
+        WeakDispatcher {
+            dispatcher: Arc::downgrade(dispatcher),
+        }
+    }
+
+    // Returns the dispatcher, or None if the game loop has ended and it was dropped. Don't hold
+    // on to the result any longer than needed
+    pub fn upgrade(&self) -> Option<Arc<Dispatcher<L>>> {
+        self.dispatcher.upgrade()
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::RwLock;
+use std::sync::Weak;
+
+use shred::ResourceId;
+
+use crate::lazy_resource::LazyLocker;
+
+// Read-only access to the dispatcher's world from outside of any system, from
+// Dispatcher::world_view. This is only meant for best-effort debug reads (i.e. a debug overlay)
+// that can live with seeing resources partway through a frame. Anything else should use
+// Dispatcher::read_resource, or declare the resource in a system.
+//
+// A read only happens if the resource's lock is free, and the lock is held while f runs, so a
+// system never fetches the resource at the same time (shred would panic). Nothing is queued on the
+// lock, a read just gives up if it's held. Resources can still be seen halfway through being
+// updated by a sequence of systems, and two resources read one after the other may come from
+// different frames. Systems that need the resource wait while f runs, so copy out what's needed
+// and return from f right away.
+//
+// Like WeakDispatcher, this doesn't keep the world alive, so the game loop can still take it back
+// when it ends.
+#[derive(Clone)]
+pub struct WorldView {
+    world: Weak<RwLock<shred::World>>,
+}
+
+impl WorldView {
+    pub(super) fn new(world: Weak<RwLock<shred::World>>) -> Self {
+        WorldView { world }
+    }
+
+    // Runs f with the resource, or returns None if something holds the resource's lock right now,
+    // the world is being changed (i.e. during maintenance), the resource doesn't exist or has no
+    // lock, or the dispatcher is gone. Never waits
+    pub fn try_read<R, F, RetT>(&self, f: F) -> Option<RetT>
+    where
+        R: shred::Resource,
+        F: FnOnce(&R) -> RetT,
+    {
+        let resource_id = ResourceId::new::<R>();
+        let world = self.world.upgrade()?;
+        let world = world.try_read().ok()?;
+
+        // Declared before the borrow so that the borrow is given back first
+        let _guards = world
+            .try_fetch::<LazyLocker>()?
+            .try_lock(std::slice::from_ref(&resource_id))
+            .ok()??;
+        let resource = world.try_fetch_internal(resource_id)?.try_borrow().ok()?;
+
+        Some(f(resource.downcast_ref::<R>()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Dispatcher;
+    use crate::DispatcherBuilder;
+    use shred::ResourceId;
+    use std::sync::Arc;
+
+    struct Counter(u32);
+
+    struct IncrementSystem;
+
+    impl<'a> shred::System<'a> for IncrementSystem {
+        type SystemData = shred::WriteExpect<'a, Counter>;
+
+        fn run(&mut self, mut counter: Self::SystemData) {
+            counter.0 += 1;
+        }
+    }
+
+    #[test]
+    fn read_gives_up_while_resource_is_held() {
+        let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter(0)).build());
+        let world_view = dispatcher.world_view();
+
+        let scope = Dispatcher::acquire_blocking(&dispatcher, &[], &[ResourceId::new::<Counter>()]);
+        assert_eq!(world_view.try_read(|counter: &Counter| counter.0), None);
+        drop(scope);
+
+        assert_eq!(world_view.try_read(|counter: &Counter| counter.0), Some(0));
+    }
+
+    #[test]
+    fn system_waits_for_read() {
+        let dispatcher = Arc::new(DispatcherBuilder::new().insert(Counter(0)).build());
+        let world_view = dispatcher.world_view();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let system_dispatcher = dispatcher.clone();
+        let read = world_view.try_read(move |counter: &Counter| {
+            // The system would panic fetching Counter if it didn't wait for the read to finish
+            std::thread::spawn(move || {
+                Dispatcher::run_system(&system_dispatcher, IncrementSystem);
+                tx.send(()).unwrap();
+            });
+            assert!(rx
+                .recv_timeout(std::time::Duration::from_millis(100))
+                .is_err());
+            (counter.0, rx)
+        });
+
+        let (count, rx) = read.unwrap();
+        assert_eq!(count, 0);
+        rx.recv_timeout(std::time::Duration::from_secs(10))
+            .expect("The system never ran");
+        assert_eq!(world_view.try_read(|counter: &Counter| counter.0), Some(1));
+    }
+}
@@ -0,0 +1,78 @@
+use hashbrown::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use shred::ResourceId;
+
+// How many times each resource's write lock has been released. Only kept when the dispatcher was
+// built with DispatcherBuilder::with_write_versions. A resource that has never been written has no
+// entry, which reads as version 0
+pub(super) struct WriteVersions {
+    versions: Mutex<HashMap<ResourceId, u64>>,
+}
+
+impl WriteVersions {
+    pub(super) fn new() -> Self {
+        WriteVersions {
+            versions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Called while the write locks are still held, so a task that takes one of these locks next
+    // always sees the bumped version
+    pub(super) fn released(&self, resources: &[ResourceId]) {
+        let mut versions = self.versions.lock().unwrap();
+        for resource_id in resources {
+            *versions.entry(resource_id.clone()).or_insert(0) += 1;
+        }
+    }
+
+    pub(super) fn versions(&self, resources: &[ResourceId]) -> Vec<u64> {
+        let versions = self.versions.lock().unwrap();
+        resources
+            .iter()
+            .map(|resource_id| versions.get(resource_id).cloned().unwrap_or(0))
+            .collect()
+    }
+}
+
+// Remembers the write versions of some resources so that a task that let go of them (i.e. to yield
+// to other systems partway through a long job) can tell whether anyone wrote them before it got
+// them back. Created by Dispatcher::reacquire_guard right after the guards are dropped, and checked
+// with changed once the resources have been acquired again.
+//
+// Only writes are counted, and only when the dispatcher was built with
+// DispatcherBuilder::with_write_versions. Without it there's nothing to compare against, so
+// changed always says the resources may have changed and the task should re-validate.
+pub struct ReacquireGuard {
+    resources: Vec<ResourceId>,
+    released_at: Option<(Vec<u64>, Arc<WriteVersions>)>,
+}
+
+impl ReacquireGuard {
+    pub(super) fn new(
+        resources: &[ResourceId],
+        write_versions: Option<&Arc<WriteVersions>>,
+    ) -> Self {
+        ReacquireGuard {
+            resources: resources.to_vec(),
+            released_at: write_versions
+                .map(|write_versions| (write_versions.versions(resources), write_versions.clone())),
+        }
+    }
+
+    // True if any of the resources has been written since the guard was created. Only meaningful
+    // while the resources are held again, otherwise another writer could still get in afterwards
+    pub fn changed(&self) -> bool {
+        match &self.released_at {
+            Some((versions, write_versions)) => {
+                write_versions.versions(&self.resources) != *versions
+            }
+            None => true,
+        }
+    }
+
+    pub fn resources(&self) -> &[ResourceId] {
+        &self.resources
+    }
+}